@@ -1,11 +1,44 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use clap::{Args, Subcommand, ValueEnum};
+use futures_util::stream::{self, StreamExt};
 
-use std::path::PathBuf;
-use clap::{Args, ValueEnum};
+use crate::commands::agent_plan::{self, PlanSettings};
+use crate::commands::ask::{
+    ProviderArg, parse_provider_value, resolve_main_prompt, resolve_max_tokens, resolve_model,
+    resolve_profile, resolve_provider, resolve_retries, resolve_retry_delay, resolve_system,
+    resolve_temperature, resolve_timeout,
+};
+use crate::commands::chroma::{self, ChromaConnectArgs, LocalChromaGuard};
+use crate::commands::embed::{self, resolve_embedding_model, resolve_embedding_provider};
+use crate::commands::prompting::{build_messages, non_empty};
+use crate::config::{self, ProfileConfig};
+use crate::rchain::knowledge::KnowledgeBase;
+use crate::rchain::memory::{self, MemoryStore};
+use crate::rchain::provider::{
+    self, AskOptions, ChatMessage, ContentPart, MessageContent, Provider, Usage,
+};
+use crate::rchain::sandbox::SandboxLimits;
+use crate::rchain::text_tools::{self, TextTurn, ToolProtocol};
+use crate::rchain::tool_policy::{ApprovalMode, ToolPolicy};
+use crate::rchain::toolbox::Toolbox;
+use crate::rchain::tools::ToolCall;
+use crate::rchain::transcript::{
+    self, RecordedTools, ResumeState, TranscriptEvent, TranscriptWriter,
+};
 
+const DEFAULT_MAX_STEPS: usize = 10;
+const DEFAULT_STEP_MAX_STEPS: usize = 5;
+const DEFAULT_MAX_REPLANS: usize = 2;
+pub(crate) const DEFAULT_PARALLEL_TOOLS: usize = 4;
 
 #[derive(Debug, Args, Clone)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct AgentArgs {
+    #[command(subcommand)]
+    command: Option<AgentSubcommand>,
+
     #[arg(long)]
     pub profile: Option<String>,
 
@@ -15,22 +48,1160 @@ pub struct AgentArgs {
     #[arg(long)]
     model: Option<String>,
 
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    #[arg(long = "max-tokens")]
+    max_tokens: Option<u32>,
+
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    #[arg(long)]
+    retries: Option<u32>,
+
+    #[arg(long = "retry-delay")]
+    retry_delay: Option<u64>,
+
+    #[arg(long)]
+    system: Option<String>,
+
+    #[arg(long = "system-file")]
+    system_file: Option<PathBuf>,
+
+    /// Maximum number of model turns before giving up
+    #[arg(long = "max-steps", default_value_t = DEFAULT_MAX_STEPS)]
+    max_steps: usize,
+
+    /// Answer in one tool loop, or plan first and run each step as its own loop
+    #[arg(long, value_enum, default_value_t = ModeArg::Loop)]
+    mode: ModeArg,
+
+    /// Maximum number of model turns per plan step (--mode plan)
+    #[arg(long = "step-max-steps", default_value_t = DEFAULT_STEP_MAX_STEPS)]
+    step_max_steps: usize,
+
+    /// How many times a failed step may trigger a new plan (--mode plan)
+    #[arg(long = "max-replans", default_value_t = DEFAULT_MAX_REPLANS)]
+    max_replans: usize,
+
+    /// Open each plan in $VISUAL or $EDITOR before running it (--mode plan)
+    #[arg(long = "edit-plan")]
+    edit_plan: bool,
+
+    /// Maximum number of tool calls from one model turn run concurrently
+    #[arg(long = "parallel-tools", default_value_t = DEFAULT_PARALLEL_TOOLS)]
+    parallel_tools: usize,
+
+    /// Restrict the agent to the named tools (repeatable)
+    #[arg(long = "tool", value_name = "NAME")]
+    tools: Vec<String>,
+
+    /// How tools are offered to the model
+    #[arg(long = "tool-protocol", value_enum, default_value_t = ToolProtocolArg::Native)]
+    tool_protocol: ToolProtocolArg,
+
+    #[command(flatten)]
+    policy: ToolPolicyArgs,
+
+    /// Offer run_python and run_shell, which run model-written code in a temporary directory.
+    /// The scripts bypass the workspace and the command lists; refused unless sh and python3
+    /// pass those lists
+    #[arg(long = "code-tools")]
+    code_tools: bool,
+
+    /// Wall-clock limit in seconds for each run_python/run_shell call
+    #[arg(long = "code-timeout", value_name = "SECS", requires = "code_tools")]
+    code_timeout: Option<u64>,
+
+    /// CPU time limit in seconds for each run_python/run_shell call
+    #[arg(long = "code-cpu", value_name = "SECS", requires = "code_tools")]
+    code_cpu: Option<u64>,
+
+    /// Memory limit in MiB for each run_python/run_shell call
+    #[arg(long = "code-memory", value_name = "MIB", requires = "code_tools")]
+    code_memory: Option<u64>,
+
+    /// Let the agent search this ChromaDB collection with `search_knowledge` (repeatable)
+    #[arg(long = "knowledge", value_name = "COLLECTION")]
+    knowledge: Vec<String>,
+
+    /// Remember durable facts across sessions in this ChromaDB collection
+    #[arg(long, value_name = "COLLECTION")]
+    memory: Option<String>,
+
+    /// Embedding provider of the --knowledge and --memory collections
+    /// [default: the one each collection records, else fireworks]
+    #[arg(long = "embedding-provider", value_enum)]
+    embedding_provider: Option<embed::ProviderArg>,
+
+    /// Embedding model of the --knowledge and --memory collections
+    /// [default: the one each collection records]
+    #[arg(long = "embedding-model")]
+    embedding_model: Option<String>,
+
+    #[command(flatten)]
+    chroma: ChromaConnectArgs,
+
+    #[arg(long)]
+    quiet: bool,
+
     /// Main prompt
     #[arg(short = 'p', long = "prompt")]
     prompt: Option<String>,
 
     #[arg(long = "prompt-file")]
     prompt_file: Option<PathBuf>,
+
+    /// Transcript file to write (default: a new file in MP_TRANSCRIPT_DIR or ~/.local/state/mpipe/transcripts)
+    #[arg(long, value_name = "PATH")]
+    transcript: Option<PathBuf>,
+
+    /// Do not record a transcript
+    #[arg(long = "no-transcript", conflicts_with = "transcript")]
+    no_transcript: bool,
+
+    /// Continue an unfinished run from its transcript, with the recorded provider, model and tool protocol
+    #[arg(long, value_name = "TRANSCRIPT", conflicts_with_all = ["transcript", "no_transcript"])]
+    resume: Option<PathBuf>,
+
+    /// Answer tool calls with the outputs recorded in a transcript instead of running the tools
+    #[arg(long = "replay-tools", value_name = "TRANSCRIPT")]
+    replay_tools: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ModeArg {
+    /// Single tool loop until the model answers
+    Loop,
+    /// Numbered plan first, then one tool loop per step
+    Plan,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum AgentSubcommand {
+    #[command(about = "Print a recorded agent transcript step by step")]
+    Replay { transcript: PathBuf },
+}
+
+/// Tool execution safeguards; each flag overrides `[tool_policy]`.
+#[derive(Debug, Args, Clone, Default)]
+pub(crate) struct ToolPolicyArgs {
+    /// Confirm tool calls on the TTY: every call, none, or only tools that write or run commands
+    #[arg(long, value_enum)]
+    approve: Option<ApproveArg>,
+
+    /// Directory file tools are confined to (default: current directory)
+    #[arg(long)]
+    workspace: Option<PathBuf>,
+
+    /// Program command tools may run (repeatable, added to the configured list)
+    #[arg(long = "allow-command", value_name = "PROGRAM")]
+    allow_commands: Vec<String>,
+
+    /// Program command tools may never run (repeatable, added to the configured list)
+    #[arg(long = "deny-command", value_name = "PROGRAM")]
+    deny_commands: Vec<String>,
+
+    /// Wall-clock limit in seconds for each tool call
+    #[arg(long = "tool-timeout")]
+    tool_timeout: Option<u64>,
+
+    /// Maximum bytes of tool output handed back to the model
+    #[arg(long = "max-tool-output")]
+    max_tool_output: Option<usize>,
+
+    /// Show tool calls to the model without executing them
+    #[arg(long = "dry-run-tools")]
+    dry_run_tools: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ApproveArg {
+    Always,
+    Never,
+    Dangerous,
+}
+
+impl From<ApproveArg> for ApprovalMode {
+    fn from(arg: ApproveArg) -> Self {
+        match arg {
+            ApproveArg::Always => ApprovalMode::Always,
+            ApproveArg::Never => ApprovalMode::Never,
+            ApproveArg::Dangerous => ApprovalMode::Dangerous,
+        }
+    }
 }
 
+impl ToolPolicyArgs {
+    /// Merges the flags over the `[tool_policy]` config section.
+    pub(crate) fn resolve(self) -> Result<ToolPolicy, String> {
+        if self.tool_timeout == Some(0) {
+            return Err("--tool-timeout must be > 0".to_string());
+        }
+        if self.max_tool_output == Some(0) {
+            return Err("--max-tool-output must be > 0".to_string());
+        }
+
+        let mut policy = ToolPolicy::from_config(&config::load_tool_policy()?)?;
+        if let Some(approve) = self.approve {
+            policy.approve = approve.into();
+        }
+        if let Some(workspace) = &self.workspace {
+            policy.set_workspace(workspace)?;
+        }
+        policy.allow_commands.extend(self.allow_commands);
+        policy.deny_commands.extend(self.deny_commands);
+        if let Some(timeout) = self.tool_timeout {
+            policy.timeout = Some(Duration::from_secs(timeout));
+        }
+        if let Some(max_bytes) = self.max_tool_output {
+            policy.max_output_bytes = Some(max_bytes);
+        }
+        policy.dry_run = self.dry_run_tools;
+        Ok(policy)
+    }
+}
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
-enum ProviderArg {
-    Openai,
-    Fireworks,
+pub(crate) enum ToolProtocolArg {
+    /// Provider function calling
+    Native,
+    /// Tools described in the system prompt, called with `CALL name(args)`
+    Text,
+    /// ReAct `Action:` / `Action Input:` transcript
+    React,
 }
 
+impl From<ToolProtocolArg> for ToolProtocol {
+    fn from(arg: ToolProtocolArg) -> Self {
+        match arg {
+            ToolProtocolArg::Native => ToolProtocol::Native,
+            ToolProtocolArg::Text => ToolProtocol::Text,
+            ToolProtocolArg::React => ToolProtocol::React,
+        }
+    }
+}
+
+/// Model/tool round trips driven until the model answers without tool calls.
+#[derive(Clone, Copy)]
+pub(crate) struct ToolLoop<'a> {
+    pub provider: Provider,
+    pub model: &'a str,
+    pub toolbox: &'a Toolbox,
+    pub protocol: ToolProtocol,
+    pub options: AskOptions,
+    pub max_steps: usize,
+    /// Tool calls of one turn executed concurrently (at least 1).
+    pub parallelism: usize,
+    pub quiet: bool,
+    /// Records every request, response and tool call when set.
+    pub transcript: Option<&'a TranscriptWriter>,
+    /// Answers tool calls from a previous transcript instead of running them.
+    pub recorded_tools: Option<&'a RecordedTools>,
+    /// Number of the first step, greater than 1 when resuming.
+    pub first_step: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct ToolLoopOutcome {
+    pub answer: String,
+    pub usage: Option<Usage>,
+}
 
 pub async fn run(cli: AgentArgs) -> Result<(), String> {
+    if let Some(AgentSubcommand::Replay { transcript }) = &cli.command {
+        return replay(transcript);
+    }
+    if cli.max_steps == 0 {
+        return Err("--max-steps must be > 0".to_string());
+    }
+    if cli.parallel_tools == 0 {
+        return Err("--parallel-tools must be > 0".to_string());
+    }
+    if cli.step_max_steps == 0 {
+        return Err("--step-max-steps must be > 0".to_string());
+    }
+    if cli.mode == ModeArg::Plan && cli.resume.is_some() {
+        return Err("--resume is not supported with --mode plan.".to_string());
+    }
+
+    let resumed = match &cli.resume {
+        Some(path) => Some(
+            transcript::resume_state(&transcript::read(path)?)
+                .map_err(|err| format!("Cannot resume '{}': {err}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let profile = resolve_profile(cli.profile.as_deref())?;
+    let provider = match (&resumed, cli.provider) {
+        (Some(state), None) => parse_provider_value(&state.provider, "transcript provider")?,
+        (_, provider) => resolve_provider(provider, &profile)?,
+    };
+    let model = match (&resumed, cli.model.clone()) {
+        (Some(state), None) => state.model.clone(),
+        (_, model) => resolve_model(model, &profile)?,
+    };
+    let protocol = match &resumed {
+        Some(state) => ToolProtocol::from_str(&state.protocol)
+            .ok_or_else(|| format!("Unknown tool protocol '{}' in transcript.", state.protocol))?,
+        None => cli.tool_protocol.into(),
+    };
+    let options = AskOptions {
+        temperature: resolve_temperature(cli.temperature, &profile)?,
+        max_tokens: resolve_max_tokens(cli.max_tokens, &profile)?,
+        timeout_secs: resolve_timeout(cli.timeout, &profile)?,
+        retries: resolve_retries(cli.retries, &profile)?,
+        retry_delay_ms: resolve_retry_delay(cli.retry_delay, &profile)?,
+    };
+
+    let system = match &resumed {
+        Some(_) => None,
+        None => resolve_system(cli.system.clone(), cli.system_file.as_deref(), &profile)?,
+    };
+
+    let recorded_tools = match &cli.replay_tools {
+        Some(path) => Some(RecordedTools::from_events(&transcript::read(path)?)),
+        None => None,
+    };
+
+    let sandbox = sandbox_limits(&cli)?;
+    let mut toolbox = load_toolbox(cli.policy.clone().resolve()?).await?;
+    if let Some(limits) = sandbox
+        && let Err(err) = toolbox.register_sandbox(limits)
+    {
+        toolbox.shutdown().await;
+        return Err(err);
+    }
+    let (memory, _local_chroma) = match open_collections(&cli, &profile, &mut toolbox).await {
+        Ok(opened) => opened,
+        Err(err) => {
+            toolbox.shutdown().await;
+            return Err(err);
+        }
+    };
+    if !cli.tools.is_empty()
+        && let Err(err) = toolbox.retain_named(&cli.tools)
+    {
+        toolbox.shutdown().await;
+        return Err(err);
+    }
+
+    let result = run_agent(
+        AgentRun {
+            cli: &cli,
+            resumed,
+            provider,
+            model: &model,
+            protocol,
+            options,
+            system,
+            recorded_tools: recorded_tools.as_ref(),
+            memory: memory.as_ref(),
+        },
+        &toolbox,
+    )
+    .await;
+    toolbox.shutdown().await;
+    let outcome = result?;
+
+    println!("{}", outcome.answer.trim_end());
     Ok(())
 }
+
+struct AgentRun<'a> {
+    cli: &'a AgentArgs,
+    resumed: Option<ResumeState>,
+    provider: Provider,
+    model: &'a str,
+    protocol: ToolProtocol,
+    options: AskOptions,
+    system: Option<String>,
+    recorded_tools: Option<&'a RecordedTools>,
+    memory: Option<&'a MemoryStore>,
+}
+
+/// Builds the conversation (fresh or resumed), opens the transcript and
+/// drives the loop.
+async fn run_agent(mut run: AgentRun<'_>, toolbox: &Toolbox) -> Result<ToolLoopOutcome, String> {
+    let cli = run.cli;
+    let resumed = run.resumed.take();
+    let mut tool_loop = ToolLoop {
+        provider: run.provider,
+        model: run.model,
+        toolbox,
+        protocol: run.protocol,
+        options: run.options,
+        max_steps: cli.max_steps,
+        parallelism: cli.parallel_tools,
+        quiet: cli.quiet,
+        transcript: None,
+        recorded_tools: run.recorded_tools,
+        first_step: 1,
+    };
+
+    let has_prompt = cli.prompt.is_some() || cli.prompt_file.is_some();
+    let (mut messages, writer) = match (resumed, &cli.resume) {
+        (Some(state), Some(path)) => {
+            let mut messages = state.messages;
+            let writer = TranscriptWriter::open(path, true)?;
+            writer.record(&TranscriptEvent::Resume {
+                messages: messages.clone(),
+                ts_ms: transcript::now_ms(),
+            })?;
+            if has_prompt {
+                let prompt = resolve_main_prompt(cli.prompt.clone(), cli.prompt_file.as_deref())?;
+                let message = ChatMessage::user_with_text(prompt.text);
+                writer.record(&TranscriptEvent::Message {
+                    message: message.clone(),
+                })?;
+                messages.push(message);
+            }
+            tool_loop.first_step = state.last_step + 1;
+            (messages, Some(writer))
+        }
+        _ if cli.mode == ModeArg::Plan => {
+            let main_prompt = resolve_main_prompt(cli.prompt.clone(), cli.prompt_file.as_deref())?;
+            let system = recall(&run, &main_prompt.text).await?;
+            let writer = open_transcript(cli, &run, toolbox)?;
+            if let Some(writer) = &writer {
+                writer.record(&TranscriptEvent::Message {
+                    message: ChatMessage::user_with_text(main_prompt.text.clone()),
+                })?;
+                if !cli.quiet {
+                    eprintln!("transcript: {}", writer.path().display());
+                }
+            }
+            tool_loop.transcript = writer.as_ref();
+            let settings = PlanSettings {
+                step_max_steps: cli.step_max_steps,
+                max_replans: cli.max_replans,
+                edit: cli.edit_plan,
+            };
+            let result = agent_plan::run(
+                &tool_loop,
+                non_empty(system.as_deref()),
+                &main_prompt.text,
+                settings,
+            )
+            .await;
+            if let (Ok(outcome), Some(memory)) = (&result, run.memory) {
+                let conversation = [
+                    ChatMessage::user_with_text(main_prompt.text),
+                    ChatMessage::assistant(outcome.answer.clone()),
+                ];
+                remember(&tool_loop, memory, &conversation, writer.as_ref()).await;
+            }
+            return result;
+        }
+        _ => {
+            let main_prompt = resolve_main_prompt(cli.prompt.clone(), cli.prompt_file.as_deref())?;
+            let system = recall(&run, &main_prompt.text).await?;
+            let mut messages = build_messages(non_empty(system.as_deref()), &main_prompt.text);
+            tool_loop.prepare(&mut messages);
+
+            let writer = open_transcript(cli, &run, toolbox)?;
+            if let Some(writer) = &writer {
+                for message in &messages {
+                    writer.record(&TranscriptEvent::Message {
+                        message: message.clone(),
+                    })?;
+                }
+            }
+            (messages, writer)
+        }
+    };
+
+    if let Some(writer) = &writer
+        && !cli.quiet
+    {
+        eprintln!("transcript: {}", writer.path().display());
+    }
+    tool_loop.transcript = writer.as_ref();
+    let result = tool_loop.run(&mut messages).await;
+    if result.is_ok()
+        && let Some(memory) = run.memory
+    {
+        remember(&tool_loop, memory, &messages, writer.as_ref()).await;
+    }
+    result
+}
+
+/// The system prompt of a fresh run, extended with the memories most
+/// relevant to the opening prompt.
+async fn recall(run: &AgentRun<'_>, prompt: &str) -> Result<Option<String>, String> {
+    let Some(memory) = run.memory else {
+        return Ok(run.system.clone());
+    };
+    let memories = memory.recall(prompt, memory::DEFAULT_RECALL).await?;
+    if !run.cli.quiet && !memories.is_empty() {
+        eprintln!(
+            "memory: recalled {} facts from '{}'",
+            memories.len(),
+            memory.name()
+        );
+    }
+    Ok(memory::with_memories(
+        non_empty(run.system.as_deref()),
+        &memories,
+    ))
+}
+
+/// Asks the model for the durable facts of the finished session and stores
+/// them. Failures only warn: the answer is already there.
+async fn remember(
+    tool_loop: &ToolLoop<'_>,
+    memory: &MemoryStore,
+    conversation: &[ChatMessage],
+    writer: Option<&TranscriptWriter>,
+) {
+    let session_id = writer
+        .and_then(|writer| writer.path().file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("session-{}-{}", transcript::now_ms(), std::process::id()));
+
+    let stored = async {
+        let response = provider::ask(
+            tool_loop.provider,
+            tool_loop.model,
+            &memory::extraction_messages(conversation),
+            tool_loop.options,
+        )
+        .await
+        .map_err(|err| err.to_string())?;
+        let facts = memory::parse_facts(&response.content)?;
+        memory
+            .remember(&facts, &session_id, transcript::now_ms())
+            .await
+    };
+    match stored.await {
+        Ok(count) if !tool_loop.quiet => {
+            eprintln!("memory: stored {count} facts in '{}'", memory.name());
+        }
+        Ok(_) => {}
+        Err(err) => eprintln!("memory: facts not stored: {err}"),
+    }
+}
+
+/// Creates the transcript of a fresh run and records its start event.
+fn open_transcript(
+    cli: &AgentArgs,
+    run: &AgentRun<'_>,
+    toolbox: &Toolbox,
+) -> Result<Option<TranscriptWriter>, String> {
+    let writer = match (&cli.transcript, cli.no_transcript) {
+        (_, true) => return Ok(None),
+        (Some(path), false) => TranscriptWriter::open(path, false)?,
+        (None, false) => TranscriptWriter::create_in(&transcript::default_dir()?)?,
+    };
+    writer.record(&TranscriptEvent::Start {
+        mpipe_version: env!("CARGO_PKG_VERSION").to_string(),
+        provider: run.provider.as_str().to_string(),
+        model: run.model.to_string(),
+        protocol: run.protocol.as_str().to_string(),
+        max_steps: cli.max_steps,
+        tools: toolbox
+            .definitions()
+            .iter()
+            .map(|tool| tool.to_json())
+            .collect(),
+        mode: (cli.mode == ModeArg::Plan).then(|| "plan".to_string()),
+        ts_ms: transcript::now_ms(),
+    })?;
+    Ok(Some(writer))
+}
+
+/// Built-in tools, the `[tools.*]` declared in the config file and the tools
+/// of every `[mcp.*]` server, which is started here.
+///
+/// Callers own the returned toolbox and must call [`Toolbox::shutdown`].
+pub(crate) async fn load_toolbox(policy: ToolPolicy) -> Result<Toolbox, String> {
+    let mut toolbox = Toolbox::with_builtins();
+    toolbox.set_policy(policy);
+    toolbox.register_config_tools(&config::load_tools()?)?;
+    if let Err(err) = toolbox
+        .register_mcp_servers(&config::load_mcp_servers()?)
+        .await
+    {
+        toolbox.shutdown().await;
+        return Err(err);
+    }
+    Ok(toolbox)
+}
+
+/// Limits of the code tools, or `None` without `--code-tools`.
+fn sandbox_limits(cli: &AgentArgs) -> Result<Option<SandboxLimits>, String> {
+    if !cli.code_tools {
+        return Ok(None);
+    }
+    for (flag, value) in [
+        ("--code-timeout", cli.code_timeout),
+        ("--code-cpu", cli.code_cpu),
+        ("--code-memory", cli.code_memory),
+    ] {
+        if value == Some(0) {
+            return Err(format!("{flag} must be > 0"));
+        }
+    }
+
+    let mut limits = SandboxLimits::default();
+    if let Some(secs) = cli.code_timeout {
+        limits.timeout = Duration::from_secs(secs);
+    }
+    if let Some(secs) = cli.code_cpu {
+        limits.cpu_secs = secs;
+    }
+    if let Some(mib) = cli.code_memory {
+        limits.memory_mb = mib;
+    }
+    Ok(Some(limits))
+}
+
+/// Opens the `--knowledge` collections, registering `search_knowledge`
+/// over them, and the `--memory` collection, created when missing.
+///
+/// The returned guard keeps a local ChromaDB server alive for the run.
+async fn open_collections(
+    cli: &AgentArgs,
+    profile: &ProfileConfig,
+    toolbox: &mut Toolbox,
+) -> Result<(Option<MemoryStore>, Option<LocalChromaGuard>), String> {
+    if cli.knowledge.is_empty() && cli.memory.is_none() {
+        return Ok((None, None));
+    }
+    let embedding_provider = resolve_embedding_provider(cli.embedding_provider, profile)?;
+    let embedding_model = resolve_embedding_model(cli.embedding_model.clone(), profile);
+
+    let (client, guard) = chroma::connect(&cli.chroma).await?;
+    if !cli.knowledge.is_empty() {
+        let mut collections = Vec::with_capacity(cli.knowledge.len());
+        for name in &cli.knowledge {
+            let collection = client
+                .get_collection(name)
+                .await
+                .map_err(|err| format!("Failed to open collection '{name}': {err}"))?;
+            collections.push(collection);
+        }
+        toolbox.register_knowledge(KnowledgeBase::new(
+            collections,
+            embedding_provider,
+            embedding_model.as_deref(),
+        )?)?;
+    }
+
+    let memory = match &cli.memory {
+        Some(name) => {
+            let collection = client
+                .get_or_create_collection(name, None)
+                .await
+                .map_err(|err| format!("Failed to open memory collection '{name}': {err}"))?;
+            Some(
+                MemoryStore::open(collection, embedding_provider, embedding_model.as_deref())
+                    .await?,
+            )
+        }
+        None => None,
+    };
+    Ok((memory, guard))
+}
+
+impl ToolLoop<'_> {
+    /// Adds the tool instructions text protocols need to the system prompt.
+    pub(crate) fn prepare(&self, messages: &mut Vec<ChatMessage>) {
+        text_tools::inject_instructions(messages, self.protocol, &self.toolbox.definitions());
+    }
+
+    /// Runs the loop on already-prepared messages, appending every turn,
+    /// and records how the run ended.
+    pub(crate) async fn run(
+        &self,
+        messages: &mut Vec<ChatMessage>,
+    ) -> Result<ToolLoopOutcome, String> {
+        let result = self.drive(messages).await;
+
+        let end = match &result {
+            Ok(outcome) => TranscriptEvent::End {
+                status: "answered".to_string(),
+                answer: Some(outcome.answer.clone()),
+                error: None,
+                usage: outcome.usage.clone(),
+                ts_ms: transcript::now_ms(),
+            },
+            Err(err) => TranscriptEvent::End {
+                status: "failed".to_string(),
+                answer: None,
+                error: Some(err.clone()),
+                usage: None,
+                ts_ms: transcript::now_ms(),
+            },
+        };
+        self.record(end)?;
+        result
+    }
+
+    /// Like [`ToolLoop::run`] without the end event, for loops that are one
+    /// part of a larger run.
+    pub(crate) async fn drive(
+        &self,
+        messages: &mut Vec<ChatMessage>,
+    ) -> Result<ToolLoopOutcome, String> {
+        match self.protocol {
+            ToolProtocol::Native => self.run_native(messages).await,
+            ToolProtocol::Text | ToolProtocol::React => self.run_text(messages).await,
+        }
+    }
+
+    async fn run_native(&self, messages: &mut Vec<ChatMessage>) -> Result<ToolLoopOutcome, String> {
+        let definitions = self.toolbox.definitions();
+        let mut usage: Option<Usage> = None;
+
+        for step in self.steps() {
+            self.record_request(step, messages)?;
+            let start = Instant::now();
+            let response = provider::ask_with_tools(
+                self.provider,
+                self.model,
+                messages,
+                &definitions,
+                self.options,
+            )
+            .await
+            .map_err(|err| err.to_string())?;
+            self.record(TranscriptEvent::Response {
+                step,
+                content: response.content.clone(),
+                tool_calls: response.tool_calls.iter().map(ToolCall::to_json).collect(),
+                usage: response.usage.clone(),
+                latency_ms: start.elapsed().as_millis() as u64,
+            })?;
+            usage = add_usage(usage, response.usage);
+
+            if response.tool_calls.is_empty() {
+                self.push(messages, ChatMessage::assistant(response.content.clone()))?;
+                return Ok(ToolLoopOutcome {
+                    answer: response.content,
+                    usage,
+                });
+            }
+
+            self.push(
+                messages,
+                ChatMessage::assistant_with_tool_calls(response.content, &response.tool_calls),
+            )?;
+            let contents = self.execute_all(&response.tool_calls, step).await;
+            for (call, content) in response.tool_calls.iter().zip(contents) {
+                self.push(messages, ChatMessage::tool(&call.id, content?))?;
+            }
+        }
+
+        Err(self.steps_exhausted())
+    }
+
+    async fn run_text(&self, messages: &mut Vec<ChatMessage>) -> Result<ToolLoopOutcome, String> {
+        let definitions = self.toolbox.definitions();
+        let mut usage: Option<Usage> = None;
+
+        for step in self.steps() {
+            self.record_request(step, messages)?;
+            let start = Instant::now();
+            let response = provider::ask(self.provider, self.model, messages, self.options)
+                .await
+                .map_err(|err| err.to_string())?;
+            self.record(TranscriptEvent::Response {
+                step,
+                content: response.content.clone(),
+                tool_calls: Vec::new(),
+                usage: response.usage.clone(),
+                latency_ms: start.elapsed().as_millis() as u64,
+            })?;
+            usage = add_usage(usage, response.usage);
+
+            let observation = match text_tools::parse_turn(
+                self.protocol,
+                &response.content,
+                &definitions,
+                step,
+            ) {
+                Ok(TextTurn::Answer(answer)) => {
+                    self.push(messages, ChatMessage::assistant(response.content))?;
+                    return Ok(ToolLoopOutcome { answer, usage });
+                }
+                Ok(TextTurn::Call(call)) => {
+                    self.push(
+                        messages,
+                        ChatMessage::assistant(text_tools::assistant_turn(
+                            self.protocol,
+                            &response.content,
+                        )),
+                    )?;
+                    self.execute(&call, step).await?
+                }
+                Err(err) => {
+                    if !self.quiet {
+                        eprintln!("tool: unparsable call: {err}");
+                    }
+                    self.push(
+                        messages,
+                        ChatMessage::assistant(text_tools::assistant_turn(
+                            self.protocol,
+                            &response.content,
+                        )),
+                    )?;
+                    format!("error: {err}")
+                }
+            };
+
+            self.push(
+                messages,
+                text_tools::observation_message(self.protocol, &observation),
+            )?;
+        }
+
+        Err(self.steps_exhausted())
+    }
+
+    /// Runs the calls of one turn, at most `parallelism` at a time, and
+    /// returns their results in call order.
+    async fn execute_all(&self, calls: &[ToolCall], step: usize) -> Vec<Result<String, String>> {
+        let mut results = stream::iter(calls.iter().enumerate())
+            .map(|(index, call)| async move { (index, self.execute(call, step).await) })
+            .buffer_unordered(self.parallelism.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Runs one call; failures become an `error: ...` observation. Only a
+    /// transcript write failure is returned as an error.
+    async fn execute(&self, call: &ToolCall, step: usize) -> Result<String, String> {
+        if !self.quiet {
+            eprintln!("tool: {}({})", call.name, call.args);
+        }
+
+        let start = Instant::now();
+        let result = match self.recorded_tools {
+            Some(recorded) => recorded.take(&call.name, &call.args).unwrap_or_else(|| {
+                Err(format!(
+                    "no recorded output for {}({}) in the replayed transcript",
+                    call.name, call.args
+                ))
+            }),
+            None => self.toolbox.call(call).await,
+        };
+        self.record(TranscriptEvent::Tool {
+            step,
+            id: call.id.clone(),
+            name: call.name.clone(),
+            args: call.args.clone(),
+            output: match &result {
+                Ok(output) | Err(output) => output.clone(),
+            },
+            ok: result.is_ok(),
+            duration_ms: start.elapsed().as_millis() as u64,
+            replayed: self.recorded_tools.is_some(),
+        })?;
+
+        Ok(match result {
+            Ok(output) => output,
+            Err(err) => {
+                if !self.quiet {
+                    eprintln!("tool: {} failed: {err}", call.name);
+                }
+                format!("error: {err}")
+            }
+        })
+    }
+
+    fn steps(&self) -> std::ops::Range<usize> {
+        self.first_step..self.first_step + self.max_steps
+    }
+
+    fn push(&self, messages: &mut Vec<ChatMessage>, message: ChatMessage) -> Result<(), String> {
+        if self.transcript.is_some() {
+            self.record(TranscriptEvent::Message {
+                message: message.clone(),
+            })?;
+        }
+        messages.push(message);
+        Ok(())
+    }
+
+    fn record_request(&self, step: usize, messages: &[ChatMessage]) -> Result<(), String> {
+        self.record(TranscriptEvent::Request {
+            step,
+            message_count: messages.len(),
+            ts_ms: transcript::now_ms(),
+        })
+    }
+
+    fn record(&self, event: TranscriptEvent) -> Result<(), String> {
+        match self.transcript {
+            Some(writer) => writer.record(&event),
+            None => Ok(()),
+        }
+    }
+
+    fn steps_exhausted(&self) -> String {
+        format!(
+            "Agent stopped after {} steps without a final answer (raise --max-steps).",
+            self.max_steps
+        )
+    }
+}
+
+/// Prints a recorded transcript step by step.
+fn replay(path: &Path) -> Result<(), String> {
+    let events = transcript::read(path)?;
+    print!("{}", render_transcript(&events));
+    Ok(())
+}
+
+fn render_transcript(events: &[TranscriptEvent]) -> String {
+    let mut out = String::new();
+    let mut in_prompt = false;
+
+    for event in events {
+        match event {
+            TranscriptEvent::Start {
+                provider,
+                model,
+                protocol,
+                tools,
+                ..
+            } => {
+                out.push_str(&format!(
+                    "== agent run: {provider}/{model} (protocol {protocol}, {} tools)\n",
+                    tools.len()
+                ));
+                in_prompt = true;
+            }
+            TranscriptEvent::Resume { messages, .. } => {
+                out.push_str(&format!("== resumed with {} messages\n", messages.len()));
+                in_prompt = true;
+            }
+            TranscriptEvent::Message { message } if in_prompt => {
+                out.push_str(&format!(
+                    "[{}] {}\n",
+                    message.role,
+                    message_text(&message.content)
+                ));
+            }
+            TranscriptEvent::Message { .. } => {}
+            TranscriptEvent::Request { step, .. } => {
+                out.push_str(&format!("-- step {step}\n"));
+                in_prompt = false;
+            }
+            TranscriptEvent::Response {
+                content,
+                tool_calls,
+                usage,
+                latency_ms,
+                ..
+            } => {
+                out.push_str(&format!(
+                    "[assistant] ({latency_ms} ms{})\n",
+                    render_usage(usage.as_ref())
+                ));
+                if !content.trim().is_empty() {
+                    out.push_str(content.trim_end());
+                    out.push('\n');
+                }
+                for call in tool_calls.iter().filter_map(ToolCall::from_json) {
+                    out.push_str(&format!("-> {}({})\n", call.name, call.args));
+                }
+            }
+            TranscriptEvent::Tool {
+                name,
+                output,
+                ok,
+                duration_ms,
+                replayed,
+                ..
+            } => {
+                out.push_str(&format!(
+                    "[tool {name}] {} ({duration_ms} ms{})\n",
+                    if *ok { "ok" } else { "error" },
+                    if *replayed { ", replayed" } else { "" }
+                ));
+                for line in output.lines() {
+                    out.push_str("  ");
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+            TranscriptEvent::Plan {
+                revision, steps, ..
+            } => {
+                match revision {
+                    0 => out.push_str("== plan\n"),
+                    n => out.push_str(&format!("== plan (revision {n})\n")),
+                }
+                for (index, step) in steps.iter().enumerate() {
+                    out.push_str(&format!(
+                        "  {}. {}\n",
+                        index + 1,
+                        step["title"].as_str().unwrap_or("?")
+                    ));
+                }
+                in_prompt = false;
+            }
+            TranscriptEvent::PlanStep {
+                index,
+                title,
+                status,
+                summary,
+                ..
+            } => {
+                out.push_str(&format!("== plan step {index} {status}: {title}"));
+                if let Some(summary) = summary {
+                    out.push_str(&format!(" — {summary}"));
+                }
+                out.push('\n');
+            }
+            TranscriptEvent::End {
+                status,
+                answer,
+                error,
+                usage,
+                ..
+            } => {
+                out.push_str(&format!("== {status}{}\n", render_usage(usage.as_ref())));
+                if let Some(text) = answer.as_ref().or(error.as_ref()) {
+                    out.push_str(text.trim_end());
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out
+}
+
+fn message_text(content: &MessageContent) -> String {
+    match content {
+        MessageContent::Simple(text) => text.clone(),
+        MessageContent::Multi(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => text.clone(),
+                ContentPart::ImageUrl { .. } => "[image]".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn render_usage(usage: Option<&Usage>) -> String {
+    let Some(usage) = usage else {
+        return String::new();
+    };
+    let tokens = |value: Option<u32>| value.map_or_else(|| "?".to_string(), |n| n.to_string());
+    format!(
+        ", tokens in={} out={}",
+        tokens(usage.prompt_tokens),
+        tokens(usage.completion_tokens)
+    )
+}
+
+pub(crate) fn add_usage(total: Option<Usage>, step: Option<Usage>) -> Option<Usage> {
+    let Some(step) = step else {
+        return total;
+    };
+    let Some(total) = total else {
+        return Some(step);
+    };
+    let sum = |a: Option<u32>, b: Option<u32>| match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    };
+    Some(Usage {
+        prompt_tokens: sum(total.prompt_tokens, step.prompt_tokens),
+        completion_tokens: sum(total.completion_tokens, step.completion_tokens),
+        total_tokens: sum(total.total_tokens, step.total_tokens),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use crate::config::{ToolConfig, ToolParamConfig};
+
+    fn nap_toolbox() -> Toolbox {
+        let params = BTreeMap::from([(
+            "seconds".to_string(),
+            ToolParamConfig {
+                kind: "string".to_string(),
+                description: None,
+                required: true,
+            },
+        )]);
+        let tools = BTreeMap::from([(
+            "nap".to_string(),
+            ToolConfig {
+                description: "Sleep then print the duration".to_string(),
+                command: "sh -c 'sleep \"$0\"; echo \"$0\"' {{seconds}}".to_string(),
+                params,
+                timeout: None,
+                cwd: None,
+                max_output_bytes: None,
+                confirm: false,
+            },
+        )]);
+        let mut toolbox = Toolbox::with_builtins();
+        toolbox
+            .register_config_tools(&tools)
+            .expect("tool should register");
+        toolbox
+    }
+
+    fn call(id: &str, name: &str, args: serde_json::Value) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            name: name.to_string(),
+            args,
+        }
+    }
+
+    #[tokio::test]
+    async fn tool_calls_run_concurrently_and_keep_call_order() {
+        let toolbox = nap_toolbox();
+        let tool_loop = ToolLoop {
+            provider: Provider::Openai,
+            model: "test",
+            toolbox: &toolbox,
+            protocol: ToolProtocol::Native,
+            options: AskOptions::default(),
+            max_steps: 1,
+            parallelism: 3,
+            quiet: true,
+            transcript: None,
+            recorded_tools: None,
+            first_step: 1,
+        };
+        let calls = [
+            call("call_a", "nap", json!({"seconds": "0.6"})),
+            call("call_b", "missing_tool", json!({})),
+            call("call_c", "nap", json!({"seconds": "0.1"})),
+            call("call_d", "nap", json!({"seconds": "0.6"})),
+        ];
+
+        let start = Instant::now();
+        let results = tool_loop
+            .execute_all(&calls, 1)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("tool failures should become results");
+
+        assert!(start.elapsed() < Duration::from_millis(1100));
+        assert_eq!(results[0].trim(), "0.6");
+        assert_eq!(results[1], "error: unknown tool 'missing_tool'");
+        assert_eq!(results[2].trim(), "0.1");
+        assert_eq!(results[3].trim(), "0.6");
+    }
+}
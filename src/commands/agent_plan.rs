@@ -0,0 +1,519 @@
+//! Plan-and-execute mode of `mpipe agent` (`--mode plan`).
+//!
+//! The model first writes a JSON plan, then every step runs as its own tool
+//! loop with a separate step budget. Step prompts carry the task, the plan
+//! and the outcome of earlier steps; a failed step triggers a re-plan of the
+//! remaining work until `--max-replans` is used up.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::commands::agent::{ToolLoop, ToolLoopOutcome, add_usage};
+use crate::commands::prompting::build_messages;
+use crate::rchain::provider::{self, Usage};
+use crate::rchain::transcript::{self, TranscriptEvent};
+
+/// One step of a plan, as written by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PlanStep {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StepStatus {
+    Pending,
+    Done,
+    Failed,
+    Skipped,
+}
+
+impl StepStatus {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// A step together with what happened when it ran.
+#[derive(Debug, Clone)]
+pub(crate) struct StepRecord {
+    pub step: PlanStep,
+    pub status: StepStatus,
+    pub summary: Option<String>,
+}
+
+/// Knobs of a plan-mode run.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PlanSettings {
+    /// Model turns each step may use.
+    pub step_max_steps: usize,
+    pub max_replans: usize,
+    /// Open every plan in `$EDITOR` before executing it.
+    pub edit: bool,
+}
+
+/// Plans, executes and reports. The returned answer is the final report;
+/// an error is returned (after printing the report) when a step failed.
+pub(crate) async fn run(
+    base: &ToolLoop<'_>,
+    system: Option<&str>,
+    task: &str,
+    settings: PlanSettings,
+) -> Result<ToolLoopOutcome, String> {
+    let result = execute_plan(base, system, task, settings).await;
+
+    let end = match &result {
+        Ok((report, usage, true)) => TranscriptEvent::End {
+            status: "answered".to_string(),
+            answer: Some(report.clone()),
+            error: None,
+            usage: usage.clone(),
+            ts_ms: transcript::now_ms(),
+        },
+        Ok((report, usage, false)) => TranscriptEvent::End {
+            status: "failed".to_string(),
+            answer: Some(report.clone()),
+            error: Some("plan did not complete".to_string()),
+            usage: usage.clone(),
+            ts_ms: transcript::now_ms(),
+        },
+        Err(err) => TranscriptEvent::End {
+            status: "failed".to_string(),
+            answer: None,
+            error: Some(err.clone()),
+            usage: None,
+            ts_ms: transcript::now_ms(),
+        },
+    };
+    record(base, end)?;
+
+    match result? {
+        (answer, usage, true) => Ok(ToolLoopOutcome { answer, usage }),
+        (report, _, false) => {
+            println!("{}", report.trim_end());
+            Err("Plan did not complete: a step failed and no re-plans are left.".to_string())
+        }
+    }
+}
+
+/// Returns the report, the summed usage and whether every step succeeded.
+async fn execute_plan(
+    base: &ToolLoop<'_>,
+    system: Option<&str>,
+    task: &str,
+    settings: PlanSettings,
+) -> Result<(String, Option<Usage>, bool), String> {
+    let mut usage: Option<Usage> = None;
+    let mut revision = 0;
+    let mut records: Vec<StepRecord> = Vec::new();
+
+    let steps = request_plan(base, system, &planning_prompt(task), &mut usage).await?;
+    let steps = accept_plan(base, steps, revision, settings)?;
+    records.extend(steps.into_iter().map(pending));
+
+    let mut next = 0;
+    let mut first_step = base.first_step;
+    while next < records.len() {
+        let index = next + 1;
+        let title = records[next].step.title.clone();
+        if !base.quiet {
+            eprintln!("plan: step {index}: {title}");
+        }
+        record_step(base, index, &title, "running", None)?;
+
+        let mut messages = build_messages(system, &step_prompt(task, &records, next));
+        let step_loop = ToolLoop {
+            max_steps: settings.step_max_steps,
+            first_step,
+            ..*base
+        };
+        step_loop.prepare(&mut messages);
+        first_step += settings.step_max_steps;
+
+        let (status, summary) = match step_loop.drive(&mut messages).await {
+            Ok(outcome) => {
+                usage = add_usage(usage, outcome.usage);
+                parse_step_answer(&outcome.answer)
+            }
+            Err(err) => (StepStatus::Failed, err),
+        };
+        records[next].status = status;
+        records[next].summary = Some(summary.clone());
+        record_step(base, index, &title, status.as_str(), Some(summary.clone()))?;
+        if !base.quiet {
+            eprintln!("plan: step {index} {}: {summary}", status.as_str());
+        }
+        next += 1;
+
+        if status == StepStatus::Failed {
+            if revision >= settings.max_replans {
+                for record in &mut records[next..] {
+                    record.status = StepStatus::Skipped;
+                }
+                break;
+            }
+            revision += 1;
+            let prompt = replan_prompt(task, &records[..next], &title, &summary);
+            let steps = request_plan(base, system, &prompt, &mut usage).await?;
+            let steps = accept_plan(base, steps, revision, settings)?;
+            records.truncate(next);
+            records.extend(steps.into_iter().map(pending));
+        }
+    }
+
+    let complete = records
+        .iter()
+        .rev()
+        .find(|record| record.status != StepStatus::Skipped)
+        .is_some_and(|record| record.status == StepStatus::Done);
+    Ok((render_report(&records), usage, complete))
+}
+
+fn pending(step: PlanStep) -> StepRecord {
+    StepRecord {
+        step,
+        status: StepStatus::Pending,
+        summary: None,
+    }
+}
+
+/// Asks the model for a plan, without tools.
+async fn request_plan(
+    base: &ToolLoop<'_>,
+    system: Option<&str>,
+    prompt: &str,
+    usage: &mut Option<Usage>,
+) -> Result<Vec<PlanStep>, String> {
+    let mut instructions = planning_instructions(base);
+    if let Some(system) = system {
+        instructions = format!("{system}\n\n{instructions}");
+    }
+    let messages = build_messages(Some(&instructions), prompt);
+    let response = provider::ask(base.provider, base.model, &messages, base.options)
+        .await
+        .map_err(|err| err.to_string())?;
+    *usage = add_usage(usage.take(), response.usage);
+    parse_plan(&response.content).map_err(|err| format!("The model returned no usable plan: {err}"))
+}
+
+/// Shows the plan, lets the user edit it when asked and records it.
+fn accept_plan(
+    base: &ToolLoop<'_>,
+    steps: Vec<PlanStep>,
+    revision: usize,
+    settings: PlanSettings,
+) -> Result<Vec<PlanStep>, String> {
+    let steps = if settings.edit {
+        edit_plan(&steps)?
+    } else {
+        steps
+    };
+    if !base.quiet {
+        let heading = if revision == 0 {
+            "plan:".to_string()
+        } else {
+            format!("plan (revision {revision}):")
+        };
+        eprintln!("{heading}");
+        for (index, step) in steps.iter().enumerate() {
+            eprintln!("  {}. {}", index + 1, step.title);
+        }
+    }
+    record(
+        base,
+        TranscriptEvent::Plan {
+            revision,
+            steps: steps.iter().map(|step| json!(step)).collect(),
+            ts_ms: transcript::now_ms(),
+        },
+    )?;
+    Ok(steps)
+}
+
+fn planning_instructions(base: &ToolLoop<'_>) -> String {
+    let tools = base
+        .toolbox
+        .definitions()
+        .iter()
+        .map(|tool| format!("- {}: {}", tool.function.name, tool.function.description))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "Break the user's task into a short numbered plan that an assistant with these tools \
+         will carry out one step at a time:\n{tools}\n\n\
+         Do not carry out any step yet. Reply with only a JSON object of the form \
+         {{\"steps\": [{{\"title\": \"...\", \"description\": \"...\"}}]}}, each step being \
+         one checkable unit of work."
+    )
+}
+
+fn planning_prompt(task: &str) -> String {
+    format!("Task:\n{task}")
+}
+
+fn replan_prompt(task: &str, done: &[StepRecord], failed: &str, reason: &str) -> String {
+    format!(
+        "Task:\n{task}\n\nProgress so far:\n{}\nStep \"{failed}\" failed: {reason}\n\n\
+         Plan the remaining work only, taking the failure into account.",
+        render_report(done)
+    )
+}
+
+fn step_prompt(task: &str, records: &[StepRecord], current: usize) -> String {
+    let mut plan = String::new();
+    for (index, record) in records.iter().enumerate() {
+        let status = if index == current {
+            "current"
+        } else {
+            record.status.as_str()
+        };
+        plan.push_str(&format!("{}. [{status}] {}", index + 1, record.step.title));
+        if let Some(summary) = &record.summary {
+            plan.push_str(&format!(" — {summary}"));
+        }
+        plan.push('\n');
+    }
+    let step = &records[current].step;
+    format!(
+        "Overall task:\n{task}\n\nPlan:\n{plan}\nCarry out step {} only: {}\n{}\n\n\
+         When the step is complete, reply with a line starting with \"DONE:\" followed by a \
+         short summary of the outcome. If it cannot be completed, reply with \"FAILED:\" and \
+         the reason.",
+        current + 1,
+        step.title,
+        step.description
+    )
+}
+
+/// Reads a plan from a model reply, tolerating prose or code fences around
+/// the JSON. Accepts `{"steps": [...]}` or a bare array; steps may be
+/// objects or plain strings.
+pub(crate) fn parse_plan(content: &str) -> Result<Vec<PlanStep>, String> {
+    let value = extract_json(content).ok_or_else(|| "no JSON object found".to_string())?;
+    let steps = match &value {
+        Value::Array(steps) => steps,
+        Value::Object(object) => object
+            .get("steps")
+            .and_then(Value::as_array)
+            .ok_or_else(|| "missing 'steps' array".to_string())?,
+        _ => return Err("expected a JSON object".to_string()),
+    };
+
+    let steps = steps
+        .iter()
+        .map(|step| match step {
+            Value::String(title) => Ok(PlanStep {
+                title: title.clone(),
+                description: String::new(),
+            }),
+            other => serde_json::from_value::<PlanStep>(other.clone())
+                .map_err(|err| format!("invalid step {other}: {err}")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if steps.is_empty() {
+        return Err("the plan has no steps".to_string());
+    }
+    if steps.iter().any(|step| step.title.trim().is_empty()) {
+        return Err("every step needs a title".to_string());
+    }
+    Ok(steps)
+}
+
+fn extract_json(content: &str) -> Option<Value> {
+    if let Ok(value) = serde_json::from_str(content.trim()) {
+        return Some(value);
+    }
+    [('{', '}'), ('[', ']')]
+        .into_iter()
+        .find_map(|(open, close)| {
+            let start = content.find(open)?;
+            let end = content.rfind(close)?;
+            (start < end)
+                .then(|| serde_json::from_str(&content[start..=end]).ok())
+                .flatten()
+        })
+}
+
+/// Classifies a step's final answer by its last `DONE:` or `FAILED:` line.
+/// Answers with neither count as done, with the whole answer as summary.
+pub(crate) fn parse_step_answer(answer: &str) -> (StepStatus, String) {
+    for line in answer.lines().rev() {
+        let line = line.trim();
+        if let Some(reason) = line.strip_prefix("FAILED:") {
+            return (StepStatus::Failed, reason.trim().to_string());
+        }
+        if let Some(summary) = line.strip_prefix("DONE:") {
+            return (StepStatus::Done, summary.trim().to_string());
+        }
+    }
+    (StepStatus::Done, answer.trim().to_string())
+}
+
+/// One line per step: `1. [done] Title — summary`.
+pub(crate) fn render_report(records: &[StepRecord]) -> String {
+    let mut out = String::new();
+    for (index, record) in records.iter().enumerate() {
+        out.push_str(&format!(
+            "{}. [{}] {}",
+            index + 1,
+            record.status.as_str(),
+            record.step.title
+        ));
+        if let Some(summary) = record.summary.as_deref().filter(|s| !s.is_empty()) {
+            out.push_str(&format!(" — {summary}"));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Writes the plan to a temporary file, opens `$VISUAL`/`$EDITOR` (default
+/// `vi`) on it and reads it back.
+fn edit_plan(steps: &[PlanStep]) -> Result<Vec<PlanStep>, String> {
+    let text = serde_json::to_string_pretty(&json!({"steps": steps}))
+        .map_err(|err| format!("Failed to serialize the plan: {err}"))?;
+    // Created exclusively with a random name; deleted when dropped.
+    let mut file = tempfile::Builder::new()
+        .prefix("mpipe-plan-")
+        .suffix(".json")
+        .tempfile()
+        .map_err(|err| format!("Failed to create a temporary plan file: {err}"))?;
+    let path = file.path().to_path_buf();
+    file.write_all((text + "\n").as_bytes())
+        .and_then(|()| file.flush())
+        .map_err(|err| format!("Failed to write '{}': {err}", path.display()))?;
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(&path)
+        .status();
+    let edited = fs::read_to_string(&path);
+    drop(file);
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => return Err(format!("Editor '{editor}' exited with {status}.")),
+        Err(err) => return Err(format!("Failed to start editor '{editor}': {err}")),
+    }
+    let edited = edited.map_err(|err| format!("Failed to read the edited plan: {err}"))?;
+    parse_plan(&edited).map_err(|err| format!("Invalid edited plan: {err}"))
+}
+
+fn record_step(
+    base: &ToolLoop<'_>,
+    index: usize,
+    title: &str,
+    status: &str,
+    summary: Option<String>,
+) -> Result<(), String> {
+    record(
+        base,
+        TranscriptEvent::PlanStep {
+            index,
+            title: title.to_string(),
+            status: status.to_string(),
+            summary,
+            ts_ms: transcript::now_ms(),
+        },
+    )
+}
+
+fn record(base: &ToolLoop<'_>, event: TranscriptEvent) -> Result<(), String> {
+    match base.transcript {
+        Some(writer) => writer.record(&event),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(title: &str) -> PlanStep {
+        PlanStep {
+            title: title.to_string(),
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn plans_are_read_from_fenced_or_bare_json() {
+        let fenced = "Here is the plan:\n```json\n{\"steps\": [{\"title\": \"Read\", \
+                      \"description\": \"Read the file\"}, {\"title\": \"Fix\"}]}\n```";
+        assert_eq!(
+            parse_plan(fenced).expect("fenced plan"),
+            vec![
+                PlanStep {
+                    title: "Read".to_string(),
+                    description: "Read the file".to_string(),
+                },
+                step("Fix"),
+            ]
+        );
+        assert_eq!(
+            parse_plan("[\"One\", \"Two\"]").expect("bare array"),
+            vec![step("One"), step("Two")]
+        );
+        assert!(parse_plan("{\"steps\": []}").is_err());
+        assert!(parse_plan("no plan here").is_err());
+    }
+
+    #[test]
+    fn step_answers_are_classified_by_their_last_marker() {
+        assert_eq!(
+            parse_step_answer("Looked around.\nDONE: found 3 files"),
+            (StepStatus::Done, "found 3 files".to_string())
+        );
+        assert_eq!(
+            parse_step_answer("FAILED: permission denied"),
+            (StepStatus::Failed, "permission denied".to_string())
+        );
+        assert_eq!(
+            parse_step_answer("All good."),
+            (StepStatus::Done, "All good.".to_string())
+        );
+    }
+
+    #[test]
+    fn report_lists_every_step_with_its_status() {
+        let records = vec![
+            StepRecord {
+                step: step("Read"),
+                status: StepStatus::Done,
+                summary: Some("read 2 files".to_string()),
+            },
+            StepRecord {
+                step: step("Patch"),
+                status: StepStatus::Failed,
+                summary: Some("tests fail".to_string()),
+            },
+            StepRecord {
+                step: step("Commit"),
+                status: StepStatus::Skipped,
+                summary: None,
+            },
+        ];
+        assert_eq!(
+            render_report(&records),
+            "1. [done] Read — read 2 files\n2. [failed] Patch — tests fail\n3. [skipped] Commit\n"
+        );
+    }
+}
@@ -1,5 +1,3 @@
-
-
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -8,13 +6,18 @@ use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use clap::{Args, ValueEnum};
 use serde::Serialize;
+use serde_json::Value;
 
+use crate::commands::agent::{
+    DEFAULT_PARALLEL_TOOLS, ToolLoop, ToolPolicyArgs, ToolProtocolArg, load_toolbox,
+};
 use crate::commands::prompting::{
-    PromptInput, PromptSource, build_messages, build_messages_with_image,
+    PromptInput, PromptSource, build_messages, build_messages_with_image, compose_prompt,
     non_empty, resolve_prompt,
 };
 use crate::config::{self, ProfileConfig};
-use crate::rchain::provider::{self, AskOptions, ChatMessage, Provider};
+use crate::rchain::provider::{self, AskOptions, AskResponse, ChatMessage, Provider};
+use crate::rchain::text_tools::ToolProtocol;
 
 #[derive(Debug, Args, Clone)]
 pub struct AskArgs {
@@ -84,10 +87,43 @@ pub struct AskArgs {
 
     #[arg(long = "prompt-file")]
     prompt_file: Option<PathBuf>,
+
+    #[arg(long)]
+    preprompt: Option<String>,
+
+    #[arg(long = "preprompt-file")]
+    preprompt_file: Option<PathBuf>,
+
+    #[arg(long)]
+    postprompt: Option<String>,
+
+    #[arg(long = "postprompt-file")]
+    postprompt_file: Option<PathBuf>,
+
+    /// Let the model call built-in and configured tools
+    #[arg(long)]
+    tools: bool,
+
+    /// How tools are offered to the model when --tools is enabled
+    #[arg(long = "tool-protocol", value_enum, default_value_t = ToolProtocolArg::Native)]
+    tool_protocol: ToolProtocolArg,
+
+    #[command(flatten)]
+    tool_policy: ToolPolicyArgs,
+
+    /// Maximum number of model turns when --tools is enabled
+    #[arg(long = "max-steps", default_value_t = 10)]
+    max_steps: usize,
+
+    /// Maximum number of tool calls from one model turn run concurrently
+    #[arg(long = "parallel-tools", default_value_t = DEFAULT_PARALLEL_TOOLS)]
+    parallel_tools: usize,
+
+    input: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
-enum ProviderArg {
+pub(crate) enum ProviderArg {
     Openai,
     Fireworks,
 }
@@ -144,6 +180,8 @@ struct DryRunOutput {
     output: String,
     show_usage: bool,
     authorization: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<Value>>,
 }
 
 #[derive(Debug)]
@@ -180,7 +218,7 @@ pub async fn run(cli: AskArgs) -> Result<(), String> {
     let retry_delay_ms = resolve_retry_delay(cli.retry_delay, &profile)?;
     let output_format = resolve_output_format(cli.output, cli.json, &profile)?;
     let show_usage = resolve_show_usage(cli.show_usage, &profile);
-    let system = resolve_system(cli.system, &profile);
+    let system = resolve_system(cli.system, cli.system_file.as_deref(), &profile)?;
 
     let options = AskOptions {
         temperature,
@@ -190,16 +228,58 @@ pub async fn run(cli: AskArgs) -> Result<(), String> {
         retry_delay_ms,
     };
 
-    let main_prompt = resolve_main_prompt(cli.prompt, cli.prompt_file.as_deref())?;
-    let prompt = &main_prompt.text;
+    let main_prompt = resolve_main_prompt(cli.prompt.or(cli.input), cli.prompt_file.as_deref())?;
+    let preprompt = resolve_prompt_segment(
+        cli.preprompt,
+        cli.preprompt_file.as_deref(),
+        "--preprompt-file",
+    )?;
+    let postprompt = resolve_prompt_segment(
+        cli.postprompt,
+        cli.postprompt_file.as_deref(),
+        "--postprompt-file",
+    )?;
+    let prompt = &compose_prompt(
+        preprompt.as_deref(),
+        &main_prompt.text,
+        postprompt.as_deref(),
+    );
 
-    let messages = if let Some(image_input) = &cli.image {
+    let mut messages = if let Some(image_input) = &cli.image {
         let resolved_url = provider::resolve_image_url(image_input)
             .map_err(|e| format!("Failed to resolve image: {}", e))?;
-        build_messages_with_image(non_empty(system.as_deref()), &prompt, &resolved_url)
+        build_messages_with_image(non_empty(system.as_deref()), prompt, &resolved_url)
+    } else {
+        build_messages(non_empty(system.as_deref()), prompt)
+    };
+
+    if cli.tools && cli.max_steps == 0 {
+        return Err("--max-steps must be > 0".to_string());
+    }
+    if cli.tools && cli.parallel_tools == 0 {
+        return Err("--parallel-tools must be > 0".to_string());
+    }
+    let toolbox = if cli.tools {
+        Some(load_toolbox(cli.tool_policy.resolve()?).await?)
     } else {
-        build_messages(non_empty(system.as_deref()), &prompt)
+        None
     };
+    let tool_loop = toolbox.as_ref().map(|toolbox| ToolLoop {
+        provider,
+        model: &model,
+        toolbox,
+        protocol: cli.tool_protocol.into(),
+        options,
+        max_steps: cli.max_steps,
+        parallelism: cli.parallel_tools,
+        quiet: cli.quiet,
+        transcript: None,
+        recorded_tools: None,
+        first_step: 1,
+    });
+    if let Some(tool_loop) = &tool_loop {
+        tool_loop.prepare(&mut messages);
+    }
 
     if cli.verbose && !cli.quiet {
         log_verbose(VerboseContext {
@@ -219,7 +299,7 @@ pub async fn run(cli: AskArgs) -> Result<(), String> {
             dry_run: true,
             provider: provider.as_str().to_string(),
             endpoint: provider::endpoint(provider).to_string(),
-            model,
+            model: model.clone(),
             messages,
             request: JsonRequest {
                 temperature,
@@ -231,7 +311,21 @@ pub async fn run(cli: AskArgs) -> Result<(), String> {
             output: output_format.as_str().to_string(),
             show_usage,
             authorization: "Bearer ***REDACTED***".to_string(),
+            tools: tool_loop
+                .as_ref()
+                .filter(|tool_loop| tool_loop.protocol == ToolProtocol::Native)
+                .map(|tool_loop| {
+                    tool_loop
+                        .toolbox
+                        .definitions()
+                        .iter()
+                        .map(|tool| tool.to_json())
+                        .collect()
+                }),
         };
+        if let Some(toolbox) = &toolbox {
+            toolbox.shutdown().await;
+        }
         let rendered = format!(
             "{}\n",
             serde_json::to_string(&output)
@@ -248,9 +342,21 @@ pub async fn run(cli: AskArgs) -> Result<(), String> {
     }
 
     let start = Instant::now();
-    let response = provider::ask(provider, &model, &messages, options)
-        .await
-        .map_err(|err| err.to_string())?;
+    let response = match &tool_loop {
+        Some(tool_loop) => {
+            let outcome = tool_loop.run(&mut messages).await;
+            tool_loop.toolbox.shutdown().await;
+            let outcome = outcome?;
+            AskResponse {
+                content: outcome.answer,
+                usage: outcome.usage,
+                tool_calls: Vec::new(),
+            }
+        }
+        None => provider::ask(provider, &model, &messages, options)
+            .await
+            .map_err(|err| err.to_string())?,
+    };
     let latency_ms = start.elapsed().as_millis();
 
     if cli.fail_on_empty && response.content.trim().is_empty() {
@@ -344,8 +450,7 @@ fn write_output(path: &Path, content: &str) -> Result<(), String> {
     Ok(())
 }
 
-
-fn resolve_main_prompt(
+pub(crate) fn resolve_main_prompt(
     cli_prompt: Option<String>,
     prompt_file: Option<&Path>,
 ) -> Result<PromptInput, String> {
@@ -369,6 +474,18 @@ fn resolve_main_prompt(
     resolve_prompt(cli_prompt)
 }
 
+fn resolve_prompt_segment(
+    inline: Option<String>,
+    file: Option<&Path>,
+    option_name: &str,
+) -> Result<Option<String>, String> {
+    if let Some(path) = file {
+        return Ok(Some(read_text_file(path, option_name)?.trim().to_string()));
+    }
+
+    Ok(inline)
+}
+
 fn read_text_file(path: &Path, option_name: &str) -> Result<String, String> {
     fs::read_to_string(path)
         .map_err(|err| format!("Failed to read {} '{}': {err}", option_name, path.display()))
@@ -379,7 +496,7 @@ fn read_text_file(path: &Path, option_name: &str) -> Result<String, String> {
 /// # Arguments
 ///
 /// * `profile_name` - Profile name
-fn resolve_profile(profile_name: Option<&str>) -> Result<ProfileConfig, String> {
+pub(crate) fn resolve_profile(profile_name: Option<&str>) -> Result<ProfileConfig, String> {
     match profile_name {
         Some(name) => config::load_profile(name),
         None => Ok(ProfileConfig::default()),
@@ -394,12 +511,22 @@ fn resolve_show_usage(cli_show_usage: bool, profile: &ProfileConfig) -> bool {
     profile.show_usage.unwrap_or(false)
 }
 
-fn resolve_system(cli_system: Option<String>, profile: &ProfileConfig) -> Option<String> {
+pub(crate) fn resolve_system(
+    cli_system: Option<String>,
+    system_file: Option<&Path>,
+    profile: &ProfileConfig,
+) -> Result<Option<String>, String> {
+    if let Some(path) = system_file {
+        return Ok(Some(
+            read_text_file(path, "--system-file")?.trim().to_string(),
+        ));
+    }
+
     if cli_system.is_some() {
-        return cli_system;
+        return Ok(cli_system);
     }
 
-    profile.system.clone()
+    Ok(profile.system.clone())
 }
 
 fn json_usage(usage: &UsageData) -> Option<JsonUsage> {
@@ -440,7 +567,7 @@ fn print_usage(usage: &Option<UsageData>, latency_ms: u128) {
     eprintln!("usage: unavailable latency_ms={latency_ms}");
 }
 
-fn resolve_provider(
+pub(crate) fn resolve_provider(
     cli_provider: Option<ProviderArg>,
     profile: &ProfileConfig,
 ) -> Result<Provider, String> {
@@ -462,7 +589,7 @@ fn resolve_provider(
     Ok(Provider::Openai)
 }
 
-fn parse_provider_value(raw: &str, source: &str) -> Result<Provider, String> {
+pub(crate) fn parse_provider_value(raw: &str, source: &str) -> Result<Provider, String> {
     match raw.trim().to_ascii_lowercase().as_str() {
         "openai" => Ok(Provider::Openai),
         "fireworks" => Ok(Provider::Fireworks),
@@ -472,7 +599,10 @@ fn parse_provider_value(raw: &str, source: &str) -> Result<Provider, String> {
     }
 }
 
-fn resolve_model(cli_model: Option<String>, profile: &ProfileConfig) -> Result<String, String> {
+pub(crate) fn resolve_model(
+    cli_model: Option<String>,
+    profile: &ProfileConfig,
+) -> Result<String, String> {
     if let Some(model) = cli_model {
         let trimmed = model.trim();
         if !trimmed.is_empty() {
@@ -497,7 +627,7 @@ fn resolve_model(cli_model: Option<String>, profile: &ProfileConfig) -> Result<S
     Err("No model provided. Use --model or set MP_MODEL.".to_string())
 }
 
-fn resolve_temperature(
+pub(crate) fn resolve_temperature(
     cli_temperature: Option<f32>,
     profile: &ProfileConfig,
 ) -> Result<Option<f32>, String> {
@@ -523,7 +653,7 @@ fn resolve_temperature(
     Ok(temperature)
 }
 
-fn resolve_max_tokens(
+pub(crate) fn resolve_max_tokens(
     cli_max_tokens: Option<u32>,
     profile: &ProfileConfig,
 ) -> Result<Option<u32>, String> {
@@ -548,7 +678,7 @@ fn resolve_max_tokens(
     Ok(max_tokens)
 }
 
-fn resolve_timeout(
+pub(crate) fn resolve_timeout(
     cli_timeout: Option<u64>,
     profile: &ProfileConfig,
 ) -> Result<Option<u64>, String> {
@@ -573,7 +703,10 @@ fn resolve_timeout(
     Ok(timeout)
 }
 
-fn resolve_retries(cli_retries: Option<u32>, profile: &ProfileConfig) -> Result<u32, String> {
+pub(crate) fn resolve_retries(
+    cli_retries: Option<u32>,
+    profile: &ProfileConfig,
+) -> Result<u32, String> {
     if let Some(retries) = cli_retries {
         return Ok(retries);
     }
@@ -588,7 +721,7 @@ fn resolve_retries(cli_retries: Option<u32>, profile: &ProfileConfig) -> Result<
     Ok(profile.retries.unwrap_or(0))
 }
 
-fn resolve_retry_delay(
+pub(crate) fn resolve_retry_delay(
     cli_retry_delay: Option<u64>,
     profile: &ProfileConfig,
 ) -> Result<u64, String> {
@@ -679,7 +812,7 @@ fn log_verbose(context: VerboseContext<'_>) {
     );
 }
 
-fn render_version() -> String {
+pub(crate) fn render_version() -> String {
     let commit = option_env!("MP_GIT_SHA").unwrap_or("unknown");
     let built = option_env!("MP_BUILD_TS").unwrap_or("unknown");
     format!(
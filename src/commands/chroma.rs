@@ -244,10 +244,9 @@ async fn start_local_chroma_if_needed(
             }
         })?;
 
-    wait_for_chroma_ready(url).await.map_err(|err| {
+    wait_for_chroma_ready(url).await.inspect_err(|_| {
         let _ = child.kill();
         let _ = child.wait();
-        err
     })?;
 
     eprintln!(
@@ -1,14 +1,21 @@
 use std::env;
 use std::fs;
 use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+use std::time::Instant;
 
 use clap::{Args, ValueEnum};
 use serde::Serialize;
 
 use crate::config::{self, ProfileConfig};
+use crate::rchain::chunking::{ChunkOptions, ChunkStrategy, TextChunk};
 use crate::rchain::embeddings::{
-    self, ChunkStrategy, EmbeddingProvider, EmbeddingResult, EmbeddingsConfig,
+    self, BatchOptions, EmbeddingProvider, EmbeddingResult, EmbeddingsConfig,
 };
+use crate::rchain::provider::Usage;
+use crate::rchain::semantic_chunking::{BreakpointThreshold, SemanticOptions};
+use crate::rchain::vector_io::{self, VectorFormat};
+use crate::rchain::vector_transform::{Quantization, VectorTransform};
 
 #[derive(Debug, Args, Clone)]
 pub struct EmbedArgs {
@@ -21,31 +28,153 @@ pub struct EmbedArgs {
     #[arg(long)]
     pub model: Option<String>,
 
+    /// Maximum chunk length in characters (tokens for `token`)
+    /// [default: 8000]
     #[arg(long)]
     pub chunk_size: Option<usize>,
 
+    /// Overlap between consecutive chunks, as a percentage of --chunk-size
+    /// (0-100) [default: 10]
     #[arg(long)]
     pub chunk_overlap: Option<usize>,
 
+    /// How the input is cut into chunks [default: paragraph]
     #[arg(long, value_enum)]
     pub chunk_strategy: Option<ChunkStrategyArg>,
 
+    /// `tokenizer.json` or tiktoken file counting tokens for
+    /// `--chunk-strategy token` [default: characters / 4]
+    #[arg(long, value_name = "PATH")]
+    pub tokenizer: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub semantic: SemanticChunkArgs,
+
+    #[command(flatten)]
+    pub transform: VectorTransformArgs,
+
     #[arg(long, value_enum)]
     pub output: Option<OutputFormatArg>,
 
     #[arg(long)]
     pub json: bool,
 
+    /// Print the tokens billed for the embeddings on stderr
+    #[arg(long)]
+    pub show_usage: bool,
+
     #[arg(long)]
     pub file: Option<std::path::PathBuf>,
 
+    #[command(flatten)]
+    pub batch: EmbeddingBatchArgs,
+
     input: Option<String>,
 }
 
+/// Request batching flags shared by commands that call an embeddings API.
+#[derive(Debug, Args, Clone)]
+pub struct EmbeddingBatchArgs {
+    /// Maximum inputs sent in one embeddings request
+    #[arg(long, value_name = "N", default_value_t = embeddings::DEFAULT_BATCH_SIZE)]
+    pub batch_size: usize,
+
+    /// Maximum estimated tokens (chars/4) sent in one embeddings request
+    #[arg(long, value_name = "N", default_value_t = embeddings::DEFAULT_BATCH_TOKENS)]
+    pub batch_tokens: usize,
+
+    /// Embeddings requests in flight at once
+    #[arg(long, value_name = "N", default_value_t = embeddings::DEFAULT_CONCURRENCY)]
+    pub concurrency: usize,
+}
+
+impl EmbeddingBatchArgs {
+    pub fn to_options(&self) -> Result<BatchOptions, String> {
+        if self.batch_size == 0 {
+            return Err("--batch-size must be > 0".to_string());
+        }
+        if self.batch_tokens == 0 {
+            return Err("--batch-tokens must be > 0".to_string());
+        }
+        if self.concurrency == 0 {
+            return Err("--concurrency must be > 0".to_string());
+        }
+        Ok(BatchOptions {
+            batch_size: self.batch_size,
+            max_batch_tokens: self.batch_tokens,
+            concurrency: self.concurrency,
+            ..BatchOptions::default()
+        })
+    }
+}
+
+/// `--chunk-strategy semantic` flags shared by `embed` and `index`.
+#[derive(Debug, Args, Clone)]
+pub struct SemanticChunkArgs {
+    /// Distance between neighbouring sentences that starts a new chunk:
+    /// percentile:<0-100> or stddev:<number> [default: percentile:95]
+    #[arg(long, value_name = "SPEC")]
+    pub semantic_threshold: Option<String>,
+
+    /// Characters a `semantic` chunk holds before it may end at a topic
+    /// change [default: --chunk-size / 10]
+    #[arg(long, value_name = "N")]
+    pub min_chunk_size: Option<usize>,
+}
+
+/// Post-processing of embeddings shared by `embed`, `index` and `grep`.
+#[derive(Debug, Args, Clone)]
+pub struct VectorTransformArgs {
+    /// Keep the first N dimensions; requested from the provider, which
+    /// shortens vectors of models supporting it (Matryoshka)
+    #[arg(long, value_name = "N")]
+    pub dimensions: Option<usize>,
+
+    /// Scale vectors to unit length (L2), after --dimensions
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// Reduce values to integers, after --normalize
+    #[arg(long, value_enum)]
+    pub quantize: Option<QuantizeArg>,
+}
+
+impl VectorTransformArgs {
+    pub fn to_transform(&self) -> Result<VectorTransform, String> {
+        if self.dimensions == Some(0) {
+            return Err("--dimensions must be > 0".to_string());
+        }
+        Ok(VectorTransform {
+            dimensions: self.dimensions,
+            normalize: self.normalize,
+            quantization: self.quantize.map(Quantization::from),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum QuantizeArg {
+    /// Values clamped to [-1, 1] and scaled to -127..127
+    Int8,
+    /// 1 for positive values, 0 otherwise
+    Binary,
+}
+
+impl From<QuantizeArg> for Quantization {
+    fn from(arg: QuantizeArg) -> Self {
+        match arg {
+            QuantizeArg::Int8 => Quantization::Int8,
+            QuantizeArg::Binary => Quantization::Binary,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum ProviderArg {
     Openai,
     Fireworks,
+    /// A local model directory passed as the model
+    Local,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -53,12 +182,26 @@ pub enum ChunkStrategyArg {
     Paragraph,
     Sentence,
     Token,
+    Character,
+    Markdown,
+    Code,
+    Semantic,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum OutputFormatArg {
+    /// Same as csv
     Text,
+    /// One object holding all chunks and embeddings
     Json,
+    /// One object per chunk: index, offsets, text and embedding
+    Jsonl,
+    /// 2-D NumPy float32 array
+    Npy,
+    /// Raw little-endian float32 values, row after row
+    F32,
+    /// One row of comma-separated values per chunk
+    Csv,
 }
 
 impl From<ChunkStrategyArg> for ChunkStrategy {
@@ -67,6 +210,10 @@ impl From<ChunkStrategyArg> for ChunkStrategy {
             ChunkStrategyArg::Paragraph => ChunkStrategy::Paragraph,
             ChunkStrategyArg::Sentence => ChunkStrategy::Sentence,
             ChunkStrategyArg::Token => ChunkStrategy::Token,
+            ChunkStrategyArg::Character => ChunkStrategy::Character,
+            ChunkStrategyArg::Markdown => ChunkStrategy::Markdown,
+            ChunkStrategyArg::Code => ChunkStrategy::Code,
+            ChunkStrategyArg::Semantic => ChunkStrategy::Semantic,
         }
     }
 }
@@ -76,6 +223,7 @@ impl From<ProviderArg> for EmbeddingProvider {
         match arg {
             ProviderArg::Openai => EmbeddingProvider::Openai,
             ProviderArg::Fireworks => EmbeddingProvider::Fireworks,
+            ProviderArg::Local => EmbeddingProvider::Local,
         }
     }
 }
@@ -84,37 +232,62 @@ impl From<ProviderArg> for EmbeddingProvider {
 struct JsonOutput {
     provider: String,
     model: String,
-    chunks: Vec<String>,
+    chunks: Vec<TextChunk>,
     embeddings: Vec<Vec<f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
 }
 
-pub fn run(cli: EmbedArgs) -> Result<(), String> {
+pub async fn run(cli: EmbedArgs) -> Result<(), String> {
     let profile = resolve_profile(cli.profile.as_deref())?;
     let provider = resolve_provider(cli.provider, &profile)?;
     let model = resolve_model(cli.model, &profile)?;
-    let chunk_size = resolve_chunk_size(cli.chunk_size, &profile)?;
-    let chunk_overlap = resolve_chunk_overlap(cli.chunk_overlap, &profile)?;
-    let chunk_strategy = resolve_chunk_strategy(cli.chunk_strategy, &profile)?;
+    let chunking = resolve_chunking(
+        cli.chunk_strategy,
+        cli.chunk_size,
+        cli.chunk_overlap,
+        cli.tokenizer,
+        &cli.semantic,
+        &profile,
+        EmbeddingsConfig::default().chunking,
+    )?;
     let output_format = resolve_output_format(cli.output, cli.json)?;
+    if matches!(output_format, OutputFormatArg::Npy | OutputFormatArg::F32)
+        && io::stdout().is_terminal()
+    {
+        return Err(
+            "--output npy and f32 write binary data; redirect stdout to a file.".to_string(),
+        );
+    }
+    let batch = cli.batch.to_options()?;
+    let transform = cli.transform.to_transform()?;
+    let show_usage = cli.show_usage || profile.show_usage.unwrap_or(false);
 
+    let source = cli.file.clone();
     let input_text = resolve_input(cli.input, cli.file)?;
 
     let config = EmbeddingsConfig {
         provider,
         model,
-        chunk_size,
-        chunk_overlap,
-        chunk_strategy,
+        chunking,
+        batch,
+        transform,
     };
 
-    let result = embeddings::embed_texts(&config, &[input_text]).map_err(|err| err.to_string())?;
+    let start = Instant::now();
+    let result = embeddings::embed_texts(&config, &[input_text], source.as_deref())
+        .await
+        .map_err(|err| err.to_string())?;
+    if show_usage {
+        print_usage(result.usage.as_ref(), start.elapsed().as_millis());
+    }
 
-    render_output(&result, output_format)?;
+    render_output(&result, output_format, transform.quantization)?;
 
     Ok(())
 }
 
-fn resolve_profile(profile_name: Option<&str>) -> Result<ProfileConfig, String> {
+pub(crate) fn resolve_profile(profile_name: Option<&str>) -> Result<ProfileConfig, String> {
     match profile_name {
         Some(name) => config::load_profile(name),
         None => Ok(ProfileConfig::default()),
@@ -140,14 +313,48 @@ fn resolve_provider(
     Ok(EmbeddingProvider::Fireworks)
 }
 
-fn parse_provider_value(raw: &str, source: &str) -> Result<EmbeddingProvider, String> {
-    match raw.trim().to_ascii_lowercase().as_str() {
-        "openai" => Ok(EmbeddingProvider::Openai),
-        "fireworks" => Ok(EmbeddingProvider::Fireworks),
-        other => Err(format!(
-            "Invalid {source} '{other}'. Supported values: openai, fireworks."
-        )),
+/// Embeddings provider of commands that also talk to a chat model:
+/// `--embedding-provider`, then `MP_EMBEDDING_PROVIDER`, then the profile's
+/// `embedding_provider`. `MP_PROVIDER` is not consulted; callers pick the
+/// fallback.
+pub(crate) fn resolve_embedding_provider(
+    cli_provider: Option<ProviderArg>,
+    profile: &ProfileConfig,
+) -> Result<Option<EmbeddingProvider>, String> {
+    if let Some(provider) = cli_provider {
+        return Ok(Some(provider.into()));
+    }
+
+    if let Ok(raw) = env::var("MP_EMBEDDING_PROVIDER") {
+        return parse_provider_value(&raw, "MP_EMBEDDING_PROVIDER").map(Some);
+    }
+
+    if let Some(provider) = &profile.embedding_provider {
+        return parse_provider_value(provider, "profile embedding_provider").map(Some);
     }
+
+    Ok(None)
+}
+
+/// `--embedding-model`, then the profile's `embedding_model`.
+pub(crate) fn resolve_embedding_model(
+    cli_model: Option<String>,
+    profile: &ProfileConfig,
+) -> Option<String> {
+    cli_model
+        .into_iter()
+        .chain(profile.embedding_model.clone())
+        .map(|model| model.trim().to_string())
+        .find(|model| !model.is_empty())
+}
+
+fn parse_provider_value(raw: &str, source: &str) -> Result<EmbeddingProvider, String> {
+    EmbeddingProvider::from_str(raw).ok_or_else(|| {
+        format!(
+            "Invalid {source} '{}'. Supported values: openai, fireworks, local.",
+            raw.trim().to_ascii_lowercase()
+        )
+    })
 }
 
 fn resolve_model(cli_model: Option<String>, profile: &ProfileConfig) -> Result<String, String> {
@@ -175,63 +382,116 @@ fn resolve_model(cli_model: Option<String>, profile: &ProfileConfig) -> Result<S
     Ok(EmbeddingProvider::Fireworks.as_str().to_string())
 }
 
-fn resolve_chunk_size(cli_size: Option<usize>, profile: &ProfileConfig) -> Result<usize, String> {
+/// Chunking of `embed` and `index`: each of strategy, size, overlap,
+/// tokenizer and semantic settings comes from the CLI, then the profile,
+/// then the command's `defaults`.
+pub(crate) fn resolve_chunking(
+    cli_strategy: Option<ChunkStrategyArg>,
+    cli_size: Option<usize>,
+    cli_overlap: Option<usize>,
+    cli_tokenizer: Option<PathBuf>,
+    cli_semantic: &SemanticChunkArgs,
+    profile: &ProfileConfig,
+    defaults: ChunkOptions,
+) -> Result<ChunkOptions, String> {
+    let profile_tokenizer = profile
+        .tokenizer
+        .as_deref()
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    Ok(ChunkOptions {
+        strategy: resolve_chunk_strategy(cli_strategy, profile)?.unwrap_or(defaults.strategy),
+        chunk_size: resolve_chunk_size(cli_size, profile)?.unwrap_or(defaults.chunk_size),
+        overlap_percent: resolve_chunk_overlap(cli_overlap, profile)?
+            .unwrap_or(defaults.overlap_percent),
+        tokenizer: cli_tokenizer.or(profile_tokenizer).or(defaults.tokenizer),
+        semantic: resolve_semantic_options(cli_semantic, profile, defaults.semantic)?,
+    })
+}
+
+fn resolve_semantic_options(
+    cli_semantic: &SemanticChunkArgs,
+    profile: &ProfileConfig,
+    defaults: SemanticOptions,
+) -> Result<SemanticOptions, String> {
+    let threshold = match (
+        &cli_semantic.semantic_threshold,
+        &profile.semantic_threshold,
+    ) {
+        (Some(raw), _) | (None, Some(raw)) => BreakpointThreshold::parse(raw)?,
+        (None, None) => defaults.threshold,
+    };
+    let min_chunk_size = cli_semantic
+        .min_chunk_size
+        .or(profile.min_chunk_size)
+        .or(defaults.min_chunk_size);
+    Ok(SemanticOptions {
+        threshold,
+        min_chunk_size,
+    })
+}
+
+fn resolve_chunk_size(
+    cli_size: Option<usize>,
+    profile: &ProfileConfig,
+) -> Result<Option<usize>, String> {
     if let Some(size) = cli_size {
         if size == 0 {
             return Err("Chunk size must be greater than 0.".to_string());
         }
-        return Ok(size);
+        return Ok(Some(size));
     }
 
     if let Some(size) = profile.chunk_size {
         if size == 0 {
             return Err("Chunk size in profile must be greater than 0.".to_string());
         }
-        return Ok(size);
+        return Ok(Some(size));
     }
 
-    Ok(8000)
+    Ok(None)
 }
 
 fn resolve_chunk_overlap(
     cli_overlap: Option<usize>,
     profile: &ProfileConfig,
-) -> Result<usize, String> {
+) -> Result<Option<usize>, String> {
     if let Some(overlap) = cli_overlap {
         if overlap > 100 {
             return Err("Chunk overlap must be between 0 and 100.".to_string());
         }
-        return Ok(overlap);
+        return Ok(Some(overlap));
     }
 
     if let Some(overlap) = profile.chunk_overlap {
         if overlap > 100 {
             return Err("Chunk overlap in profile must be between 0 and 100.".to_string());
         }
-        return Ok(overlap);
+        return Ok(Some(overlap));
     }
 
-    Ok(10)
+    Ok(None)
 }
 
 fn resolve_chunk_strategy(
     cli_strategy: Option<ChunkStrategyArg>,
     profile: &ProfileConfig,
-) -> Result<ChunkStrategy, String> {
+) -> Result<Option<ChunkStrategy>, String> {
     if let Some(strategy) = cli_strategy {
-        return Ok(strategy.into());
+        return Ok(Some(strategy.into()));
     }
 
     if let Some(raw) = &profile.chunk_strategy {
         if let Some(strategy) = ChunkStrategy::from_str(raw) {
-            return Ok(strategy);
+            return Ok(Some(strategy));
         }
         return Err(format!(
-            "Invalid chunk strategy '{raw}'. Supported values: paragraph, sentence, token."
+            "Invalid chunk strategy '{raw}'. Supported values: paragraph, sentence, token, character, markdown, code, semantic."
         ));
     }
 
-    Ok(ChunkStrategy::Paragraph)
+    Ok(None)
 }
 
 fn resolve_output_format(
@@ -282,23 +542,25 @@ fn resolve_input(
     Ok(text)
 }
 
-fn render_output(result: &EmbeddingResult, format: OutputFormatArg) -> Result<(), String> {
-    match format {
-        OutputFormatArg::Text => render_text(result),
-        OutputFormatArg::Json => render_json(result),
-    }
-}
-
-fn render_text(result: &EmbeddingResult) -> Result<(), String> {
-    for embedding in &result.embeddings {
-        let line = embedding
-            .iter()
-            .map(|v| v.to_string())
-            .collect::<Vec<_>>()
-            .join(",");
-        println!("{line}");
-    }
-    Ok(())
+fn render_output(
+    result: &EmbeddingResult,
+    format: OutputFormatArg,
+    quantization: Option<Quantization>,
+) -> Result<(), String> {
+    let format = match format {
+        OutputFormatArg::Json => return render_json(result),
+        OutputFormatArg::Jsonl => VectorFormat::Jsonl,
+        OutputFormatArg::Npy => VectorFormat::Npy,
+        OutputFormatArg::F32 => VectorFormat::F32,
+        OutputFormatArg::Text | OutputFormatArg::Csv => VectorFormat::Csv,
+    };
+    vector_io::write_vectors(
+        format,
+        &result.chunks,
+        &result.embeddings,
+        quantization,
+        &mut io::stdout().lock(),
+    )
 }
 
 fn render_json(result: &EmbeddingResult) -> Result<(), String> {
@@ -307,6 +569,7 @@ fn render_json(result: &EmbeddingResult) -> Result<(), String> {
         model: result.model.clone(),
         chunks: result.chunks.clone(),
         embeddings: result.embeddings.clone(),
+        usage: result.usage.clone(),
     };
 
     let json =
@@ -315,3 +578,14 @@ fn render_json(result: &EmbeddingResult) -> Result<(), String> {
     println!("{json}");
     Ok(())
 }
+
+/// Prints the tokens an embeddings call was billed for, in the format of
+/// `ask --show-usage`.
+pub(crate) fn print_usage(usage: Option<&Usage>, latency_ms: u128) {
+    match usage.and_then(|usage| usage.prompt_tokens) {
+        Some(tokens) => {
+            eprintln!("usage: prompt_tokens={tokens} total_tokens={tokens} latency_ms={latency_ms}")
+        }
+        None => eprintln!("usage: unavailable latency_ms={latency_ms}"),
+    }
+}
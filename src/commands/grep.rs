@@ -1,23 +1,38 @@
 use std::env;
 
-use chromadb::collection::QueryOptions;
 use clap::{Args, ValueEnum};
 use serde::Serialize;
 
+use crate::commands::ask::parse_provider_value;
 use crate::commands::chroma::ChromaConnectArgs;
+use crate::commands::embed::{
+    self, VectorTransformArgs, resolve_embedding_model, resolve_embedding_provider, resolve_profile,
+};
 use crate::commands::prompting::resolve_prompt;
-use crate::rchain::embeddings::{EmbeddingProvider, embed_chunks_with_provider};
+use crate::config::ProfileConfig;
+use crate::rchain::collection_meta::{EmbeddingSpec, ensure_compatible, query_settings};
+use crate::rchain::knowledge::{SourceHit, embed_query, query_collection};
 use crate::rchain::provider::{self, AskOptions, Provider};
 
 const DEFAULT_COLLECTION: &str = "mpipe";
 
 #[derive(Debug, Args, Clone)]
 pub struct GrepArgs {
+    #[arg(long)]
+    profile: Option<String>,
+
     #[arg(long)]
     collection: Option<String>,
 
+    #[arg(long = "embedding-provider", value_enum)]
+    embedding_provider: Option<embed::ProviderArg>,
+
     #[arg(long = "embedding-model")]
-    embedding_model: String,
+    embedding_model: Option<String>,
+
+    /// Defaults to the transform recorded on the collection
+    #[command(flatten)]
+    transform: VectorTransformArgs,
 
     #[arg(long, default_value_t = 5)]
     top_k: usize,
@@ -43,6 +58,11 @@ pub struct GrepArgs {
     #[arg(long)]
     json: bool,
 
+    /// Query even if the collection was built with another embedding
+    /// provider, model, dimension or transform (prints a warning instead)
+    #[arg(long)]
+    force: bool,
+
     #[command(flatten)]
     chroma: ChromaConnectArgs,
 
@@ -55,16 +75,6 @@ enum ProviderArg {
     Fireworks,
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct SourceHit {
-    rank: usize,
-    id: String,
-    source: Option<String>,
-    chunk_index: Option<usize>,
-    distance: Option<f32>,
-    document: String,
-}
-
 #[derive(Debug, Serialize)]
 struct GrepJsonOutput {
     collection: String,
@@ -78,13 +88,15 @@ pub async fn run(args: GrepArgs) -> Result<(), String> {
         return Err("--top-k must be > 0".to_string());
     }
 
+    let profile = resolve_profile(args.profile.as_deref())?;
     let prompt = resolve_prompt(args.prompt)?;
     let prompt_text = prompt.text;
-    let provider = resolve_provider(args.provider)?;
-    let model = resolve_model(args.model)?;
-    let collection_name = resolve_collection_name(args.collection.as_deref());
-
-    let query_embedding = embed_prompt(&args.embedding_model, &prompt_text)?;
+    let provider = resolve_provider(args.provider, &profile)?;
+    let model = resolve_model(args.model, &profile)?;
+    let embedding_provider = resolve_embedding_provider(args.embedding_provider, &profile)?;
+    let embedding_model = resolve_embedding_model(args.embedding_model, &profile);
+    let collection_name = resolve_collection_name(args.collection.as_deref(), &profile);
+    let transform = args.transform.to_transform()?;
 
     let (client, _local_chroma) = crate::commands::chroma::connect(&args.chroma).await?;
     let collection = client
@@ -92,22 +104,43 @@ pub async fn run(args: GrepArgs) -> Result<(), String> {
         .await
         .map_err(|err| format!("Failed to open collection '{collection_name}': {err}"))?;
 
-    let query_result = collection
-        .query(
-            QueryOptions {
-                query_embeddings: Some(vec![query_embedding]),
-                query_texts: None,
-                n_results: Some(args.top_k),
-                where_metadata: None,
-                where_document: None,
-                include: Some(vec!["metadatas", "documents", "distances"]),
-            },
-            None,
-        )
-        .await
-        .map_err(|err| format!("Failed to query collection '{collection_name}': {err}"))?;
+    let stored_spec = EmbeddingSpec::from_metadata(collection.metadata());
+    let (embedding_provider, embedding_model) = query_settings(
+        &collection_name,
+        &stored_spec,
+        embedding_provider,
+        embedding_model.as_deref(),
+        args.force,
+    )?;
+    // Without transform flags the query gets the one the collection records.
+    let query_transform = if transform.is_identity() {
+        stored_spec.transform.unwrap_or_default()
+    } else {
+        let current_spec = EmbeddingSpec {
+            transform: Some(transform),
+            ..EmbeddingSpec::default()
+        };
+        ensure_compatible(&collection_name, &stored_spec, &current_spec, args.force)?;
+        transform
+    };
+    let query_embedding = embed_query(
+        embedding_provider,
+        &embedding_model,
+        &prompt_text,
+        &query_transform,
+    )
+    .await?;
+    ensure_compatible(
+        &collection_name,
+        &stored_spec,
+        &EmbeddingSpec {
+            dimension: Some(query_embedding.len()),
+            ..EmbeddingSpec::default()
+        },
+        args.force,
+    )?;
 
-    let sources = collect_sources(&query_result)?;
+    let sources = query_collection(&collection, query_embedding, args.top_k).await?;
     if sources.is_empty() {
         return Err(format!(
             "No matching chunks found in collection '{collection_name}'."
@@ -120,7 +153,11 @@ pub async fn run(args: GrepArgs) -> Result<(), String> {
     );
 
     let mut messages = Vec::new();
-    if let Some(system) = args.system.as_deref().map(str::trim)
+    if let Some(system) = args
+        .system
+        .as_deref()
+        .or(profile.system.as_deref())
+        .map(str::trim)
         && !system.is_empty()
     {
         messages.push(provider::ChatMessage::system(system));
@@ -132,11 +169,11 @@ pub async fn run(args: GrepArgs) -> Result<(), String> {
         &model,
         &messages,
         AskOptions {
-            temperature: args.temperature,
-            max_tokens: args.max_tokens,
-            timeout_secs: args.timeout,
-            retries: 0,
-            retry_delay_ms: 500,
+            temperature: args.temperature.or(profile.temperature),
+            max_tokens: args.max_tokens.or(profile.max_tokens),
+            timeout_secs: args.timeout.or(profile.timeout),
+            retries: profile.retries.unwrap_or(0),
+            retry_delay_ms: profile.retry_delay.unwrap_or(500),
         },
     )
     .await
@@ -169,78 +206,19 @@ pub async fn run(args: GrepArgs) -> Result<(), String> {
             .map(|d| format!(" distance={d:.4}"))
             .unwrap_or_default();
         println!(
-            "- [{}] {} (id={}{}{})",
-            hit.rank, source, hit.id, chunk, distance
+            "- [{}] {}{} (id={}{}{})",
+            hit.rank,
+            source,
+            hit.location_label(),
+            hit.id,
+            chunk,
+            distance
         );
     }
 
     Ok(())
 }
 
-fn embed_prompt(model: &str, prompt: &str) -> Result<Vec<f32>, String> {
-    let chunks = vec![prompt.to_string()];
-    let mut vectors = embed_chunks_with_provider(EmbeddingProvider::Fireworks, model, &chunks)
-        .map_err(|err| format!("Failed to embed prompt: {err}"))?;
-    let vector = vectors
-        .pop()
-        .ok_or_else(|| "Embedding provider returned no vector for prompt.".to_string())?;
-    Ok(vector.into_iter().map(|v| v as f32).collect())
-}
-
-fn collect_sources(
-    query_result: &chromadb::collection::QueryResult,
-) -> Result<Vec<SourceHit>, String> {
-    let ids = query_result
-        .ids
-        .first()
-        .ok_or_else(|| "Query result did not contain ids.".to_string())?;
-    let docs = query_result
-        .documents
-        .as_ref()
-        .and_then(|all| all.first())
-        .cloned()
-        .unwrap_or_default();
-    let metadatas = query_result
-        .metadatas
-        .as_ref()
-        .and_then(|all| all.first())
-        .cloned()
-        .unwrap_or_default();
-    let distances = query_result
-        .distances
-        .as_ref()
-        .and_then(|all| all.first())
-        .cloned()
-        .unwrap_or_default();
-
-    let mut hits = Vec::with_capacity(ids.len());
-    for (idx, id) in ids.iter().enumerate() {
-        let document = docs.get(idx).cloned().unwrap_or_default();
-        let metadata = metadatas.get(idx).and_then(|m| m.as_ref());
-        let source = metadata.and_then(|m| {
-            m.get("source")
-                .and_then(|value| value.as_str())
-                .map(str::to_string)
-        });
-        let chunk_index = metadata.and_then(|m| {
-            m.get("chunk_index")
-                .and_then(|value| value.as_u64())
-                .map(|v| v as usize)
-        });
-        let distance = distances.get(idx).cloned();
-
-        hits.push(SourceHit {
-            rank: idx + 1,
-            id: id.clone(),
-            source,
-            chunk_index,
-            distance,
-            document,
-        });
-    }
-    Ok(hits)
-}
-
 fn build_context(sources: &[SourceHit]) -> String {
     let mut lines = Vec::with_capacity(sources.len());
     for hit in sources {
@@ -250,9 +228,10 @@ fn build_context(sources: &[SourceHit]) -> String {
             .map(|d| format!("{d:.4}"))
             .unwrap_or_else(|| "n/a".to_string());
         lines.push(format!(
-            "[{}] source={} id={} distance={}\n{}",
+            "[{}] source={}{} id={} distance={}\n{}",
             hit.rank,
             source,
+            hit.location_label(),
             hit.id,
             distance,
             hit.document.trim()
@@ -261,7 +240,10 @@ fn build_context(sources: &[SourceHit]) -> String {
     lines.join("\n\n")
 }
 
-fn resolve_provider(cli_provider: Option<ProviderArg>) -> Result<Provider, String> {
+fn resolve_provider(
+    cli_provider: Option<ProviderArg>,
+    profile: &ProfileConfig,
+) -> Result<Provider, String> {
     if let Some(provider) = cli_provider {
         return Ok(match provider {
             ProviderArg::Openai => Provider::Openai,
@@ -277,11 +259,14 @@ fn resolve_provider(cli_provider: Option<ProviderArg>) -> Result<Provider, Strin
                 "Invalid MP_PROVIDER '{other}'. Supported values: openai, fireworks."
             )),
         },
-        Err(_) => Ok(Provider::Openai),
+        Err(_) => match &profile.provider {
+            Some(raw) => parse_provider_value(raw, "profile provider"),
+            None => Ok(Provider::Openai),
+        },
     }
 }
 
-fn resolve_model(cli_model: Option<String>) -> Result<String, String> {
+fn resolve_model(cli_model: Option<String>, profile: &ProfileConfig) -> Result<String, String> {
     if let Some(model) = cli_model {
         let trimmed = model.trim();
         if trimmed.is_empty() {
@@ -297,10 +282,17 @@ fn resolve_model(cli_model: Option<String>) -> Result<String, String> {
         }
     }
 
+    if let Some(model) = &profile.model {
+        let trimmed = model.trim();
+        if !trimmed.is_empty() {
+            return Ok(trimmed.to_string());
+        }
+    }
+
     Err("No model provided. Use --model or set MP_MODEL.".to_string())
 }
 
-fn resolve_collection_name(cli_collection: Option<&str>) -> String {
+fn resolve_collection_name(cli_collection: Option<&str>, profile: &ProfileConfig) -> String {
     if let Some(collection) = cli_collection {
         let trimmed = collection.trim();
         if !trimmed.is_empty() {
@@ -315,5 +307,12 @@ fn resolve_collection_name(cli_collection: Option<&str>) -> String {
         }
     }
 
+    if let Some(collection) = &profile.collection {
+        let trimmed = collection.trim();
+        if !trimmed.is_empty() {
+            return trimmed.to_string();
+        }
+    }
+
     DEFAULT_COLLECTION.to_string()
 }
@@ -2,17 +2,34 @@ use std::env;
 use std::fs;
 use std::io::{self, IsTerminal, Read};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use chromadb::collection::CollectionEntries;
-use clap::Args;
+use clap::{Args, ValueEnum};
 use serde_json::{Map, Value};
 
 use crate::commands::chroma::{self, ChromaConnectArgs};
-use crate::rchain::embeddings::{EmbeddingProvider, embed_chunks_with_provider};
+use crate::commands::embed::{
+    self, ChunkStrategyArg, EmbeddingBatchArgs, ProviderArg, SemanticChunkArgs,
+    VectorTransformArgs, resolve_chunking, resolve_embedding_model, resolve_embedding_provider,
+    resolve_profile,
+};
+use crate::config::ProfileConfig;
+use crate::rchain::chunking::{ChunkOptions, ChunkStrategy, TextChunk};
+use crate::rchain::collection_meta::{EmbeddingSpec, ensure_compatible};
+use crate::rchain::embeddings::{BatchOptions, EmbeddingClient, EmbeddingProvider};
+use crate::rchain::semantic_chunking::{SemanticOptions, semantic_chunks};
+use crate::rchain::vector_io::{self, VectorFormat};
+use crate::rchain::vector_transform::VectorTransform;
 
 const DEFAULT_COLLECTION: &str = "mpipe";
-const DEFAULT_CHUNK_SIZE: usize = 1000;
-const DEFAULT_CHUNK_OVERLAP: usize = 200;
+const DEFAULT_CHUNKING: ChunkOptions = ChunkOptions {
+    strategy: ChunkStrategy::Character,
+    chunk_size: 1000,
+    overlap_percent: 20,
+    tokenizer: None,
+    semantic: SemanticOptions::DEFAULT,
+};
 
 #[derive(Debug, Args, Clone)]
 pub struct IndexArgs {
@@ -22,21 +39,54 @@ pub struct IndexArgs {
     #[arg(long)]
     document: Option<String>,
 
+    #[arg(long)]
+    profile: Option<String>,
+
+    #[arg(long = "embedding-provider", value_enum)]
+    embedding_provider: Option<ProviderArg>,
+
     #[arg(long = "embedding-model")]
     embedding_model: Option<String>,
 
+    /// Maximum chunk length in characters (tokens for `token`)
+    /// [default: 1000]
     #[arg(long, value_name = "SIZE")]
     chunk_size: Option<usize>,
 
-    #[arg(long, value_name = "SIZE")]
+    /// Overlap between consecutive chunks, as a percentage of --chunk-size
+    /// (0-100) [default: 20]
+    #[arg(long, value_name = "PERCENT")]
     chunk_overlap: Option<usize>,
 
+    /// How the document is cut into chunks [default: character]
+    #[arg(long, value_enum)]
+    chunk_strategy: Option<ChunkStrategyArg>,
+
+    /// `tokenizer.json` or tiktoken file counting tokens for
+    /// `--chunk-strategy token` [default: characters / 4]
+    #[arg(long, value_name = "PATH")]
+    tokenizer: Option<PathBuf>,
+
+    #[command(flatten)]
+    semantic: SemanticChunkArgs,
+
     #[arg(long)]
     collection: Option<String>,
 
     #[command(flatten)]
     chroma: ChromaConnectArgs,
 
+    #[command(flatten)]
+    batch: EmbeddingBatchArgs,
+
+    /// Also applied to precomputed embeddings
+    #[command(flatten)]
+    transform: VectorTransformArgs,
+
+    /// Print the tokens billed for the embeddings on stderr
+    #[arg(long)]
+    show_usage: bool,
+
     #[arg(long = "source")]
     source: Option<String>,
 
@@ -48,58 +98,163 @@ pub struct IndexArgs {
 
     #[arg(long = "metadata-json")]
     metadata_json: Option<PathBuf>,
+
+    /// Precomputed embeddings, one per chunk, instead of calling an
+    /// embeddings API; stdin is read when it is piped
+    #[arg(long, value_name = "PATH")]
+    embeddings_file: Option<PathBuf>,
+
+    /// Format of --embeddings-file or stdin embeddings [default: from the
+    /// file extension, csv for stdin]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    embeddings_format: Option<EmbeddingsFormatArg>,
+
+    /// Index even if the collection was built with another embedding
+    /// provider, model, dimension or transform (prints a warning instead)
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum EmbeddingsFormatArg {
+    Jsonl,
+    Npy,
+    F32,
+    Csv,
 }
 
-#[derive(Debug, Clone)]
-struct Chunk {
-    text: String,
-    char_start: usize,
-    char_end: usize,
+impl From<EmbeddingsFormatArg> for VectorFormat {
+    fn from(arg: EmbeddingsFormatArg) -> Self {
+        match arg {
+            EmbeddingsFormatArg::Jsonl => VectorFormat::Jsonl,
+            EmbeddingsFormatArg::Npy => VectorFormat::Npy,
+            EmbeddingsFormatArg::F32 => VectorFormat::F32,
+            EmbeddingsFormatArg::Csv => VectorFormat::Csv,
+        }
+    }
 }
 
 pub async fn run(args: IndexArgs) -> Result<(), String> {
     validate_inputs(&args)?;
-
-    let chunk_size = args.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
-    let chunk_overlap = args.chunk_overlap.unwrap_or(DEFAULT_CHUNK_OVERLAP);
-    if chunk_size == 0 {
-        return Err("--chunk-size must be > 0".to_string());
-    }
-    if chunk_overlap >= chunk_size {
-        return Err("--chunk-overlap must be < --chunk-size".to_string());
-    }
+    let profile = resolve_profile(args.profile.as_deref())?;
+
+    let chunking = resolve_chunking(
+        args.chunk_strategy,
+        args.chunk_size,
+        args.chunk_overlap,
+        args.tokenizer.clone(),
+        &args.semantic,
+        &profile,
+        DEFAULT_CHUNKING,
+    )?;
+    let batch = args.batch.to_options()?;
+    let transform = args.transform.to_transform()?;
 
     let document = read_document(&args)?;
-    let chunks = split_text(&document, chunk_size, chunk_overlap);
+    let source = resolve_source(&args)?;
+    // `code` chunking detects the language from the file, or the source name;
+    // `semantic` chunking needs the embeddings client and has no chunker.
+    let path = args.file.as_deref().unwrap_or(Path::new(&source));
+    let chunker = match chunking.strategy {
+        ChunkStrategy::Semantic => None,
+        _ => Some(chunking.chunker(Some(path))?),
+    };
+
+    let precomputed = read_precomputed_embeddings(&args)?;
+    // Embeddings already provided; the embedding model is ignored.
+    let embedding_settings = match precomputed {
+        Some(_) => None,
+        None => {
+            let provider = resolve_embedding_provider(args.embedding_provider, &profile)?
+                .unwrap_or(EmbeddingProvider::Fireworks);
+            let model = resolve_embedding_model(args.embedding_model.clone(), &profile)
+                .ok_or_else(|| {
+                    "Missing --embedding-model (required when stdin embeddings are not provided)."
+                        .to_string()
+                })?;
+            Some((provider, model))
+        }
+    };
+
+    let chunks = match (chunker, &embedding_settings) {
+        (Some(chunker), _) => chunker.chunk(&document),
+        (None, Some((provider, model))) => {
+            let client = EmbeddingClient::new(*provider, model, batch)
+                .map_err(|err| format!("Failed to chunk document: {err}"))?;
+            let start = Instant::now();
+            let (chunks, usage) = semantic_chunks(&document, &chunking, &client).await?;
+            if args.show_usage {
+                embed::print_usage(usage.as_ref(), start.elapsed().as_millis());
+            }
+            chunks
+        }
+        (None, None) => {
+            return Err(
+                "The semantic chunk strategy embeds the document to chunk it and cannot be used with precomputed embeddings."
+                    .to_string(),
+            );
+        }
+    };
     if chunks.is_empty() {
         return Err("Document is empty after trimming.".to_string());
     }
-
-    let embeddings_from_stdin = read_embeddings_from_stdin()?;
-    if embeddings_from_stdin.is_some() && args.embedding_model.is_some() {
-        // Embeddings already provided; model is ignored.
-    }
-
-    let embeddings = if let Some(vectors) = embeddings_from_stdin {
-        validate_embeddings_count(&vectors, chunks.len())?;
-        validate_embeddings_dimensions(&vectors)?;
-        vectors
-    } else {
-        let model = args.embedding_model.as_ref().ok_or_else(|| {
-            "Missing --embedding-model (required when stdin embeddings are not provided)."
-                .to_string()
-        })?;
-        embed_chunks(model, &chunks).await?
+    let embeddings_from_input = match precomputed {
+        Some((format, bytes)) => {
+            let vectors = vector_io::read_vectors(format, &bytes, chunks.len())?;
+            validate_embeddings_count(&vectors, chunks.len())?;
+            validate_embeddings_dimensions(&vectors)?;
+            Some(transform_embeddings(vectors, &transform)?)
+        }
+        None => None,
     };
-    let source = resolve_source(&args)?;
 
-    let collection_name = resolve_collection_name(args.collection.as_deref());
+    let collection_name = resolve_collection_name(args.collection.as_deref(), &profile);
     let (client, _local_chroma) = chroma::connect(&args.chroma).await?;
 
     let collection = client
         .get_or_create_collection(&collection_name, None)
         .await
         .map_err(|err| format!("Failed to open collection '{collection_name}': {err}"))?;
+    let stored_spec = EmbeddingSpec::from_metadata(collection.metadata());
+    // How precomputed embeddings were made is unknown; only the transform
+    // applied here is recorded for them.
+    let mut spec = match &embedding_settings {
+        Some((provider, model)) => EmbeddingSpec {
+            transform: Some(transform),
+            ..EmbeddingSpec::new(*provider, model, None)
+        },
+        None => EmbeddingSpec {
+            transform: (!transform.is_identity()).then_some(transform),
+            ..EmbeddingSpec::default()
+        },
+    };
+    ensure_compatible(&collection_name, &stored_spec, &spec, args.force)?;
+
+    let embeddings = match &embedding_settings {
+        Some((provider, model)) => {
+            embed_chunks(
+                *provider,
+                model,
+                &chunks,
+                batch,
+                &transform,
+                args.show_usage,
+            )
+            .await?
+        }
+        None => embeddings_from_input.unwrap_or_default(),
+    };
+    let dimension = embeddings.first().map(Vec::len);
+    ensure_compatible(
+        &collection_name,
+        &stored_spec,
+        &EmbeddingSpec {
+            dimension,
+            ..EmbeddingSpec::default()
+        },
+        args.force,
+    )?;
+    spec.dimension = dimension;
 
     let mut base_metadata = load_metadata_json(args.metadata_json.as_deref())?;
     let overrides = parse_metadata_overrides(&args.metadata)?;
@@ -124,6 +279,12 @@ pub async fn run(args: IndexArgs) -> Result<(), String> {
         .upsert(collection_entries, None)
         .await
         .map_err(|err| format!("Failed to upsert into collection '{collection_name}': {err}"))?;
+    collection
+        .modify(None, Some(&spec.to_metadata(collection.metadata())))
+        .await
+        .map_err(|err| {
+            format!("Failed to update metadata of collection '{collection_name}': {err}")
+        })?;
 
     println!(
         "indexed {} chunks into collection '{}'",
@@ -188,56 +349,51 @@ fn read_file(path: &Path) -> Result<String, String> {
     Ok(contents)
 }
 
-fn read_embeddings_from_stdin() -> Result<Option<Vec<Vec<f32>>>, String> {
+/// Embeddings passed with `--embeddings-file`, or piped on stdin, with
+/// their format. They are parsed once the chunk count is known.
+fn read_precomputed_embeddings(
+    args: &IndexArgs,
+) -> Result<Option<(VectorFormat, Vec<u8>)>, String> {
+    let format = args.embeddings_format.map(VectorFormat::from);
+    if let Some(path) = &args.embeddings_file {
+        let format = format
+            .or_else(|| VectorFormat::from_path(path))
+            .ok_or_else(|| {
+                format!(
+                    "Cannot tell the format of embeddings file '{}'; pass --embeddings-format jsonl|npy|f32|csv.",
+                    path.display()
+                )
+            })?;
+        let bytes = fs::read(path)
+            .map_err(|err| format!("Failed to read embeddings file '{}': {err}", path.display()))?;
+        if is_blank(format, &bytes) {
+            return Err(format!("Embeddings file '{}' is empty.", path.display()));
+        }
+        return Ok(Some((format, bytes)));
+    }
+
     if io::stdin().is_terminal() {
         return Ok(None);
     }
 
-    let mut buffer = String::new();
+    let mut bytes = Vec::new();
     io::stdin()
-        .read_to_string(&mut buffer)
+        .read_to_end(&mut bytes)
         .map_err(|err| format!("Failed to read stdin embeddings: {err}"))?;
 
-    if buffer.trim().is_empty() {
+    let format = format.unwrap_or(VectorFormat::Csv);
+    if is_blank(format, &bytes) {
         return Err("Stdin embeddings are empty.".to_string());
     }
 
-    let mut embeddings = Vec::new();
-    for (line_idx, raw_line) in buffer.lines().enumerate() {
-        let line = raw_line.trim();
-        if line.is_empty() {
-            continue;
-        }
-        let mut vector = Vec::new();
-        for (value_idx, value) in line.split(',').enumerate() {
-            let trimmed = value.trim();
-            if trimmed.is_empty() {
-                continue;
-            }
-            let parsed = trimmed.parse::<f32>().map_err(|_| {
-                format!(
-                    "Invalid float at line {} position {}: '{}'",
-                    line_idx + 1,
-                    value_idx + 1,
-                    trimmed
-                )
-            })?;
-            vector.push(parsed);
-        }
-        if vector.is_empty() {
-            return Err(format!(
-                "No floats parsed for embeddings line {}.",
-                line_idx + 1
-            ));
-        }
-        embeddings.push(vector);
-    }
+    Ok(Some((format, bytes)))
+}
 
-    if embeddings.is_empty() {
-        return Err("No embeddings parsed from stdin.".to_string());
+fn is_blank(format: VectorFormat, bytes: &[u8]) -> bool {
+    match format.is_binary() {
+        true => bytes.is_empty(),
+        false => bytes.iter().all(u8::is_ascii_whitespace),
     }
-
-    Ok(Some(embeddings))
 }
 
 fn validate_embeddings_count(embeddings: &[Vec<f32>], chunk_count: usize) -> Result<(), String> {
@@ -273,27 +429,54 @@ fn validate_embeddings_dimensions(embeddings: &[Vec<f32>]) -> Result<(), String>
     Ok(())
 }
 
-async fn embed_chunks(model: &str, chunks: &[Chunk]) -> Result<Vec<Vec<f32>>, String> {
-    let model = model.to_string();
+async fn embed_chunks(
+    provider: EmbeddingProvider,
+    model: &str,
+    chunks: &[TextChunk],
+    batch: BatchOptions,
+    transform: &VectorTransform,
+    show_usage: bool,
+) -> Result<Vec<Vec<f32>>, String> {
     let chunk_texts = chunks
         .iter()
         .map(|chunk| chunk.text.clone())
         .collect::<Vec<_>>();
 
-    tokio::task::spawn_blocking(move || {
-        let embeddings =
-            embed_chunks_with_provider(EmbeddingProvider::Fireworks, &model, &chunk_texts)
-                .map_err(|err| format!("Failed to embed chunks: {err}"))?
-                .into_iter()
-                .map(|vector| vector.into_iter().map(|value| value as f32).collect())
-                .collect();
-        Ok::<_, String>(embeddings)
-    })
-    .await
-    .map_err(|err| format!("Embedding task failed: {err}"))?
+    let client = EmbeddingClient::new(provider, model, batch)
+        .map_err(|err| format!("Failed to embed chunks: {err}"))?;
+    let start = Instant::now();
+    let embeddings = client
+        .embed_transformed(&chunk_texts, transform)
+        .await
+        .map_err(|err| format!("Failed to embed chunks: {err}"))?;
+    if show_usage {
+        embed::print_usage(embeddings.usage.as_ref(), start.elapsed().as_millis());
+    }
+    let embeddings = embeddings
+        .vectors
+        .into_iter()
+        .map(|vector| vector.into_iter().map(|value| value as f32).collect())
+        .collect();
+    Ok(embeddings)
+}
+
+fn transform_embeddings(
+    embeddings: Vec<Vec<f32>>,
+    transform: &VectorTransform,
+) -> Result<Vec<Vec<f32>>, String> {
+    if transform.is_identity() {
+        return Ok(embeddings);
+    }
+    embeddings
+        .into_iter()
+        .map(|vector| {
+            let vector = transform.apply(vector.into_iter().map(f64::from).collect())?;
+            Ok(vector.into_iter().map(|value| value as f32).collect())
+        })
+        .collect()
 }
 
-fn resolve_collection_name(cli_collection: Option<&str>) -> String {
+fn resolve_collection_name(cli_collection: Option<&str>, profile: &ProfileConfig) -> String {
     if let Some(collection) = cli_collection {
         let trimmed = collection.trim();
         if !trimmed.is_empty() {
@@ -308,6 +491,13 @@ fn resolve_collection_name(cli_collection: Option<&str>) -> String {
         }
     }
 
+    if let Some(collection) = &profile.collection {
+        let trimmed = collection.trim();
+        if !trimmed.is_empty() {
+            return trimmed.to_string();
+        }
+    }
+
     DEFAULT_COLLECTION.to_string()
 }
 
@@ -383,7 +573,7 @@ fn build_ids(
 }
 
 fn build_chunk_metadatas(
-    chunks: &[Chunk],
+    chunks: &[TextChunk],
     base: &Map<String, Value>,
     source: &str,
     chunk_count: usize,
@@ -398,55 +588,23 @@ fn build_chunk_metadatas(
             metadata.insert("chunk_count".to_string(), Value::from(chunk_count));
             metadata.insert("char_start".to_string(), Value::from(chunk.char_start));
             metadata.insert("char_end".to_string(), Value::from(chunk.char_end));
+            metadata.insert("byte_start".to_string(), Value::from(chunk.byte_start));
+            metadata.insert("byte_end".to_string(), Value::from(chunk.byte_end));
+            if let Some(heading_path) = &chunk.heading_path {
+                metadata.insert(
+                    "heading_path".to_string(),
+                    Value::String(heading_path.clone()),
+                );
+            }
+            if let Some(code) = &chunk.code {
+                metadata.insert("language".to_string(), Value::from(code.language));
+                if let Some(symbol) = &code.symbol {
+                    metadata.insert("symbol".to_string(), Value::String(symbol.clone()));
+                }
+                metadata.insert("line_start".to_string(), Value::from(code.line_start));
+                metadata.insert("line_end".to_string(), Value::from(code.line_end));
+            }
             metadata
         })
         .collect()
 }
-
-fn split_text(text: &str, chunk_size: usize, overlap: usize) -> Vec<Chunk> {
-    let trimmed = text.trim();
-    if trimmed.is_empty() {
-        return Vec::new();
-    }
-
-    let chars: Vec<char> = text.chars().collect();
-    let len = chars.len();
-    let mut chunks = Vec::new();
-    let mut start = 0;
-
-    while start < len {
-        let mut end = (start + chunk_size).min(len);
-        if end < len {
-            if let Some(split_index) = (start..end)
-                .rev()
-                .find(|&idx| chars[idx].is_whitespace() && idx > start)
-            {
-                end = split_index;
-            }
-        }
-
-        if end == start {
-            end = (start + chunk_size).min(len);
-        }
-
-        let text = chars[start..end].iter().collect::<String>();
-        chunks.push(Chunk {
-            text,
-            char_start: start,
-            char_end: end,
-        });
-
-        if end == len {
-            break;
-        }
-
-        let next_start = end.saturating_sub(overlap);
-        if next_start <= start {
-            start = end;
-        } else {
-            start = next_start;
-        }
-    }
-
-    chunks
-}
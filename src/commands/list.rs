@@ -5,11 +5,16 @@ use clap::Args;
 use serde::Serialize;
 
 use crate::commands::chroma::ChromaConnectArgs;
+use crate::commands::embed::resolve_profile;
+use crate::config::ProfileConfig;
 
 const DEFAULT_COLLECTION: &str = "mpipe";
 
 #[derive(Debug, Args, Clone)]
 pub struct ListArgs {
+    #[arg(long)]
+    profile: Option<String>,
+
     #[arg(long)]
     collection: Option<String>,
 
@@ -40,7 +45,8 @@ pub async fn run(args: ListArgs) -> Result<(), String> {
         return Err("--limit must be > 0".to_string());
     }
 
-    let collection_name = resolve_collection_name(args.collection.as_deref());
+    let profile = resolve_profile(args.profile.as_deref())?;
+    let collection_name = resolve_collection_name(args.collection.as_deref(), &profile);
     let (client, _local_chroma) = crate::commands::chroma::connect(&args.chroma).await?;
     let collection = client
         .get_collection(&collection_name)
@@ -128,7 +134,7 @@ pub async fn run(args: ListArgs) -> Result<(), String> {
     Ok(())
 }
 
-fn resolve_collection_name(cli_collection: Option<&str>) -> String {
+fn resolve_collection_name(cli_collection: Option<&str>, profile: &ProfileConfig) -> String {
     if let Some(collection) = cli_collection {
         let trimmed = collection.trim();
         if !trimmed.is_empty() {
@@ -143,6 +149,13 @@ fn resolve_collection_name(cli_collection: Option<&str>) -> String {
         }
     }
 
+    if let Some(collection) = &profile.collection {
+        let trimmed = collection.trim();
+        if !trimmed.is_empty() {
+            return trimmed.to_string();
+        }
+    }
+
     DEFAULT_COLLECTION.to_string()
 }
 
@@ -0,0 +1,192 @@
+use clap::{Args, Subcommand};
+
+use crate::commands::chroma::{self, ChromaConnectArgs};
+use crate::commands::embed::ProviderArg;
+use crate::rchain::memory::{self, MemoryEntry, MemoryStore};
+
+#[derive(Debug, Args, Clone)]
+pub struct MemoryArgs {
+    #[command(subcommand)]
+    command: MemorySubcommand,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum MemorySubcommand {
+    #[command(about = "List remembered facts, newest first")]
+    List(MemoryListArgs),
+    #[command(about = "Delete facts by id, or every fact of a session")]
+    Forget(MemoryForgetArgs),
+    #[command(about = "Show the facts closest to a query")]
+    Search(MemorySearchArgs),
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct MemoryListArgs {
+    /// Memory collection
+    collection: String,
+
+    /// Only facts stored by this session
+    #[arg(long)]
+    session: Option<String>,
+
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    chroma: ChromaConnectArgs,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct MemoryForgetArgs {
+    /// Memory collection
+    collection: String,
+
+    /// Ids of the facts to delete
+    #[arg(required_unless_present = "session", conflicts_with = "session")]
+    ids: Vec<String>,
+
+    /// Delete every fact stored by this session
+    #[arg(long)]
+    session: Option<String>,
+
+    #[command(flatten)]
+    chroma: ChromaConnectArgs,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct MemorySearchArgs {
+    /// Memory collection
+    collection: String,
+
+    query: String,
+
+    /// [default: the one the collection records, else fireworks]
+    #[arg(long = "embedding-provider", value_enum)]
+    embedding_provider: Option<ProviderArg>,
+
+    /// [default: the one the collection records]
+    #[arg(long = "embedding-model")]
+    embedding_model: Option<String>,
+
+    #[arg(long, default_value_t = memory::DEFAULT_RECALL)]
+    top_k: usize,
+
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    chroma: ChromaConnectArgs,
+}
+
+pub async fn run(args: MemoryArgs) -> Result<(), String> {
+    match args.command {
+        MemorySubcommand::List(args) => run_list(args).await,
+        MemorySubcommand::Forget(args) => run_forget(args).await,
+        MemorySubcommand::Search(args) => run_search(args).await,
+    }
+}
+
+async fn run_list(args: MemoryListArgs) -> Result<(), String> {
+    if args.limit == 0 {
+        return Err("--limit must be > 0".to_string());
+    }
+
+    let (client, _local_chroma) = chroma::connect(&args.chroma).await?;
+    let collection = client
+        .get_collection(&args.collection)
+        .await
+        .map_err(|err| format!("Failed to open collection '{}': {err}", args.collection))?;
+    let entries = memory::list_memories(
+        &collection,
+        args.session.as_deref(),
+        args.limit,
+        args.offset,
+    )
+    .await?;
+
+    if args.json {
+        return print_json(&entries);
+    }
+    if entries.is_empty() {
+        println!("no memories in collection '{}'", args.collection);
+        return Ok(());
+    }
+    for entry in &entries {
+        println!("{}", render_entry(entry));
+    }
+    Ok(())
+}
+
+async fn run_forget(args: MemoryForgetArgs) -> Result<(), String> {
+    let (client, _local_chroma) = chroma::connect(&args.chroma).await?;
+    let collection = client
+        .get_collection(&args.collection)
+        .await
+        .map_err(|err| format!("Failed to open collection '{}': {err}", args.collection))?;
+    memory::forget_memories(&collection, &args.ids, args.session.as_deref()).await?;
+
+    match &args.session {
+        Some(session) => println!("forgot the memories of session '{session}'"),
+        None => println!("forgot {} memories", args.ids.len()),
+    }
+    Ok(())
+}
+
+async fn run_search(args: MemorySearchArgs) -> Result<(), String> {
+    if args.top_k == 0 {
+        return Err("--top-k must be > 0".to_string());
+    }
+
+    let (client, _local_chroma) = chroma::connect(&args.chroma).await?;
+    let collection = client
+        .get_collection(&args.collection)
+        .await
+        .map_err(|err| format!("Failed to open collection '{}': {err}", args.collection))?;
+    let store = MemoryStore::inspect(
+        collection,
+        args.embedding_provider.map(Into::into),
+        args.embedding_model.as_deref(),
+    )?;
+    let entries = store.recall(&args.query, args.top_k).await?;
+
+    if args.json {
+        return print_json(&entries);
+    }
+    if entries.is_empty() {
+        println!("no memories in collection '{}'", args.collection);
+        return Ok(());
+    }
+    for entry in &entries {
+        let distance = entry
+            .distance
+            .map(|d| format!("\tdistance={d:.4}"))
+            .unwrap_or_default();
+        println!("{}{distance}", render_entry(entry));
+    }
+    Ok(())
+}
+
+fn render_entry(entry: &MemoryEntry) -> String {
+    format!(
+        "{}\tsession={}\tts_ms={}\t{}",
+        entry.id,
+        entry.session_id.as_deref().unwrap_or("-"),
+        entry
+            .ts_ms
+            .map_or_else(|| "-".to_string(), |ts| ts.to_string()),
+        entry.fact
+    )
+}
+
+fn print_json(entries: &[MemoryEntry]) -> Result<(), String> {
+    let rendered = serde_json::to_string(entries)
+        .map_err(|err| format!("Failed to serialize memory output: {err}"))?;
+    println!("{rendered}");
+    Ok(())
+}
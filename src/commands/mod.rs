@@ -1,5 +1,5 @@
-
 pub mod agent;
+pub mod agent_plan;
 
 pub mod ask;
 pub mod chroma;
@@ -9,7 +9,8 @@ pub mod embed;
 pub mod grep;
 pub mod index;
 pub mod list;
+pub mod memory;
 pub mod models;
 pub mod prompt;
 pub mod prompting;
-mod tools;
+pub mod tools;
@@ -1,17 +1,185 @@
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use serde_json::Value;
 
+use crate::commands::agent::load_toolbox;
+use crate::config;
+use crate::rchain::command_tools::render_argv;
+use crate::rchain::tool_policy::ToolPolicy;
+use crate::rchain::toolbox::{RegisteredTool, Toolbox};
+use crate::rchain::tools::ToolCall;
 
-use clap::{Args, ValueEnum};
+#[derive(Debug, Args, Clone)]
+pub struct ToolsArgs {
+    #[command(subcommand)]
+    command: ToolSubcommand,
+}
 
+#[derive(Debug, Subcommand, Clone)]
+enum ToolSubcommand {
+    #[command(about = "List built-in and configured tools")]
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    #[command(about = "Show a tool's description and parameters")]
+    Describe { name: String },
+    #[command(about = "Print the JSON declaration sent to providers")]
+    Schema { name: String },
+    #[command(about = "Check tool declarations, and optionally arguments for one tool")]
+    Validate(ToolValidateArgs),
+    #[command(about = "Execute a tool locally, without any LLM")]
+    Run(ToolRunArgs),
+}
 
 #[derive(Debug, Args, Clone)]
-pub struct ToolsArgs {
-    #[arg(short = 'V', long = "version", action = clap::ArgAction::SetTrue)]
-    pub version: bool,
+pub struct ToolValidateArgs {
+    name: Option<String>,
+
+    /// JSON object of arguments to check against the tool's parameters
+    #[arg(long, requires = "name")]
+    args: Option<String>,
 }
 
 #[derive(Debug, Args, Clone)]
-pub struct ToolArgs {
-    #[arg(short = 'V', long = "version", action = clap::ArgAction::SetTrue)]
-    pub version: bool,
+pub struct ToolRunArgs {
+    name: String,
+
+    /// JSON object of arguments
+    #[arg(long, default_value = "{}")]
+    args: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ListedTool<'a> {
+    name: &'a str,
+    origin: &'static str,
+    description: &'a str,
+}
+
+pub async fn run(args: ToolsArgs) -> Result<(), String> {
+    let policy = ToolPolicy::from_config(&config::load_tool_policy()?)?;
+    let toolbox = load_toolbox(policy).await?;
+    let result = run_command(&toolbox, args.command).await;
+    toolbox.shutdown().await;
+    result
+}
+
+async fn run_command(toolbox: &Toolbox, command: ToolSubcommand) -> Result<(), String> {
+    match command {
+        ToolSubcommand::List { json } => run_list(toolbox, json),
+        ToolSubcommand::Describe { name } => run_describe(find_tool(toolbox, &name)?),
+        ToolSubcommand::Schema { name } => {
+            let schema = find_tool(toolbox, &name)?.definition.to_json();
+            let rendered = serde_json::to_string_pretty(&schema)
+                .map_err(|err| format!("Failed to serialize tool schema: {err}"))?;
+            println!("{rendered}");
+            Ok(())
+        }
+        ToolSubcommand::Validate(args) => run_validate(toolbox, args),
+        ToolSubcommand::Run(args) => {
+            let call = ToolCall {
+                id: "local".to_string(),
+                name: find_tool(toolbox, &args.name)?.name().to_string(),
+                args: parse_args(&args.args)?,
+            };
+            let output = toolbox
+                .call(&call)
+                .await
+                .map_err(|err| format!("Tool '{}' failed: {err}", call.name))?;
+            println!("{}", output.trim_end());
+            Ok(())
+        }
+    }
 }
 
+fn run_list(toolbox: &Toolbox, json: bool) -> Result<(), String> {
+    let entries = toolbox
+        .iter()
+        .map(|tool| ListedTool {
+            name: tool.name(),
+            origin: tool.origin.as_str(),
+            description: &tool.definition.function.description,
+        })
+        .collect::<Vec<_>>();
+
+    if json {
+        let rendered = serde_json::to_string(&entries)
+            .map_err(|err| format!("Failed to serialize tool list: {err}"))?;
+        println!("{rendered}");
+        return Ok(());
+    }
+
+    for entry in entries {
+        println!("{}\t{}\t{}", entry.name, entry.origin, entry.description);
+    }
+    Ok(())
+}
+
+fn run_describe(tool: &RegisteredTool) -> Result<(), String> {
+    let function = &tool.definition.function;
+    println!("{} ({})", function.name, tool.origin.as_str());
+    println!("{}", function.description);
+    if tool.requires_confirmation() {
+        println!("confirm: yes");
+    }
+    if tool.is_dangerous() {
+        println!("dangerous: yes");
+    }
+    if function.params.is_empty() {
+        println!("parameters: none");
+        return Ok(());
+    }
+    println!("parameters:");
+    for param in &function.params {
+        println!(
+            "  {}: {}{}{}",
+            param.name,
+            param.kind.as_str(),
+            if param.required { " (required)" } else { "" },
+            param
+                .description
+                .as_deref()
+                .map(|description| format!(" - {description}"))
+                .unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn run_validate(toolbox: &Toolbox, args: ToolValidateArgs) -> Result<(), String> {
+    let Some(name) = args.name else {
+        println!("tools OK: {} available", toolbox.iter().count());
+        return Ok(());
+    };
+
+    let tool = find_tool(toolbox, &name)?;
+    let Some(raw) = args.args else {
+        println!("tool OK: {}", tool.name());
+        return Ok(());
+    };
+
+    let argv = tool
+        .validate_args(&parse_args(&raw)?)
+        .map_err(|err| format!("Invalid arguments for tool '{name}': {err}"))?;
+    match argv {
+        Some(argv) => println!("args OK: {}", render_argv(&argv)),
+        None => println!("args OK"),
+    }
+    Ok(())
+}
+
+fn find_tool<'a>(toolbox: &'a Toolbox, name: &str) -> Result<&'a RegisteredTool, String> {
+    toolbox.get(name).ok_or_else(|| {
+        format!("Unknown tool '{name}'. Run `mpipe tool list` to see available tools.")
+    })
+}
+
+fn parse_args(raw: &str) -> Result<Value, String> {
+    let value: Value =
+        serde_json::from_str(raw).map_err(|err| format!("Invalid --args JSON: {err}"))?;
+    if !value.is_object() {
+        return Err("--args must be a JSON object.".to_string());
+    }
+    Ok(value)
+}
@@ -1,10 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 
+use crate::rchain::command_tools::CommandTemplate;
+use crate::rchain::embeddings::EmbeddingProvider;
+use crate::rchain::tool_policy::ApprovalMode;
+use crate::rchain::tools::ToolParamType;
+
 /// Default put None
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct ProfileConfig {
@@ -18,10 +23,15 @@ pub struct ProfileConfig {
     pub retry_delay: Option<u64>,
     pub output: Option<String>,
     pub show_usage: Option<bool>,
+    pub embedding_provider: Option<String>,
     pub embedding_model: Option<String>,
+    pub collection: Option<String>,
     pub chunk_size: Option<usize>,
     pub chunk_overlap: Option<usize>,
     pub chunk_strategy: Option<String>,
+    pub tokenizer: Option<String>,
+    pub semantic_threshold: Option<String>,
+    pub min_chunk_size: Option<usize>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -42,10 +52,84 @@ struct ProviderSectionConfig {
     defaults: Option<ProviderDefaultsConfig>,
 }
 
+/// Shell-command tool declared under `[tools.<name>]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolConfig {
+    pub description: String,
+    /// Argument template, e.g. `rg --json -- {{pattern}} {{path}}`.
+    pub command: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, ToolParamConfig>,
+    /// Wall-clock limit in seconds.
+    pub timeout: Option<u64>,
+    pub cwd: Option<PathBuf>,
+    pub max_output_bytes: Option<usize>,
+    /// Ask on the TTY before every execution.
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// One typed parameter of a configured tool.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolParamConfig {
+    #[serde(rename = "type", default = "default_tool_param_type")]
+    pub kind: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Stdio MCP server declared under `[mcp.<name>]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct McpServerConfig {
+    /// Program and arguments, e.g. `["npx", "-y", "@modelcontextprotocol/server-git"]`.
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    /// Per-request limit in seconds.
+    pub timeout: Option<u64>,
+    pub max_output_bytes: Option<usize>,
+}
+
+/// Safeguards for tool execution, `[tool_policy]`.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct ToolPolicyConfig {
+    /// `always`, `never` or `dangerous`.
+    pub approve: Option<String>,
+    /// Directory file tools are confined to (default: current directory).
+    pub workspace: Option<PathBuf>,
+    #[serde(default)]
+    pub allow_commands: Vec<String>,
+    #[serde(default)]
+    pub deny_commands: Vec<String>,
+    /// Wall-clock limit in seconds for every tool call.
+    pub timeout: Option<u64>,
+    pub max_output_bytes: Option<usize>,
+    #[serde(default)]
+    pub tools: BTreeMap<String, ToolLimitsConfig>,
+}
+
+/// Per-tool overrides, `[tool_policy.tools.<name>]`.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct ToolLimitsConfig {
+    pub timeout: Option<u64>,
+    pub max_output_bytes: Option<usize>,
+    /// Whether `approve = "dangerous"` asks before running this tool.
+    pub dangerous: Option<bool>,
+}
+
+fn default_tool_param_type() -> String {
+    "string".to_string()
+}
+
 #[derive(Debug, Deserialize, Default)]
 struct ConfigFile {
     profiles: Option<HashMap<String, ProfileConfig>>,
     providers: Option<HashMap<String, ProviderSectionConfig>>,
+    tools: Option<BTreeMap<String, ToolConfig>>,
+    mcp: Option<BTreeMap<String, McpServerConfig>>,
+    tool_policy: Option<ToolPolicyConfig>,
 }
 
 /// Load profile configuration from the default config file.
@@ -65,6 +149,36 @@ pub fn load_profile(name: &str) -> Result<ProfileConfig, String> {
     ))
 }
 
+/// Load `[tools.*]` declarations; a missing config file yields no tools.
+pub fn load_tools() -> Result<BTreeMap<String, ToolConfig>, String> {
+    if !config_path()?.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let (_, config) = load_and_validate_config_file()?;
+    Ok(config.tools.unwrap_or_default())
+}
+
+/// Load `[mcp.*]` server declarations; a missing config file yields none.
+pub fn load_mcp_servers() -> Result<BTreeMap<String, McpServerConfig>, String> {
+    if !config_path()?.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let (_, config) = load_and_validate_config_file()?;
+    Ok(config.mcp.unwrap_or_default())
+}
+
+/// Load the `[tool_policy]` section; a missing file or section yields defaults.
+pub fn load_tool_policy() -> Result<ToolPolicyConfig, String> {
+    if !config_path()?.exists() {
+        return Ok(ToolPolicyConfig::default());
+    }
+
+    let (_, config) = load_and_validate_config_file()?;
+    Ok(config.tool_policy.unwrap_or_default())
+}
+
 pub fn validate_config(profile_name: Option<&str>) -> Result<PathBuf, String> {
     let (path, config) = load_and_validate_config_file()?;
 
@@ -139,10 +253,15 @@ fn merge_provider_defaults(
         retry_delay: profile.retry_delay.or(defaults.retry_delay),
         output: profile.output.clone().or(defaults.output),
         show_usage: profile.show_usage.or(defaults.show_usage),
+        embedding_provider: profile.embedding_provider.clone(),
         embedding_model: profile.embedding_model.clone(),
+        collection: profile.collection.clone(),
         chunk_size: profile.chunk_size,
         chunk_overlap: profile.chunk_overlap,
         chunk_strategy: profile.chunk_strategy.clone(),
+        tokenizer: profile.tokenizer.clone(),
+        semantic_threshold: profile.semantic_threshold.clone(),
+        min_chunk_size: profile.min_chunk_size,
     }
 }
 
@@ -168,6 +287,163 @@ fn validate_config_file(config: &ConfigFile, path: &Path) -> Result<(), String>
         }
     }
 
+    if let Some(tools) = &config.tools {
+        for (name, tool) in tools {
+            validate_tool(path, name, tool)?;
+        }
+    }
+
+    if let Some(servers) = &config.mcp {
+        for (name, server) in servers {
+            validate_mcp_server(path, name, server)?;
+        }
+    }
+
+    if let Some(policy) = &config.tool_policy {
+        validate_tool_policy(path, policy)?;
+    }
+
+    Ok(())
+}
+
+fn validate_tool_policy(path: &Path, policy: &ToolPolicyConfig) -> Result<(), String> {
+    let invalid = |field: &str, reason: String| {
+        format!(
+            "Invalid value at 'tool_policy{field}' in config file '{}': {reason}",
+            path.display()
+        )
+    };
+
+    if let Some(approve) = &policy.approve
+        && ApprovalMode::from_str(approve).is_none()
+    {
+        return Err(invalid(
+            ".approve",
+            format!("'{approve}' (supported values: always, never, dangerous)."),
+        ));
+    }
+
+    let limits = std::iter::once((String::new(), policy.timeout, policy.max_output_bytes)).chain(
+        policy.tools.iter().map(|(name, limits)| {
+            (
+                format!(".tools.{name}"),
+                limits.timeout,
+                limits.max_output_bytes,
+            )
+        }),
+    );
+    for (section, timeout, max_output_bytes) in limits {
+        if timeout == Some(0) {
+            return Err(invalid(
+                &format!("{section}.timeout"),
+                "0 (must be > 0).".to_string(),
+            ));
+        }
+        if max_output_bytes == Some(0) {
+            return Err(invalid(
+                &format!("{section}.max_output_bytes"),
+                "0 (must be > 0).".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_mcp_server(path: &Path, name: &str, server: &McpServerConfig) -> Result<(), String> {
+    let invalid = |field: &str, reason: &str| {
+        format!(
+            "Invalid value at 'mcp.{name}{field}' in config file '{}': {reason}",
+            path.display()
+        )
+    };
+
+    if !is_valid_tool_name(name) {
+        return Err(invalid(
+            "",
+            "server names may only contain ASCII letters, digits, '_' and '-'.",
+        ));
+    }
+    if server
+        .command
+        .first()
+        .is_none_or(|program| program.trim().is_empty())
+    {
+        return Err(invalid(".command", "command must name a program."));
+    }
+    if server.timeout == Some(0) {
+        return Err(invalid(".timeout", "0 (must be > 0)."));
+    }
+    if server.max_output_bytes == Some(0) {
+        return Err(invalid(".max_output_bytes", "0 (must be > 0)."));
+    }
+
+    Ok(())
+}
+
+fn is_valid_tool_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn validate_tool(path: &Path, name: &str, tool: &ToolConfig) -> Result<(), String> {
+    let section_path = format!("tools.{name}");
+    let invalid = |field: &str, reason: String| {
+        format!(
+            "Invalid value at '{section_path}{field}' in config file '{}': {reason}",
+            path.display()
+        )
+    };
+
+    if !is_valid_tool_name(name) {
+        return Err(invalid(
+            "",
+            "tool names may only contain ASCII letters, digits, '_' and '-'.".to_string(),
+        ));
+    }
+
+    if tool.description.trim().is_empty() {
+        return Err(invalid(
+            ".description",
+            "description cannot be empty.".to_string(),
+        ));
+    }
+
+    let template = CommandTemplate::parse(&tool.command)
+        .map_err(|err| invalid(".command", format!("{err}.")))?;
+    for placeholder in template.placeholders() {
+        if !tool.params.contains_key(placeholder) {
+            return Err(invalid(
+                ".command",
+                format!(
+                    "placeholder '{{{{{placeholder}}}}}' has no matching [{section_path}.params.{placeholder}] entry."
+                ),
+            ));
+        }
+    }
+
+    for (param_name, param) in &tool.params {
+        if ToolParamType::from_str(&param.kind).is_none() {
+            return Err(invalid(
+                &format!(".params.{param_name}.type"),
+                format!(
+                    "'{}' (supported values: string, integer, number, boolean, array, object).",
+                    param.kind
+                ),
+            ));
+        }
+    }
+
+    if tool.timeout == Some(0) {
+        return Err(invalid(".timeout", "0 (must be > 0).".to_string()));
+    }
+
+    if tool.max_output_bytes == Some(0) {
+        return Err(invalid(".max_output_bytes", "0 (must be > 0).".to_string()));
+    }
+
     Ok(())
 }
 
@@ -182,6 +458,16 @@ fn validate_profile(path: &Path, name: &str, profile: &ProfileConfig) -> Result<
         }
     }
 
+    if let Some(provider_raw) = &profile.embedding_provider
+        && EmbeddingProvider::from_str(provider_raw).is_none()
+    {
+        return Err(format!(
+            "Invalid profile embedding provider '{}'. Supported values: openai, fireworks, local. (at 'profiles.{name}.embedding_provider' in '{}')",
+            provider_raw.trim().to_ascii_lowercase(),
+            path.display()
+        ));
+    }
+
     validate_profile_fields(path, &format!("profiles.{name}"), profile)
 }
 
@@ -486,4 +772,50 @@ mod tests {
 
         assert!(err.contains("Profile 'missing' not found"));
     }
+
+    #[test]
+    fn validate_config_rejects_undeclared_tool_placeholder() {
+        let config_path = unique_temp_path("tool-undeclared-placeholder");
+        fs::write(
+            &config_path,
+            "[tools.search]\ndescription = \"Search files\"\ncommand = \"rg --json -- {{pattern}} {{path}}\"\n\n[tools.search.params.pattern]\ntype = \"string\"\nrequired = true\n",
+        )
+        .expect("config should be writable");
+
+        let err = {
+            unsafe {
+                env::set_var("MP_CONFIG", &config_path);
+            }
+            let result = validate_config(None).expect_err("undeclared placeholder should fail");
+            unsafe {
+                env::remove_var("MP_CONFIG");
+            }
+            result
+        };
+
+        assert!(err.contains("tools.search.command"));
+        assert!(err.contains("{{path}}"));
+    }
+
+    #[test]
+    fn validate_config_rejects_mcp_server_without_command() {
+        let config: ConfigFile =
+            toml::from_str("[mcp.files]\ncommand = []\n").expect("config should parse");
+
+        let err = validate_config_file(&config, Path::new("config.toml"))
+            .expect_err("empty MCP command should fail");
+
+        assert!(err.contains("mcp.files.command"));
+    }
+
+    #[test]
+    fn validate_config_rejects_unknown_approval_mode() {
+        let config: ConfigFile = toml::from_str("[tool_policy]\napprove = \"sometimes\"\n")
+            .expect("config should parse");
+
+        let err = validate_config_file(&config, Path::new("config.toml"))
+            .expect_err("unknown approval mode should fail");
+
+        assert!(err.contains("tool_policy.approve"));
+    }
 }
@@ -4,19 +4,18 @@ use std::process;
 use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::{generate, shells};
 
-use owo_colors::OwoColorize;
-
-use mpipe::commands::ask::{self, AskArgs};
 use mpipe::commands::agent::{self, AgentArgs};
+use mpipe::commands::ask::{self, AskArgs};
 use mpipe::commands::config::{self, ConfigArgs};
 use mpipe::commands::download::{self, DownloadArgs};
 use mpipe::commands::embed::{self, EmbedArgs};
 use mpipe::commands::grep::{self, GrepArgs};
 use mpipe::commands::index::{self, IndexArgs};
 use mpipe::commands::list::{self, ListArgs};
+use mpipe::commands::memory::{self, MemoryArgs};
 use mpipe::commands::models::{self, ModelsArgs};
 use mpipe::commands::prompt::{self, PromptArgs};
-use mpipe::commands::tools::{self, ToolArgs};
+use mpipe::commands::tools::{self, ToolsArgs};
 
 const ROOT_HELP_EXAMPLES: &str = "Examples:\n\
   mpipe ask --provider fireworks --model accounts/fireworks/models/kimi-k2-instruct-0905 \"2+2?\"\n\
@@ -51,7 +50,7 @@ enum Commands {
     #[command(about = "List known models")]
     Models(ModelsArgs),
     #[command(about = "Index documents into ChromaDB")]
-    Index(IndexArgs),
+    Index(Box<IndexArgs>),
     #[command(about = "Retrieve and answer from ChromaDB (classic RAG)")]
     Grep(Box<GrepArgs>),
     #[command(about = "List entries in a ChromaDB collection")]
@@ -71,6 +70,8 @@ enum Commands {
     },
     #[command(about = "Tool management")]
     Tool(ToolsArgs),
+    #[command(about = "Inspect and prune long-term agent memory")]
+    Memory(MemoryArgs),
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -97,13 +98,15 @@ async fn main() {
         Commands::Ask(args) => ask::run(*args).await,
         Commands::Agent(args) => agent::run(*args).await,
         Commands::Models(args) => models::run(args),
-        Commands::Index(args) => index::run(args).await,
+        Commands::Index(args) => index::run(*args).await,
         Commands::Grep(args) => grep::run(*args).await,
         Commands::List(args) => list::run(args).await,
         Commands::Prompt(args) => prompt::run(args),
-        Commands::Embed(args) => embed::run(args),
+        Commands::Embed(args) => embed::run(args).await,
         Commands::Download(args) => download::run(args),
         Commands::Config(args) => config::run(args),
+        Commands::Tool(args) => tools::run(args).await,
+        Commands::Memory(args) => memory::run(args).await,
         Commands::Completion { shell } => {
             print_completion(shell);
             Ok(())
@@ -204,22 +204,8 @@ impl ChatMessage {
 }
 
 fn parse_tool_calls(message: &Value) -> Vec<ToolCall> {
-    let mut tool_calls = Vec::new();
-    if let Some(calls) = message["tool_calls"].as_array() {
-        for call in calls {
-            let id = call["id"].as_str().unwrap_or("").to_string();
-            let name = call["function"]["name"].as_str().unwrap_or("").to_string();
-            let arguments = &call["function"]["arguments"];
-            let args = match arguments {
-                Value::String(raw) => {
-                    serde_json::from_str(raw).unwrap_or(Value::String(raw.clone()))
-                }
-                other => other.clone(),
-            };
-            if !name.is_empty() {
-                tool_calls.push(ToolCall { id, name, args });
-            }
-        }
-    }
-    tool_calls
+    message["tool_calls"]
+        .as_array()
+        .map(|calls| calls.iter().filter_map(ToolCall::from_json).collect())
+        .unwrap_or_default()
 }
@@ -0,0 +1,905 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::rchain::code_chunking::{CodeChunker, CodeLocation};
+use crate::rchain::semantic_chunking::SemanticOptions;
+use crate::rchain::tokenizer::Tokenizer;
+
+/// How a text is cut into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStrategy {
+    /// Consecutive paragraphs (separated by blank lines).
+    Paragraph,
+    /// Consecutive sentences.
+    Sentence,
+    /// Token windows; the size counts tokens of `ChunkOptions::tokenizer`.
+    Token,
+    /// Fixed-size character windows, cut back to the last whitespace.
+    Character,
+    /// Markdown sections, split at headings and merged up to the size.
+    Markdown,
+    /// Source code, split at top-level items; the language comes from the
+    /// file extension.
+    Code,
+    /// Sentences grouped by topic, cut where the embeddings of neighbouring
+    /// sentences drift apart.
+    Semantic,
+}
+
+impl ChunkStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Paragraph => "paragraph",
+            Self::Sentence => "sentence",
+            Self::Token => "token",
+            Self::Character => "character",
+            Self::Markdown => "markdown",
+            Self::Code => "code",
+            Self::Semantic => "semantic",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "paragraph" => Some(Self::Paragraph),
+            "sentence" => Some(Self::Sentence),
+            "token" => Some(Self::Token),
+            "character" => Some(Self::Character),
+            "markdown" => Some(Self::Markdown),
+            "code" => Some(Self::Code),
+            "semantic" => Some(Self::Semantic),
+            _ => None,
+        }
+    }
+}
+
+/// One piece of a chunked text, with its position in the source.
+///
+/// `text` is the exact source slice `byte_start..byte_end`; the `char_*`
+/// offsets count Unicode scalar values. `heading_path` is the breadcrumb
+/// of Markdown headings the chunk sits under (`Install > Linux`), set by
+/// the markdown chunker only; `code` is set by the code chunker only.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TextChunk {
+    pub text: String,
+    pub char_start: usize,
+    pub char_end: usize,
+    pub byte_start: usize,
+    pub byte_end: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heading_path: Option<String>,
+    #[serde(flatten)]
+    pub code: Option<CodeLocation>,
+}
+
+/// Splits a text into chunks, in source order.
+pub trait Chunker {
+    fn chunk(&self, text: &str) -> Vec<TextChunk>;
+}
+
+/// Strategy, size and overlap of a chunker.
+///
+/// `chunk_size` is in characters (tokens for [`ChunkStrategy::Token`]).
+/// `overlap_percent` of it is repeated at the start of the next chunk.
+/// `markdown` only overlaps inside sections too large for one chunk, and
+/// `code` and `semantic` never do, as they cut at structural or topic
+/// boundaries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkOptions {
+    pub strategy: ChunkStrategy,
+    pub chunk_size: usize,
+    pub overlap_percent: usize,
+    /// `tokenizer.json` or tiktoken file counting tokens for the `token`
+    /// strategy; tokens are estimated as characters / 4 without one.
+    pub tokenizer: Option<PathBuf>,
+    /// Breakpoint threshold and minimum size of the `semantic` strategy.
+    pub semantic: SemanticOptions,
+}
+
+impl ChunkOptions {
+    /// Characters of overlap between consecutive chunks.
+    pub fn overlap_chars(&self) -> usize {
+        self.chunk_size * self.overlap_percent / 100
+    }
+
+    /// The chunker of this strategy. `source` is the path of the text,
+    /// which the `code` strategy needs to detect the language. The
+    /// `semantic` strategy has no chunker, as it needs an embeddings client:
+    /// see [`semantic_chunks`](crate::rchain::semantic_chunking::semantic_chunks).
+    pub fn chunker(&self, source: Option<&Path>) -> Result<Box<dyn Chunker + Send + Sync>, String> {
+        Ok(match self.strategy {
+            ChunkStrategy::Paragraph => Box::new(ParagraphChunker(self.clone())),
+            ChunkStrategy::Sentence => Box::new(SentenceChunker(self.clone())),
+            ChunkStrategy::Token => Box::new(TokenChunker::new(self.clone())?),
+            ChunkStrategy::Character => Box::new(CharacterChunker(self.clone())),
+            ChunkStrategy::Markdown => Box::new(MarkdownChunker(self.clone())),
+            ChunkStrategy::Code => {
+                let path = source.ok_or_else(|| {
+                    "The code chunk strategy needs a file name to detect the language.".to_string()
+                })?;
+                Box::new(CodeChunker::for_path(self.clone(), path)?)
+            }
+            ChunkStrategy::Semantic => {
+                return Err(
+                    "The semantic chunk strategy embeds while chunking; use semantic_chunks."
+                        .to_string(),
+                );
+            }
+        })
+    }
+}
+
+/// Splits `text`, read from `source` if known, with the chunker of `options`.
+pub fn chunk_text(
+    text: &str,
+    options: &ChunkOptions,
+    source: Option<&Path>,
+) -> Result<Vec<TextChunk>, String> {
+    Ok(options.chunker(source)?.chunk(text))
+}
+
+/// Packs paragraphs; chunks start with as many trailing paragraphs of the
+/// previous chunk as fit in the overlap.
+#[derive(Debug, Clone)]
+pub struct ParagraphChunker(pub ChunkOptions);
+
+impl Chunker for ParagraphChunker {
+    fn chunk(&self, text: &str) -> Vec<TextChunk> {
+        let mut paragraphs = Vec::new();
+        let mut start = 0;
+        for (separator, _) in text.match_indices("\n\n").chain([(text.len(), "")]) {
+            if separator < start {
+                continue;
+            }
+            if let Some(span) = trimmed_span(text, start..separator) {
+                paragraphs.push(span);
+            }
+            start = separator + 2;
+        }
+        pack_spans(text, &paragraphs, &self.0)
+    }
+}
+
+/// Packs sentences, found with Unicode sentence boundaries; chunks start
+/// with as many trailing sentences of the previous chunk as fit in the
+/// overlap.
+#[derive(Debug, Clone)]
+pub struct SentenceChunker(pub ChunkOptions);
+
+impl Chunker for SentenceChunker {
+    fn chunk(&self, text: &str) -> Vec<TextChunk> {
+        pack_spans(text, &sentence_spans(text), &self.0)
+    }
+}
+
+/// Words ending in a period that do not end a sentence, lowercased.
+/// Single letters followed by a period (`M.`, `J.`) are handled as
+/// initials, see [`continues_sentence`].
+const ABBREVIATIONS: &[&str] = &[
+    // English
+    "e.g.", "i.e.", "cf.", "vs.", "mr.", "mrs.", "ms.", "dr.", "prof.", "st.", "fig.", "approx.",
+    // French
+    "mm.", "mme.", "mmes.", "mlle.", "mlles.", "env.", "p.ex.", "c.-à-d.", // German
+    "z.b.", "d.h.", "u.a.", "bzw.", "vgl.", "ca.", "evtl.", "ggf.", "str.", "sog.",
+];
+
+/// Titles that are also common words ("Call me.", "said no."), matched
+/// case-sensitively and only before a capitalized name.
+const NAME_TITLES: &[&str] = &["Me.", "Fr.", "Hr."];
+
+/// Like [`NAME_TITLES`], before a number: `No. 5`, `Nr. 3`.
+const NUMBER_TITLES: &[&str] = &["No.", "Nr."];
+
+/// Sentences of `text` per the Unicode sentence boundaries of UAX #29,
+/// trimmed, with breaks after abbreviations (`Dr.`, `e.g.`, `M.`) undone.
+pub(crate) fn sentence_spans(text: &str) -> Vec<Range<usize>> {
+    let mut sentences: Vec<Range<usize>> = Vec::new();
+    for (start, sentence) in text.split_sentence_bound_indices() {
+        let Some(span) = trimmed_span(text, start..start + sentence.len()) else {
+            continue;
+        };
+        match sentences.last_mut() {
+            Some(last) if continues_sentence(&text[last.clone()], &text[span.clone()]) => {
+                last.end = span.end
+            }
+            _ => sentences.push(span),
+        }
+    }
+    sentences
+}
+
+/// Whether `sentence` ends with an abbreviation rather than a sentence
+/// end, given the `next` sentence found by UAX #29.
+fn continues_sentence(sentence: &str, next: &str) -> bool {
+    let mut words = sentence
+        .split_whitespace()
+        .rev()
+        .map(|word| word.trim_start_matches(|c: char| !c.is_alphanumeric()));
+    let Some(last) = words.next() else {
+        return false;
+    };
+    if !last.ends_with('.') {
+        return false;
+    }
+    let previous = words.next();
+    let next_word = next
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(|c: char| !c.is_alphanumeric());
+    let next_char = next_word.chars().next();
+    let capitalized = next_char.is_some_and(char::is_uppercase);
+
+    let lowercase = last.to_lowercase();
+    if ABBREVIATIONS.contains(&lowercase.as_str()) {
+        return true;
+    }
+    // Abbreviations written with a space: `z. B.`, `d. h.`.
+    if let Some(previous) = previous
+        && is_initial(previous)
+        && ABBREVIATIONS.contains(&format!("{previous}{last}").to_lowercase().as_str())
+    {
+        return true;
+    }
+    if NAME_TITLES.contains(&last) {
+        return capitalized;
+    }
+    if NUMBER_TITLES.contains(&last) {
+        return next_char.is_some_and(|c| c.is_ascii_digit());
+    }
+    // An initial starts a sentence or follows a title or another initial
+    // (`M. Dupont`, `Dr. J. Smith`), or precedes one (`J. R. R. Tolkien`);
+    // "Plan B." and "vitamin C." end their sentence.
+    is_initial(last)
+        && capitalized
+        && (is_initial(next_word) || previous.is_none_or(|word| word.ends_with('.')))
+}
+
+fn is_initial(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(c), Some('.'), None) if c.is_alphabetic()
+    )
+}
+
+/// Windows of at most `chunk_size` tokens, counted by a real tokenizer
+/// (or estimated, see [`Tokenizer::Estimate`]). Windows end at a word
+/// boundary when one is available, and the next one starts `overlap_percent`
+/// of `chunk_size` tokens earlier, at the start of a word.
+pub struct TokenChunker {
+    options: ChunkOptions,
+    tokenizer: Tokenizer,
+}
+
+impl TokenChunker {
+    /// A chunker counting tokens with the tokenizer file of `options`, or
+    /// estimating them without one.
+    pub fn new(options: ChunkOptions) -> Result<Self, String> {
+        let tokenizer = match &options.tokenizer {
+            Some(path) => Tokenizer::load(path)?,
+            None => Tokenizer::Estimate,
+        };
+        Ok(Self { options, tokenizer })
+    }
+}
+
+impl Chunker for TokenChunker {
+    fn chunk(&self, text: &str) -> Vec<TextChunk> {
+        let tokens = self.tokenizer.token_spans(text);
+        let offsets = CharOffsets::new(text);
+        let chunk_size = self.options.chunk_size.max(1);
+        let overlap = chunk_size * self.options.overlap_percent / 100;
+        // A window may start or end before token `i` when whitespace
+        // separates it from the previous token.
+        let word_start = |i: usize| {
+            i == 0
+                || i == tokens.len()
+                || text[tokens[i - 1].0.end..tokens[i].0.start]
+                    .chars()
+                    .chain(text[tokens[i].0.clone()].chars().take(1))
+                    .any(char::is_whitespace)
+        };
+
+        let mut chunks = Vec::new();
+        let is_blank = |i: usize| text[tokens[i].0.clone()].trim().is_empty();
+        let mut start = 0;
+        loop {
+            // Whitespace-only tokens would only pad the window.
+            while start < tokens.len() && is_blank(start) {
+                start += 1;
+            }
+            if start == tokens.len() {
+                break;
+            }
+            let mut end = start;
+            let mut used = 0;
+            while end < tokens.len() && (end == start || used + tokens[end].1 <= chunk_size) {
+                used += tokens[end].1;
+                end += 1;
+            }
+            if end < tokens.len()
+                && let Some(boundary) = (start + 1..=end).rev().find(|&i| word_start(i))
+            {
+                end = boundary;
+            }
+
+            let bytes = tokens[start].0.start..tokens[end - 1].0.end;
+            if let Some(bytes) = trimmed_span(text, bytes) {
+                chunks.push(offsets.chunk(text, bytes));
+            }
+            if end == tokens.len() {
+                break;
+            }
+
+            let mut next = end;
+            let mut carried = 0;
+            while next > start + 1 && carried + tokens[next - 1].1 <= overlap {
+                carried += tokens[next - 1].1;
+                next -= 1;
+            }
+            while next < end && !word_start(next) {
+                next += 1;
+            }
+            start = next;
+        }
+        chunks
+    }
+}
+
+/// Windows of `chunk_size` characters, each cut back to the last
+/// whitespace when one is available; the next window starts the overlap
+/// before the end of the previous one and ends past it, running to the end
+/// of a word when the overlap leaves no room for one.
+#[derive(Debug, Clone)]
+pub struct CharacterChunker(pub ChunkOptions);
+
+impl Chunker for CharacterChunker {
+    fn chunk(&self, text: &str) -> Vec<TextChunk> {
+        if text.trim().is_empty() {
+            return Vec::new();
+        }
+
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let byte_at = |index: usize| chars.get(index).map_or(text.len(), |(byte, _)| *byte);
+        let len = chars.len();
+        let chunk_size = self.0.chunk_size.max(1);
+        let overlap = self.0.overlap_chars();
+        let mut chunks = Vec::new();
+        let mut start = chars.iter().take_while(|(_, c)| c.is_whitespace()).count();
+        let mut previous_end = start;
+
+        while start < len {
+            // Every window ends past the previous one, so none repeats text
+            // the previous window already holds.
+            let limit = (start + chunk_size).max(previous_end + 1).min(len);
+            let mut end = limit;
+            if limit < len {
+                let floor = (previous_end + 1).max(start + 1);
+                let word_start = (start + 1..limit)
+                    .rev()
+                    .find(|&idx| chars[idx - 1].1.is_whitespace())
+                    .unwrap_or(start);
+                let word_end = (limit..len)
+                    .find(|&idx| chars[idx].1.is_whitespace())
+                    .unwrap_or(len);
+                if let Some(split_index) = (floor..limit)
+                    .rev()
+                    .find(|&idx| chars[idx].1.is_whitespace())
+                {
+                    end = split_index;
+                } else if word_end - word_start <= chunk_size {
+                    // The window only holds the overlap: finish the word
+                    // it cuts rather than repeat the previous window.
+                    end = word_end;
+                }
+            }
+
+            let bytes = byte_at(start)..byte_at(end);
+            chunks.push(TextChunk {
+                text: text[bytes.clone()].to_string(),
+                char_start: start,
+                char_end: end,
+                byte_start: bytes.start,
+                byte_end: bytes.end,
+                heading_path: None,
+                code: None,
+            });
+
+            if end == len {
+                break;
+            }
+            previous_end = end;
+
+            // Step back by the overlap, to the start of the word it lands
+            // in, or forward to the next word when that word starts the
+            // window; a window never starts mid-word or on whitespace.
+            let mut next_start = end.saturating_sub(overlap).max(start + 1);
+            if overlap > 0
+                && !chars[next_start].1.is_whitespace()
+                && let Some(word_start) = (start + 1..=next_start)
+                    .rev()
+                    .find(|&idx| chars[idx - 1].1.is_whitespace())
+            {
+                next_start = word_start;
+            }
+            while next_start < end && !chars[next_start - 1].1.is_whitespace() {
+                next_start += 1;
+            }
+            while next_start < len && chars[next_start].1.is_whitespace() {
+                next_start += 1;
+            }
+            start = next_start;
+        }
+
+        chunks
+    }
+}
+
+/// Splits Markdown at headings and packs whole sections up to
+/// `chunk_size`. Each chunk carries the heading path it sits under.
+///
+/// Fenced code blocks and tables are never cut: a section larger than
+/// `chunk_size` is packed from its blocks (paragraphs, fences, tables) like
+/// [`ParagraphChunker`] does, and a single oversized block stays whole.
+#[derive(Debug, Clone)]
+pub struct MarkdownChunker(pub ChunkOptions);
+
+/// A heading and the blocks up to the next heading.
+struct MarkdownSection {
+    path: Vec<String>,
+    blocks: Vec<Range<usize>>,
+}
+
+impl Chunker for MarkdownChunker {
+    fn chunk(&self, text: &str) -> Vec<TextChunk> {
+        let sections = markdown_sections(text);
+        let offsets = CharOffsets::new(text);
+        let chars = |bytes: Range<usize>| offsets.char_at(bytes.end) - offsets.char_at(bytes.start);
+        let span = |sections: &[MarkdownSection]| {
+            sections[0].blocks[0].start..sections[sections.len() - 1].blocks.last().unwrap().end
+        };
+
+        let mut chunks = Vec::new();
+        let flush = |group: &[MarkdownSection], chunks: &mut Vec<TextChunk>| {
+            if group.is_empty() {
+                return;
+            }
+            let mut chunk = offsets.chunk(text, span(group));
+            chunk.heading_path = common_heading_path(group);
+            chunks.push(chunk);
+        };
+
+        let mut group_start = 0;
+        for (i, section) in sections.iter().enumerate() {
+            let section_span = span(std::slice::from_ref(section));
+            if chars(section_span) > self.0.chunk_size {
+                flush(&sections[group_start..i], &mut chunks);
+                let breadcrumb = heading_path(&section.path);
+                for mut chunk in pack_spans(text, &section.blocks, &self.0) {
+                    chunk.heading_path = breadcrumb.clone();
+                    chunks.push(chunk);
+                }
+                group_start = i + 1;
+            } else if i > group_start && chars(span(&sections[group_start..=i])) > self.0.chunk_size
+            {
+                flush(&sections[group_start..i], &mut chunks);
+                group_start = i;
+            }
+        }
+        flush(&sections[group_start..], &mut chunks);
+        chunks
+    }
+}
+
+/// The breadcrumb shared by all sections of a chunk.
+fn common_heading_path(sections: &[MarkdownSection]) -> Option<String> {
+    let mut common = sections[0].path.as_slice();
+    for section in &sections[1..] {
+        let shared = common
+            .iter()
+            .zip(&section.path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        common = &common[..shared];
+    }
+    heading_path(common)
+}
+
+fn heading_path(headings: &[String]) -> Option<String> {
+    (!headings.is_empty()).then(|| headings.join(" > "))
+}
+
+/// Cuts Markdown into heading sections made of blocks. Blocks end at blank
+/// lines and headings, except inside fenced code blocks; sections without
+/// any non-blank block are dropped.
+fn markdown_sections(text: &str) -> Vec<MarkdownSection> {
+    let mut sections = vec![MarkdownSection {
+        path: Vec::new(),
+        blocks: Vec::new(),
+    }];
+    let mut levels: Vec<(usize, String)> = Vec::new();
+    let mut fence: Option<(char, usize)> = None;
+    let mut block_start: Option<usize> = None;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let content = line.trim_end_matches(['\n', '\r']);
+
+        if let Some((marker, len)) = fence {
+            if fence_marker(content)
+                .is_some_and(|(m, l, info)| m == marker && l >= len && info.is_empty())
+            {
+                fence = None;
+            }
+            continue;
+        }
+        if let Some((marker, len, _)) = fence_marker(content) {
+            fence = Some((marker, len));
+            block_start.get_or_insert(line_start);
+            continue;
+        }
+
+        let current = sections.last_mut().expect("at least one section");
+        if content.trim().is_empty() {
+            if let Some(start) = block_start.take() {
+                current.blocks.extend(trimmed_span(text, start..line_start));
+            }
+        } else if let Some((level, title)) = atx_heading(content) {
+            if let Some(start) = block_start.take() {
+                current.blocks.extend(trimmed_span(text, start..line_start));
+            }
+            levels.retain(|(existing, _)| *existing < level);
+            levels.push((level, title.to_string()));
+            sections.push(MarkdownSection {
+                path: levels.iter().map(|(_, title)| title.clone()).collect(),
+                blocks: trimmed_span(text, line_start..offset).into_iter().collect(),
+            });
+        } else {
+            block_start.get_or_insert(line_start);
+        }
+    }
+    if let Some(start) = block_start {
+        let current = sections.last_mut().expect("at least one section");
+        current.blocks.extend(trimmed_span(text, start..text.len()));
+    }
+
+    sections.retain(|section| !section.blocks.is_empty());
+    sections
+}
+
+/// `(level, title)` of an ATX heading line (`## Title ##`).
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = strip_indent(line)?;
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &trimmed[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let title = rest.trim();
+    let title = match title.trim_end_matches('#') {
+        stripped if stripped.is_empty() || stripped.ends_with([' ', '\t']) => stripped.trim_end(),
+        _ => title,
+    };
+    Some((level, title))
+}
+
+/// `(marker, length, info string)` of a code fence line (```` ```rust ````).
+fn fence_marker(line: &str) -> Option<(char, usize, &str)> {
+    let trimmed = strip_indent(line)?;
+    let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = trimmed.chars().take_while(|&c| c == marker).count();
+    (len >= 3).then(|| (marker, len, trimmed[len..].trim()))
+}
+
+/// `line` without up to three leading spaces; `None` when indented more,
+/// which makes it an indented code line rather than a heading or fence.
+fn strip_indent(line: &str) -> Option<&str> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    (indent <= 3).then(|| &line[indent..])
+}
+
+/// Maps byte offsets of a text to char offsets.
+pub(crate) struct CharOffsets {
+    boundaries: Vec<usize>,
+}
+
+impl CharOffsets {
+    pub(crate) fn new(text: &str) -> Self {
+        Self {
+            boundaries: text.char_indices().map(|(byte, _)| byte).collect(),
+        }
+    }
+
+    pub(crate) fn char_at(&self, byte: usize) -> usize {
+        self.boundaries.partition_point(|&boundary| boundary < byte)
+    }
+
+    pub(crate) fn chunk(&self, text: &str, bytes: Range<usize>) -> TextChunk {
+        TextChunk {
+            text: text[bytes.clone()].to_string(),
+            char_start: self.char_at(bytes.start),
+            char_end: self.char_at(bytes.end),
+            byte_start: bytes.start,
+            byte_end: bytes.end,
+            heading_path: None,
+            code: None,
+        }
+    }
+}
+
+/// The part of `text[bytes]` without surrounding whitespace, if any.
+fn trimmed_span(text: &str, bytes: Range<usize>) -> Option<Range<usize>> {
+    let slice = &text[bytes.clone()];
+    let trimmed = slice.trim_start();
+    let start = bytes.start + (slice.len() - trimmed.len());
+    let end = start + trimmed.trim_end().len();
+    (start < end).then_some(start..end)
+}
+
+/// Groups consecutive units (paragraphs or sentences) into chunks of at
+/// most `chunk_size` characters, each chunk being the source slice from its
+/// first unit to its last. A unit longer than `chunk_size` is a chunk of
+/// its own.
+fn pack_spans(text: &str, spans: &[Range<usize>], options: &ChunkOptions) -> Vec<TextChunk> {
+    let offsets = CharOffsets::new(text);
+    let chars = |bytes: Range<usize>| offsets.char_at(bytes.end) - offsets.char_at(bytes.start);
+    let chunk_size = options.chunk_size;
+    let overlap_chars = options.overlap_chars();
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    for i in 0..spans.len() {
+        if i > start && chars(spans[start].start..spans[i].end) > chunk_size {
+            chunks.push(offsets.chunk(text, spans[start].start..spans[i - 1].end));
+            // Carry trailing units of the flushed chunk, never all of them,
+            // as long as they fit the overlap and leave room for unit `i`.
+            let mut next = i;
+            while next > start + 1
+                && chars(spans[next - 1].start..spans[i - 1].end) <= overlap_chars
+                && chars(spans[next - 1].start..spans[i].end) <= chunk_size
+            {
+                next -= 1;
+            }
+            start = next;
+        }
+    }
+    if let Some(last) = spans.last() {
+        chunks.push(offsets.chunk(text, spans[start].start..last.end));
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(strategy: ChunkStrategy, chunk_size: usize, overlap_percent: usize) -> ChunkOptions {
+        ChunkOptions {
+            strategy,
+            chunk_size,
+            overlap_percent,
+            tokenizer: None,
+            semantic: SemanticOptions::DEFAULT,
+        }
+    }
+
+    fn split(text: &str, options: &ChunkOptions) -> Vec<TextChunk> {
+        chunk_text(text, options, None).expect("text strategies need no source")
+    }
+
+    fn texts(chunks: &[TextChunk]) -> Vec<&str> {
+        chunks.iter().map(|chunk| chunk.text.as_str()).collect()
+    }
+
+    #[test]
+    fn paragraph_chunks_carry_trailing_paragraphs_as_overlap() {
+        let text = "aaaa\n\nbbbb\n\ncccc\n\ndddd";
+        assert_eq!(
+            texts(&split(text, &options(ChunkStrategy::Paragraph, 10, 50))),
+            ["aaaa\n\nbbbb", "bbbb\n\ncccc", "cccc\n\ndddd"]
+        );
+        assert_eq!(
+            texts(&split(text, &options(ChunkStrategy::Paragraph, 10, 0))),
+            ["aaaa\n\nbbbb", "cccc\n\ndddd"]
+        );
+        // A paragraph larger than the overlap is not carried.
+        assert_eq!(
+            texts(&split(
+                "aaaaaa\n\nbbbbbb\n\ncc",
+                &options(ChunkStrategy::Paragraph, 10, 50)
+            )),
+            ["aaaaaa", "bbbbbb\n\ncc"]
+        );
+    }
+
+    #[test]
+    fn sentence_chunks_report_char_and_byte_offsets() {
+        let text = "héllo wörld. Ça va? Oui.";
+        let chunks = split(text, &options(ChunkStrategy::Sentence, 12, 0));
+        assert_eq!(
+            chunks,
+            vec![
+                TextChunk {
+                    text: "héllo wörld.".to_string(),
+                    char_start: 0,
+                    char_end: 12,
+                    byte_start: 0,
+                    byte_end: 14,
+                    heading_path: None,
+                    code: None,
+                },
+                TextChunk {
+                    text: "Ça va? Oui.".to_string(),
+                    char_start: 13,
+                    char_end: 24,
+                    byte_start: 15,
+                    byte_end: 27,
+                    heading_path: None,
+                    code: None,
+                },
+            ]
+        );
+        for chunk in &chunks {
+            assert_eq!(&text[chunk.byte_start..chunk.byte_end], chunk.text);
+        }
+
+        let overlapping = split("One. Two. Six.", &options(ChunkStrategy::Sentence, 9, 50));
+        assert_eq!(texts(&overlapping), ["One. Two.", "Two. Six."]);
+    }
+
+    #[test]
+    fn every_strategy_overlaps_by_a_percentage_of_the_size() {
+        let text = "alpha beta gamma delta epsilon";
+
+        // Estimated tokens: alph|a beta gamm|a delt|a epsi|lon.
+        let words = split(text, &options(ChunkStrategy::Token, 4, 50));
+        assert_eq!(
+            texts(&words),
+            ["alpha beta", "beta gamma", "gamma delta", "delta epsilon"]
+        );
+
+        let windows = split(text, &options(ChunkStrategy::Character, 12, 50));
+        assert_eq!(
+            texts(&windows),
+            ["alpha beta", "beta gamma", "gamma delta", "delta epsilon"]
+        );
+        assert_eq!((windows[1].char_start, windows[1].char_end), (6, 16));
+        assert_eq!(windows[1].byte_start, 6);
+
+        let long = "lorem ipsum dolor sit amet consectetur adipiscing elit sed do".repeat(3);
+        let windows = split(&long, &options(ChunkStrategy::Character, 20, 30));
+        for pair in windows.windows(2) {
+            assert!(pair[1].char_end > pair[0].char_end, "{pair:?}");
+            assert!(pair[1].char_start < pair[0].char_end, "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn markdown_chunks_merge_small_sections_under_their_common_heading() {
+        let text = "# Install\n\nIntro.\n\n## Linux\n\nUse apt.\n\n### Troubleshooting\n\nRetry.\n\n## macOS ##\n\nUse brew.\n";
+        let chunks = split(text, &options(ChunkStrategy::Markdown, 40, 0));
+        assert_eq!(
+            texts(&chunks),
+            [
+                "# Install\n\nIntro.\n\n## Linux\n\nUse apt.",
+                "### Troubleshooting\n\nRetry.",
+                "## macOS ##\n\nUse brew."
+            ]
+        );
+        let paths: Vec<_> = chunks.iter().map(|c| c.heading_path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            [
+                Some("Install"),
+                Some("Install > Linux > Troubleshooting"),
+                Some("Install > macOS")
+            ]
+        );
+        for chunk in &chunks {
+            assert_eq!(&text[chunk.byte_start..chunk.byte_end], chunk.text);
+        }
+    }
+
+    #[test]
+    fn markdown_chunks_never_cut_code_fences_or_tables() {
+        let fence = "```sh\n# not a heading\n\nmake install\n```";
+        let table = "| os | cmd |\n|----|-----|\n| linux | apt |";
+        let text = format!("# Build\n\n{fence}\n\n{table}\n\nDone.");
+        let chunks = split(&text, &options(ChunkStrategy::Markdown, 20, 0));
+        assert_eq!(texts(&chunks), ["# Build", fence, table, "Done."]);
+        assert!(
+            chunks
+                .iter()
+                .all(|chunk| chunk.heading_path.as_deref() == Some("Build"))
+        );
+
+        let plain = split(
+            "No headings here.",
+            &options(ChunkStrategy::Markdown, 100, 0),
+        );
+        assert_eq!(texts(&plain), ["No headings here."]);
+        assert_eq!(plain[0].heading_path, None);
+    }
+
+    fn sentences(text: &str) -> Vec<&str> {
+        sentence_spans(text)
+            .into_iter()
+            .map(|span| &text[span])
+            .collect()
+    }
+
+    #[test]
+    fn sentences_follow_unicode_boundaries_in_french_german_and_japanese() {
+        assert_eq!(
+            sentences("M. Dupont est arrivé, c.-à-d. hier. Il a dit « bonjour » ! Ça va ? Oui."),
+            [
+                "M. Dupont est arrivé, c.-à-d. hier.",
+                "Il a dit « bonjour » !",
+                "Ça va ?",
+                "Oui."
+            ]
+        );
+        assert_eq!(
+            sentences("Das ist z. B. gut. Dr. Müller kommt um 10 Uhr. Wirklich?"),
+            [
+                "Das ist z. B. gut.",
+                "Dr. Müller kommt um 10 Uhr.",
+                "Wirklich?"
+            ]
+        );
+        assert_eq!(
+            sentences("Me. Dupont plaide. Fr. Schmidt wohnt in Nr. 4. Ça va."),
+            [
+                "Me. Dupont plaide.",
+                "Fr. Schmidt wohnt in Nr. 4.",
+                "Ça va."
+            ]
+        );
+        assert_eq!(
+            sentences("Call me. I'll wait. He said no. Then he left."),
+            ["Call me.", "I'll wait.", "He said no.", "Then he left."]
+        );
+        assert_eq!(
+            sentences("We need Plan B. Then we go. Take vitamin C. It helps."),
+            [
+                "We need Plan B.",
+                "Then we go.",
+                "Take vitamin C.",
+                "It helps."
+            ]
+        );
+        assert_eq!(
+            sentences("J. R. R. Tolkien wrote it. Ask Dr. J. Smith about No. 5."),
+            [
+                "J. R. R. Tolkien wrote it.",
+                "Ask Dr. J. Smith about No. 5."
+            ]
+        );
+        assert_eq!(
+            sentences("今日は晴れです。明日は雨でしょうか？はい！"),
+            ["今日は晴れです。", "明日は雨でしょうか？", "はい！"]
+        );
+        assert_eq!(
+            sentences("¿Qué tal? ¡Muy bien! Use tabs, e.g. Vim or Emacs."),
+            ["¿Qué tal?", "¡Muy bien!", "Use tabs, e.g. Vim or Emacs."]
+        );
+    }
+
+    #[test]
+    fn japanese_sentences_pack_without_spaces() {
+        let text = "一つ目。二つ目。三つ目。";
+        let chunks = split(text, &options(ChunkStrategy::Sentence, 8, 0));
+        assert_eq!(texts(&chunks), ["一つ目。二つ目。", "三つ目。"]);
+        assert_eq!((chunks[1].char_start, chunks[1].byte_start), (8, 24));
+    }
+}
@@ -0,0 +1,489 @@
+use std::ops::Range;
+use std::path::Path;
+
+use serde::Serialize;
+use tree_sitter::{Language, Node, Parser};
+
+use crate::rchain::chunking::{CharOffsets, CharacterChunker, ChunkOptions, Chunker, TextChunk};
+
+/// Languages understood by the `code` chunk strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeLanguage {
+    Rust,
+    Python,
+    TypeScript,
+    Tsx,
+    Go,
+}
+
+impl CodeLanguage {
+    /// Detects the language from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "rs" => Some(Self::Rust),
+            "py" | "pyi" => Some(Self::Python),
+            "ts" | "mts" | "cts" => Some(Self::TypeScript),
+            "tsx" => Some(Self::Tsx),
+            "go" => Some(Self::Go),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rust => "rust",
+            Self::Python => "python",
+            Self::TypeScript | Self::Tsx => "typescript",
+            Self::Go => "go",
+        }
+    }
+
+    fn grammar(&self) -> Language {
+        match self {
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+            Self::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Self::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Self::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+
+    /// Separator between the parts of a qualified symbol.
+    fn separator(&self) -> &'static str {
+        match self {
+            Self::Rust => "::",
+            _ => ".",
+        }
+    }
+}
+
+/// Where a code chunk comes from: its language, the items it holds
+/// (`commands::index::run`, comma-separated when several) and its 1-based,
+/// inclusive line range.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CodeLocation {
+    pub language: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    pub line_start: usize,
+    pub line_end: usize,
+}
+
+/// Splits source code at top-level items (functions, impls, classes, …)
+/// and packs consecutive small items up to `chunk_size`.
+///
+/// An item larger than `chunk_size` is split at the statements or members
+/// of its body, recursively; its signature goes with the first part. Leading
+/// comments and attributes stay with the item they precede.
+#[derive(Debug, Clone)]
+pub struct CodeChunker {
+    options: ChunkOptions,
+    language: CodeLanguage,
+    /// Module path derived from the file path (`commands::index`).
+    module: Vec<String>,
+}
+
+/// An item or body member, with the bytes it covers and its symbol.
+struct CodeUnit<'tree> {
+    bytes: Range<usize>,
+    symbol: Option<String>,
+    node: Option<Node<'tree>>,
+}
+
+impl CodeChunker {
+    pub fn for_path(options: ChunkOptions, path: &Path) -> Result<Self, String> {
+        let language = CodeLanguage::from_path(path).ok_or_else(|| {
+            format!(
+                "Cannot detect the language of '{}' for the code chunk strategy (supported extensions: .rs, .py, .ts, .tsx, .go).",
+                path.display()
+            )
+        })?;
+        Ok(Self {
+            options,
+            language,
+            module: module_path(language, path),
+        })
+    }
+
+    /// Units for the named children of `container`. Children without a name
+    /// take `parent`'s symbol when `inherit` is set.
+    fn units<'tree>(
+        &self,
+        text: &str,
+        container: Node<'tree>,
+        parent: Option<&str>,
+        inherit: bool,
+    ) -> Vec<CodeUnit<'tree>> {
+        let mut units = Vec::new();
+        let mut leading_start = None;
+        let mut cursor = container.walk();
+        for child in container.named_children(&mut cursor) {
+            if is_leading(child.kind()) {
+                leading_start.get_or_insert(child.start_byte());
+                continue;
+            }
+            let start = leading_start.take().unwrap_or(child.start_byte());
+            let symbol = match item_name(child, text) {
+                Some(name) => Some(match parent {
+                    Some(parent) => format!("{parent}{}{name}", self.language.separator()),
+                    None => name,
+                }),
+                None if inherit => parent.map(str::to_string),
+                None => None,
+            };
+            units.push(CodeUnit {
+                bytes: line_start(text, start)..child.end_byte(),
+                symbol,
+                node: Some(child),
+            });
+        }
+        if let Some(start) = leading_start {
+            let end = container
+                .named_child(container.named_child_count().saturating_sub(1))
+                .map_or(start, |last| last.end_byte());
+            units.push(CodeUnit {
+                bytes: line_start(text, start)..end,
+                symbol: inherit.then(|| parent.map(str::to_string)).flatten(),
+                node: None,
+            });
+        }
+        units
+    }
+
+    /// The members of an oversized unit's body, the first one extended back
+    /// to the unit's start and the last one to its end.
+    fn split<'tree>(&self, text: &str, unit: &CodeUnit<'tree>) -> Option<Vec<CodeUnit<'tree>>> {
+        let body = body_of(unit.node?)?;
+        let mut parts = self.units(text, body, unit.symbol.as_deref(), true);
+        parts.first_mut()?.bytes.start = unit.bytes.start;
+        parts.last_mut()?.bytes.end = unit.bytes.end;
+        Some(parts)
+    }
+
+    fn pack(
+        &self,
+        text: &str,
+        units: Vec<CodeUnit<'_>>,
+        context: &ChunkContext,
+        chunks: &mut Vec<TextChunk>,
+    ) {
+        let size = |bytes: Range<usize>| {
+            context.offsets.char_at(bytes.end) - context.offsets.char_at(bytes.start)
+        };
+        let mut group: Vec<CodeUnit<'_>> = Vec::new();
+        for unit in units {
+            let oversized = size(unit.bytes.clone()) > self.options.chunk_size;
+            if oversized {
+                self.flush(text, &mut group, context, chunks);
+                match self.split(text, &unit) {
+                    Some(parts) => self.pack(text, parts, context, chunks),
+                    None => self.flush(text, &mut vec![unit], context, chunks),
+                }
+                continue;
+            }
+            if let Some(first) = group.first()
+                && size(first.bytes.start..unit.bytes.end) > self.options.chunk_size
+            {
+                self.flush(text, &mut group, context, chunks);
+            }
+            group.push(unit);
+        }
+        self.flush(text, &mut group, context, chunks);
+    }
+
+    fn flush(
+        &self,
+        text: &str,
+        group: &mut Vec<CodeUnit<'_>>,
+        context: &ChunkContext,
+        chunks: &mut Vec<TextChunk>,
+    ) {
+        let (Some(first), Some(last)) = (group.first(), group.last()) else {
+            return;
+        };
+        let bytes = first.bytes.start..last.bytes.end;
+        let mut symbols: Vec<&str> = Vec::new();
+        for symbol in group.iter().filter_map(|unit| unit.symbol.as_deref()) {
+            if !symbols.contains(&symbol) {
+                symbols.push(symbol);
+            }
+        }
+        let mut chunk = context.offsets.chunk(text, bytes.clone());
+        chunk.code = Some(CodeLocation {
+            language: self.language.as_str(),
+            symbol: (!symbols.is_empty()).then(|| symbols.join(", ")),
+            line_start: context.line_of(bytes.start),
+            line_end: context.line_of(bytes.end.saturating_sub(1).max(bytes.start)),
+        });
+        chunks.push(chunk);
+        group.clear();
+    }
+}
+
+impl Chunker for CodeChunker {
+    fn chunk(&self, text: &str) -> Vec<TextChunk> {
+        let mut parser = Parser::new();
+        let tree = parser
+            .set_language(&self.language.grammar())
+            .ok()
+            .and_then(|()| parser.parse(text, None));
+        let Some(tree) = tree else {
+            return CharacterChunker(self.options.clone()).chunk(text);
+        };
+        let root = tree.root_node();
+
+        let mut module = self.module.clone();
+        if self.language == CodeLanguage::Go
+            && let Some(package) = go_package(root, text)
+        {
+            module = vec![package];
+        }
+        let module = (!module.is_empty()).then(|| module.join(self.language.separator()));
+
+        let context = ChunkContext {
+            offsets: CharOffsets::new(text),
+            newlines: text.match_indices('\n').map(|(byte, _)| byte).collect(),
+        };
+        let units = self.units(text, root, module.as_deref(), false);
+        let mut chunks = Vec::new();
+        self.pack(text, units, &context, &mut chunks);
+        chunks
+    }
+}
+
+struct ChunkContext {
+    offsets: CharOffsets,
+    newlines: Vec<usize>,
+}
+
+impl ChunkContext {
+    /// 1-based line of a byte offset.
+    fn line_of(&self, byte: usize) -> usize {
+        self.newlines.partition_point(|&newline| newline < byte) + 1
+    }
+}
+
+/// Comments and attributes, which belong to the item after them.
+fn is_leading(kind: &str) -> bool {
+    matches!(
+        kind,
+        "comment" | "line_comment" | "block_comment" | "attribute_item" | "inner_attribute_item"
+    )
+}
+
+/// `byte`, moved back to the start of its line when only indentation
+/// precedes it there.
+fn line_start(text: &str, byte: usize) -> usize {
+    let line_begin = text[..byte].rfind('\n').map_or(0, |newline| newline + 1);
+    if text[line_begin..byte].trim().is_empty() {
+        line_begin
+    } else {
+        byte
+    }
+}
+
+/// The name an item declares, if any.
+fn item_name(node: Node<'_>, text: &str) -> Option<String> {
+    let source = text.as_bytes();
+    let field_text = |node: Node<'_>, field: &str| {
+        node.child_by_field_name(field)
+            .and_then(|child| child.utf8_text(source).ok())
+            .map(str::to_string)
+    };
+    match node.kind() {
+        // Imports name what they bring in, not an item of this file.
+        kind if kind.starts_with("import") => None,
+        // `impl Trait for Type` is named after the type.
+        "impl_item" => field_text(node, "type"),
+        "decorated_definition" => item_name(node.child_by_field_name("definition")?, text),
+        "export_statement" => item_name(node.child_by_field_name("declaration")?, text),
+        "lexical_declaration" | "type_declaration" => {
+            let mut cursor = node.walk();
+            let first = node.named_children(&mut cursor).next()?;
+            field_text(first, "name")
+        }
+        "method_declaration" => {
+            let name = field_text(node, "name")?;
+            let receiver = node
+                .child_by_field_name("receiver")
+                .and_then(|receiver| first_descendant(receiver, "type_identifier"))
+                .and_then(|receiver| receiver.utf8_text(source).ok());
+            Some(match receiver {
+                Some(receiver) => format!("{receiver}.{name}"),
+                None => name,
+            })
+        }
+        _ => field_text(node, "name"),
+    }
+}
+
+/// The node holding an item's members or statements, looking through
+/// wrappers such as decorators, `export` and `const f = () => { … }`.
+fn body_of(node: Node<'_>) -> Option<Node<'_>> {
+    if let Some(body) = node.child_by_field_name("body") {
+        return Some(body);
+    }
+    for field in ["definition", "declaration", "value", "consequence"] {
+        if let Some(body) = node.child_by_field_name(field).and_then(body_of) {
+            return Some(body);
+        }
+    }
+    if node.named_child_count() == 1 {
+        return body_of(node.named_child(0)?);
+    }
+    None
+}
+
+fn first_descendant<'tree>(node: Node<'tree>, kind: &str) -> Option<Node<'tree>> {
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.named_children(&mut cursor).collect();
+    children.into_iter().find_map(|child| {
+        if child.kind() == kind {
+            Some(child)
+        } else {
+            first_descendant(child, kind)
+        }
+    })
+}
+
+fn go_package(root: Node<'_>, text: &str) -> Option<String> {
+    let mut cursor = root.walk();
+    let clause = root
+        .named_children(&mut cursor)
+        .find(|child| child.kind() == "package_clause")?;
+    first_descendant(clause, "package_identifier")?
+        .utf8_text(text.as_bytes())
+        .ok()
+        .map(str::to_string)
+}
+
+/// Module path of a source file: the components after `src/` for Rust
+/// (without `mod`, `lib` and `main`), the file stem otherwise (the directory
+/// for `__init__.py` and `index.ts`). Go files use their `package` instead.
+fn module_path(language: CodeLanguage, path: &Path) -> Vec<String> {
+    let components: Vec<String> = path
+        .with_extension("")
+        .components()
+        .filter_map(|component| component.as_os_str().to_str().map(str::to_string))
+        .collect();
+    let mut module = match language {
+        CodeLanguage::Rust => match components.iter().rposition(|part| part == "src") {
+            Some(src) => components[src + 1..].to_vec(),
+            None => components.last().cloned().into_iter().collect(),
+        },
+        _ => components.iter().rev().take(2).rev().cloned().collect(),
+    };
+    let index_names: &[&str] = match language {
+        CodeLanguage::Rust => &["mod", "lib", "main"],
+        CodeLanguage::Python => &["__init__"],
+        CodeLanguage::TypeScript | CodeLanguage::Tsx => &["index"],
+        CodeLanguage::Go => &[],
+    };
+    if module
+        .last()
+        .is_some_and(|last| index_names.contains(&last.as_str()))
+    {
+        module.pop();
+    }
+    if language != CodeLanguage::Rust && module.len() > 1 {
+        module.remove(0);
+    }
+    module
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rchain::chunking::ChunkStrategy;
+    use crate::rchain::semantic_chunking::SemanticOptions;
+
+    fn options(chunk_size: usize) -> ChunkOptions {
+        ChunkOptions {
+            strategy: ChunkStrategy::Code,
+            chunk_size,
+            overlap_percent: 0,
+            tokenizer: None,
+            semantic: SemanticOptions::DEFAULT,
+        }
+    }
+
+    fn locations(chunks: &[TextChunk]) -> Vec<(Option<&str>, usize, usize)> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let code = chunk.code.as_ref().expect("code location");
+                (code.symbol.as_deref(), code.line_start, code.line_end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rust_items_are_chunked_with_module_symbols_and_lines() {
+        let source = "use std::fs;\n\n/// Reads it.\nfn read() -> String {\n    fs::read_to_string(\"x\").unwrap()\n}\n\nstruct Chunk {\n    text: String,\n}\n\nimpl Chunk {\n    fn new() -> Self {\n        Self { text: read() }\n    }\n\n    fn len(&self) -> usize {\n        self.text.len()\n    }\n}\n";
+        let chunker =
+            CodeChunker::for_path(options(90), Path::new("src/commands/index.rs")).expect("rust");
+        let chunks = chunker.chunk(source);
+
+        assert_eq!(
+            locations(&chunks),
+            [
+                (Some("commands::index::read"), 1, 6),
+                (Some("commands::index::Chunk"), 8, 10),
+                (Some("commands::index::Chunk::new"), 12, 15),
+                (Some("commands::index::Chunk::len"), 17, 20),
+            ]
+        );
+        assert!(
+            chunks[0]
+                .text
+                .starts_with("use std::fs;\n\n/// Reads it.\nfn read()")
+        );
+        assert!(chunks[2].text.starts_with("impl Chunk {\n    fn new()"));
+        assert!(chunks[3].text.ends_with("    }\n}"));
+        assert_eq!(
+            chunks[0].code.as_ref().map(|code| code.language),
+            Some("rust")
+        );
+        for chunk in &chunks {
+            assert_eq!(&source[chunk.byte_start..chunk.byte_end], chunk.text);
+        }
+    }
+
+    #[test]
+    fn python_typescript_and_go_items_are_named() {
+        let python = "import os\n\n\n@cache\ndef load(path):\n    return os.path\n\n\nclass Store:\n    def get(self):\n        return 1\n";
+        let chunks = CodeChunker::for_path(options(60), Path::new("pkg/store.py"))
+            .expect("python")
+            .chunk(python);
+        assert_eq!(
+            locations(&chunks),
+            [(Some("store.load"), 1, 6), (Some("store.Store"), 9, 11)]
+        );
+
+        let typescript = "export function greet(name: string) {\n  return name;\n}\n\nexport const answer = () => 42;\n";
+        let chunks = CodeChunker::for_path(options(60), Path::new("web/src/index.ts"))
+            .expect("typescript")
+            .chunk(typescript);
+        assert_eq!(
+            locations(&chunks),
+            [(Some("src.greet"), 1, 3), (Some("src.answer"), 5, 5)]
+        );
+
+        let go = "package server\n\nfunc (s *Server) Start() error {\n\treturn nil\n}\n";
+        let chunks = CodeChunker::for_path(options(50), Path::new("cmd/main.go"))
+            .expect("go")
+            .chunk(go);
+        assert_eq!(
+            locations(&chunks),
+            [(None, 1, 1), (Some("server.Server.Start"), 3, 5)]
+        );
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected() {
+        let err = CodeChunker::for_path(options(100), Path::new("notes.md")).expect_err("markdown");
+        assert!(err.contains("'notes.md'"));
+        assert!(err.contains(".rs, .py, .ts, .tsx, .go"));
+    }
+}
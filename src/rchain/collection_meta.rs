@@ -0,0 +1,283 @@
+use serde_json::{Map, Value, json};
+
+use crate::rchain::embeddings::EmbeddingProvider;
+use crate::rchain::vector_transform::{Quantization, VectorTransform};
+
+const PROVIDER_KEY: &str = "embedding_provider";
+const MODEL_KEY: &str = "embedding_model";
+const DIMENSION_KEY: &str = "embedding_dimension";
+const TRUNCATE_KEY: &str = "embedding_dimensions";
+const NORMALIZE_KEY: &str = "embedding_normalize";
+const QUANTIZATION_KEY: &str = "embedding_quantization";
+const VERSION_KEY: &str = "mpipe_version";
+
+/// Embedding settings of a collection, as recorded in its metadata by
+/// `mpipe index` or as used by the current command. Unknown fields are
+/// `None` and never count as a mismatch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmbeddingSpec {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub dimension: Option<usize>,
+    /// How the model's vectors were transformed before being stored.
+    pub transform: Option<VectorTransform>,
+}
+
+impl EmbeddingSpec {
+    pub fn new(provider: EmbeddingProvider, model: &str, dimension: Option<usize>) -> Self {
+        Self {
+            provider: Some(provider.as_str().to_string()),
+            model: Some(model.to_string()),
+            dimension,
+            transform: None,
+        }
+    }
+
+    /// Reads the settings recorded on a collection.
+    pub fn from_metadata(metadata: Option<&Map<String, Value>>) -> Self {
+        let text = |key: &str| {
+            metadata
+                .and_then(|m| m.get(key))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        let number = |key: &str| {
+            metadata
+                .and_then(|m| m.get(key))
+                .and_then(Value::as_u64)
+                .map(|value| value as usize)
+        };
+        // The normalize flag is written with every transform, so its
+        // presence tells a recorded identity from an unknown one.
+        let transform = metadata
+            .and_then(|m| m.get(NORMALIZE_KEY))
+            .and_then(Value::as_bool)
+            .map(|normalize| VectorTransform {
+                dimensions: number(TRUNCATE_KEY),
+                normalize,
+                quantization: text(QUANTIZATION_KEY)
+                    .as_deref()
+                    .and_then(Quantization::from_str),
+            });
+        Self {
+            provider: text(PROVIDER_KEY),
+            model: text(MODEL_KEY),
+            dimension: number(DIMENSION_KEY),
+            transform,
+        }
+    }
+
+    /// Collection metadata recording these settings on top of `existing`.
+    ///
+    /// Index parameters (`hnsw:*`) are left out: ChromaDB refuses to change
+    /// them after creation.
+    pub fn to_metadata(&self, existing: Option<&Map<String, Value>>) -> Map<String, Value> {
+        let mut metadata = existing
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| !key.starts_with("hnsw:"))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Map<_, _>>();
+        if let Some(provider) = &self.provider {
+            metadata.insert(PROVIDER_KEY.to_string(), json!(provider));
+        }
+        if let Some(model) = &self.model {
+            metadata.insert(MODEL_KEY.to_string(), json!(model));
+        }
+        if let Some(dimension) = self.dimension {
+            metadata.insert(DIMENSION_KEY.to_string(), json!(dimension));
+        }
+        if let Some(transform) = self.transform {
+            metadata.remove(TRUNCATE_KEY);
+            metadata.remove(QUANTIZATION_KEY);
+            metadata.insert(NORMALIZE_KEY.to_string(), json!(transform.normalize));
+            if let Some(dimensions) = transform.dimensions {
+                metadata.insert(TRUNCATE_KEY.to_string(), json!(dimensions));
+            }
+            if let Some(quantization) = transform.quantization {
+                metadata.insert(QUANTIZATION_KEY.to_string(), json!(quantization.as_str()));
+            }
+        }
+        metadata.insert(VERSION_KEY.to_string(), json!(env!("CARGO_PKG_VERSION")));
+        metadata
+    }
+
+    /// Differences between what a collection was indexed with and what the
+    /// current command uses, one line each.
+    pub fn mismatches(&self, current: &Self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if let (Some(stored), Some(used)) = (&self.provider, &current.provider)
+            && stored != used
+        {
+            mismatches.push(format!(
+                "embedding provider '{used}' (indexed with '{stored}')"
+            ));
+        }
+        if let (Some(stored), Some(used)) = (&self.model, &current.model)
+            && stored != used
+        {
+            mismatches.push(format!(
+                "embedding model '{used}' (indexed with '{stored}')"
+            ));
+        }
+        if let (Some(stored), Some(used)) = (self.dimension, current.dimension)
+            && stored != used
+        {
+            mismatches.push(format!(
+                "embedding dimension {used} (indexed with {stored})"
+            ));
+        }
+        if let (Some(stored), Some(used)) = (self.transform, current.transform)
+            && stored != used
+        {
+            mismatches.push(format!(
+                "embedding transform '{used}' (indexed with '{stored}')"
+            ));
+        }
+        mismatches
+    }
+}
+
+/// Refuses to use `collection` with embeddings unlike the ones it was
+/// indexed with, or only warns on stderr when `force` is set.
+pub fn ensure_compatible(
+    collection: &str,
+    stored: &EmbeddingSpec,
+    current: &EmbeddingSpec,
+    force: bool,
+) -> Result<(), String> {
+    let mismatches = stored.mismatches(current);
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "Collection '{collection}' does not match this command: {}.",
+        mismatches.join(", ")
+    );
+    if force {
+        eprintln!("warning: {message}");
+        return Ok(());
+    }
+    Err(format!("{message} Pass --force to proceed anyway."))
+}
+
+/// Provider and model that embed queries of `collection`: the given ones,
+/// else the recorded ones. A mismatch with the recorded settings is refused
+/// unless `force` is set.
+pub fn query_settings(
+    collection: &str,
+    stored: &EmbeddingSpec,
+    provider: Option<EmbeddingProvider>,
+    model: Option<&str>,
+    force: bool,
+) -> Result<(EmbeddingProvider, String), String> {
+    let provider = provider
+        .or_else(|| {
+            stored
+                .provider
+                .as_deref()
+                .and_then(EmbeddingProvider::from_str)
+        })
+        .unwrap_or(EmbeddingProvider::Fireworks);
+    let model = model
+        .map(str::to_string)
+        .or_else(|| stored.model.clone())
+        .ok_or_else(|| {
+            format!("Missing --embedding-model (collection '{collection}' does not record one).")
+        })?;
+    ensure_compatible(
+        collection,
+        stored,
+        &EmbeddingSpec::new(provider, &model, None),
+        force,
+    )?;
+    Ok((provider, model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_settings_round_trip_through_metadata() {
+        let mut existing = Map::new();
+        existing.insert("hnsw:space".to_string(), json!("cosine"));
+        existing.insert("team".to_string(), json!("docs"));
+
+        let mut spec =
+            EmbeddingSpec::new(EmbeddingProvider::Openai, "text-embedding-3-small", Some(3));
+        assert_eq!(
+            EmbeddingSpec::from_metadata(Some(&spec.to_metadata(None))),
+            spec
+        );
+        spec.transform = Some(VectorTransform {
+            dimensions: Some(256),
+            normalize: true,
+            quantization: Some(Quantization::Int8),
+        });
+        let metadata = spec.to_metadata(Some(&existing));
+        assert!(!metadata.contains_key("hnsw:space"));
+        assert_eq!(metadata["team"], json!("docs"));
+        assert_eq!(metadata[VERSION_KEY], json!(env!("CARGO_PKG_VERSION")));
+        assert_eq!(EmbeddingSpec::from_metadata(Some(&metadata)), spec);
+        assert_eq!(EmbeddingSpec::from_metadata(None), EmbeddingSpec::default());
+    }
+
+    #[test]
+    fn only_known_fields_can_mismatch() {
+        let stored = EmbeddingSpec::new(EmbeddingProvider::Fireworks, "qwen3", Some(4096));
+        let current = EmbeddingSpec {
+            provider: None,
+            model: Some("nomic".to_string()),
+            dimension: Some(768),
+            transform: Some(VectorTransform::default()),
+        };
+        assert_eq!(
+            stored.mismatches(&current),
+            vec![
+                "embedding model 'nomic' (indexed with 'qwen3')",
+                "embedding dimension 768 (indexed with 4096)"
+            ]
+        );
+        assert!(stored.mismatches(&EmbeddingSpec::default()).is_empty());
+        let normalized = EmbeddingSpec {
+            transform: Some(VectorTransform {
+                normalize: true,
+                ..VectorTransform::default()
+            }),
+            ..EmbeddingSpec::default()
+        };
+        assert_eq!(
+            normalized.mismatches(&current),
+            vec!["embedding transform 'none' (indexed with 'normalize')"]
+        );
+
+        let err = ensure_compatible("docs", &stored, &current, false).expect_err("mismatch");
+        assert!(err.starts_with("Collection 'docs' does not match this command"));
+        assert!(err.ends_with("Pass --force to proceed anyway."));
+        assert!(ensure_compatible("docs", &stored, &current, true).is_ok());
+    }
+
+    #[test]
+    fn query_settings_default_to_the_recorded_ones() {
+        let stored = EmbeddingSpec::new(EmbeddingProvider::Local, "/models/bge", Some(384));
+        assert_eq!(
+            query_settings("docs", &stored, None, None, false),
+            Ok((EmbeddingProvider::Local, "/models/bge".to_string()))
+        );
+        assert!(
+            query_settings(
+                "docs",
+                &stored,
+                Some(EmbeddingProvider::Openai),
+                None,
+                false
+            )
+            .is_err_and(|err| err.contains("embedding provider 'openai'"))
+        );
+        assert!(
+            query_settings("docs", &EmbeddingSpec::default(), None, None, false)
+                .is_err_and(|err| err.starts_with("Missing --embedding-model"))
+        );
+    }
+}
@@ -0,0 +1,479 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+use crate::config::ToolConfig;
+use crate::rchain::tools::{ToolDefinition, ToolFunction, ToolParam, ToolParamType};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// Parsed `command` template of a configured tool.
+///
+/// The template is split into argv words like a POSIX shell would (whitespace,
+/// single/double quotes, backslash escapes) but is never handed to a shell.
+/// `{{name}}` placeholders are substituted per word after splitting, so an
+/// argument value always stays inside the word it was declared in.
+#[derive(Debug, Clone)]
+pub struct CommandTemplate {
+    words: Vec<Vec<Segment>>,
+}
+
+impl CommandTemplate {
+    /// Splits a template into words and placeholders.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut in_word = false;
+        let mut quote: Option<char> = None;
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some(open), c) if c == open => quote = None,
+                (Some('"'), '\\') => match chars.next() {
+                    Some(next @ ('"' | '\\')) => word.push(next),
+                    Some(next) => {
+                        word.push('\\');
+                        word.push(next);
+                    }
+                    None => return Err("unterminated double quote".to_string()),
+                },
+                (Some(_), c) => word.push(c),
+                (None, '\'' | '"') => {
+                    quote = Some(c);
+                    in_word = true;
+                }
+                (None, '\\') => {
+                    let next = chars
+                        .next()
+                        .ok_or_else(|| "trailing backslash".to_string())?;
+                    word.push(next);
+                    in_word = true;
+                }
+                (None, c) if c.is_whitespace() => {
+                    if in_word {
+                        words.push(split_placeholders(&word)?);
+                        word.clear();
+                        in_word = false;
+                    }
+                }
+                (None, c) => {
+                    word.push(c);
+                    in_word = true;
+                }
+            }
+        }
+
+        if let Some(open) = quote {
+            let kind = if open == '"' { "double" } else { "single" };
+            return Err(format!("unterminated {kind} quote"));
+        }
+        if in_word {
+            words.push(split_placeholders(&word)?);
+        }
+
+        match words.first().map(Vec::as_slice) {
+            None => return Err("command is empty".to_string()),
+            Some([Segment::Literal(_)]) => {}
+            Some(_) => return Err("the program name cannot contain placeholders".to_string()),
+        }
+
+        Ok(Self { words })
+    }
+
+    /// Placeholder names referenced by the template, in order of appearance.
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.words
+            .iter()
+            .flatten()
+            .filter_map(|segment| match segment {
+                Segment::Placeholder(name) => Some(name.as_str()),
+                Segment::Literal(_) => None,
+            })
+    }
+
+    /// Substitutes arguments and returns the argv vector.
+    ///
+    /// A word made of a single placeholder is dropped when the argument is
+    /// absent and expands to one word per element for arrays. Values starting
+    /// with `-` are refused unless a literal `--` word precedes them, so a
+    /// model cannot smuggle extra options into the program.
+    pub fn render(&self, args: &Map<String, Value>) -> Result<Vec<String>, String> {
+        let mut argv = Vec::new();
+        let mut options_ended = false;
+
+        for word in &self.words {
+            if let [Segment::Placeholder(name)] = word.as_slice() {
+                let values = match args.get(name) {
+                    None | Some(Value::Null) => Vec::new(),
+                    Some(Value::Array(items)) => items.iter().map(value_to_arg).collect(),
+                    Some(value) => vec![value_to_arg(value)],
+                };
+                for value in values {
+                    check_option_like(name, &value, options_ended)?;
+                    argv.push(value);
+                }
+                continue;
+            }
+
+            let mut rendered = String::new();
+            for segment in word {
+                match segment {
+                    Segment::Literal(text) => rendered.push_str(text),
+                    Segment::Placeholder(name) => {
+                        let value = args.get(name).map(value_to_arg).unwrap_or_default();
+                        rendered.push_str(&value);
+                    }
+                }
+            }
+            if let Some(Segment::Placeholder(name)) = word.first() {
+                check_option_like(name, &rendered, options_ended)?;
+            }
+            if rendered == "--" {
+                options_ended = true;
+            }
+            argv.push(rendered);
+        }
+
+        Ok(argv)
+    }
+}
+
+fn split_placeholders(word: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut rest = word;
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unterminated placeholder in '{word}'"))?;
+        let name = after[..end].trim();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!("invalid placeholder '{{{{{name}}}}}'"));
+        }
+        segments.push(Segment::Placeholder(name.to_string()));
+        rest = &after[end + 2..];
+    }
+
+    if !rest.is_empty() || segments.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+
+    Ok(segments)
+}
+
+fn value_to_arg(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn check_option_like(name: &str, value: &str, options_ended: bool) -> Result<(), String> {
+    if !options_ended && value.starts_with('-') {
+        return Err(format!(
+            "argument '{name}' starts with '-'; add a literal `--` before {{{{{name}}}}} in the command template to allow it"
+        ));
+    }
+    Ok(())
+}
+
+/// Tool backed by a local program, declared in `config.toml`.
+#[derive(Debug, Clone)]
+pub struct CommandTool {
+    /// Tool name exposed to the model.
+    pub name: String,
+    /// Tool description exposed to the model.
+    pub description: String,
+    /// Whether every call must be confirmed on the TTY.
+    pub confirm: bool,
+    params: Vec<ToolParam>,
+    template: CommandTemplate,
+    timeout: Duration,
+    cwd: Option<PathBuf>,
+    max_output_bytes: usize,
+}
+
+impl CommandTool {
+    /// Builds a tool from its `[tools.<name>]` section.
+    pub fn from_config(name: &str, config: &ToolConfig) -> Result<Self, String> {
+        let template = CommandTemplate::parse(&config.command)
+            .map_err(|err| format!("Invalid command for tool '{name}': {err}."))?;
+        let params = config
+            .params
+            .iter()
+            .map(|(param_name, param)| {
+                let kind = ToolParamType::from_str(&param.kind).ok_or_else(|| {
+                    format!(
+                        "Invalid type '{}' for parameter '{param_name}' of tool '{name}'.",
+                        param.kind
+                    )
+                })?;
+                Ok(ToolParam::new(
+                    param_name,
+                    kind,
+                    param.required,
+                    param.description.clone(),
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            name: name.to_string(),
+            description: config.description.trim().to_string(),
+            confirm: config.confirm,
+            params,
+            template,
+            timeout: Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            cwd: config.cwd.clone(),
+            max_output_bytes: config.max_output_bytes.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES),
+        })
+    }
+
+    /// Returns the provider-facing declaration of this tool.
+    pub fn definition(&self) -> ToolDefinition {
+        let function = self
+            .params
+            .iter()
+            .cloned()
+            .fold(ToolFunction::new(&self.name, &self.description), |f, p| {
+                f.with_param(p)
+            });
+        ToolDefinition::from_function(function)
+    }
+
+    /// Validates arguments against the declared parameters and renders argv.
+    pub fn argv(&self, args: &Value) -> Result<Vec<String>, String> {
+        let args = self.definition().function.validate_args(args)?;
+        self.template.render(&args)
+    }
+
+    /// Executes the program and returns its (possibly truncated) output.
+    ///
+    /// A non-zero exit status is reported in the output rather than as an
+    /// error so the model can react to it.
+    pub async fn run(&self, args: &Value) -> Result<String, String> {
+        self.run_argv(&self.argv(args)?).await
+    }
+
+    /// Executes an argv previously rendered by [`CommandTool::argv`].
+    pub async fn run_argv(&self, argv: &[String]) -> Result<String, String> {
+        let (program, rest) = argv
+            .split_first()
+            .ok_or_else(|| "command rendered to an empty argv".to_string())?;
+
+        let mut command = Command::new(program);
+        command
+            .args(rest)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("failed to start '{program}': {err}"))?;
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let max_bytes = self.max_output_bytes;
+        let output = async {
+            tokio::join!(
+                read_capped(stdout, max_bytes),
+                read_capped(stderr, max_bytes),
+                child.wait()
+            )
+        };
+        let (stdout, stderr, status) = tokio::time::timeout(self.timeout, output)
+            .await
+            .map_err(|_| format!("timed out after {}s", self.timeout.as_secs()))?;
+        let status = status.map_err(|err| format!("failed to wait for '{program}': {err}"))?;
+        let (stdout, stderr) = (
+            stdout.map_err(|err| format!("failed to read output of '{program}': {err}"))?,
+            stderr.map_err(|err| format!("failed to read output of '{program}': {err}"))?,
+        );
+
+        let mut text = String::from_utf8_lossy(&stdout.kept).into_owned();
+        if !stderr.kept.is_empty() {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str("[stderr]\n");
+            text.push_str(&String::from_utf8_lossy(&stderr.kept));
+        }
+        if !status.success() {
+            let code = status
+                .code()
+                .map_or_else(|| "signal".to_string(), |code| code.to_string());
+            text = format!("[exit status {code}]\n{text}");
+        }
+
+        Ok(truncate_dropping(
+            text,
+            max_bytes,
+            stdout.dropped + stderr.dropped,
+        ))
+    }
+}
+
+/// Renders argv for display, single-quoting words a shell would split.
+pub fn render_argv(argv: &[String]) -> String {
+    argv.iter()
+        .map(|word| {
+            if !word.is_empty()
+                && word
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c))
+            {
+                word.clone()
+            } else {
+                format!("'{}'", word.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Cuts `text` to at most `max_bytes` on a char boundary and notes the loss.
+pub fn truncate_output(text: String, max_bytes: usize) -> String {
+    truncate_dropping(text, max_bytes, 0)
+}
+
+/// Like [`truncate_output`] for text that already lost `unread` bytes.
+pub(crate) fn truncate_dropping(mut text: String, max_bytes: usize, unread: usize) -> String {
+    if text.len() <= max_bytes && unread == 0 {
+        return text;
+    }
+
+    let mut cut = max_bytes.min(text.len());
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let dropped = text.len() - cut + unread;
+    text.truncate(cut);
+    text.push_str(&format!("\n[truncated {dropped} bytes]"));
+    text
+}
+
+/// The first bytes of a child's output stream and the count of the rest.
+#[derive(Debug, Default)]
+pub(crate) struct CappedOutput {
+    pub kept: Vec<u8>,
+    pub dropped: usize,
+}
+
+/// Reads `stream` to its end, keeping at most `max_bytes` in memory. The
+/// stream is drained so the child never blocks on a full pipe.
+pub(crate) async fn read_capped(
+    stream: Option<impl AsyncRead + Unpin>,
+    max_bytes: usize,
+) -> std::io::Result<CappedOutput> {
+    let mut output = CappedOutput::default();
+    let Some(mut stream) = stream else {
+        return Ok(output);
+    };
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(output);
+        }
+        let keep = read.min(max_bytes - output.kept.len());
+        output.kept.extend_from_slice(&buffer[..keep]);
+        output.dropped += read - keep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &str, args: Value) -> Result<Vec<String>, String> {
+        let args = args.as_object().cloned().unwrap_or_default();
+        CommandTemplate::parse(template)?.render(&args)
+    }
+
+    #[test]
+    fn placeholders_stay_in_their_own_word() {
+        let argv = render(
+            "rg --json -- {{pattern}} {{path}}",
+            json!({"pattern": "a b; rm -rf /", "path": "src"}),
+        )
+        .expect("template should render");
+        assert_eq!(argv, ["rg", "--json", "--", "a b; rm -rf /", "src"]);
+    }
+
+    #[test]
+    fn quotes_and_embedded_placeholders_are_supported() {
+        let argv = render(r#"grep "--max-count={{n}}" 'a b' x\ y"#, json!({"n": 3}))
+            .expect("template should render");
+        assert_eq!(argv, ["grep", "--max-count=3", "a b", "x y"]);
+    }
+
+    #[test]
+    fn missing_optional_and_array_arguments() {
+        let argv = render("ls {{flags}} -- {{paths}}", json!({"paths": ["a", "-b"]}))
+            .expect("template should render");
+        assert_eq!(argv, ["ls", "--", "a", "-b"]);
+    }
+
+    #[test]
+    fn option_like_values_are_rejected_before_double_dash() {
+        let err = render("rg {{pattern}}", json!({"pattern": "--pre=sh"}))
+            .expect_err("option-like value should be refused");
+        assert!(err.contains("starts with '-'"));
+    }
+
+    #[test]
+    fn invalid_templates_are_reported() {
+        assert!(CommandTemplate::parse("").is_err());
+        assert!(CommandTemplate::parse("echo 'open").is_err());
+        assert!(CommandTemplate::parse("echo {{x").is_err());
+        assert!(CommandTemplate::parse("{{program}} arg").is_err());
+    }
+
+    #[tokio::test]
+    async fn output_past_the_cap_is_counted_not_kept() {
+        let config = ToolConfig {
+            description: "Prints a lot".to_string(),
+            command: "sh -c 'head -c 100000 /dev/zero | tr \\\\0 a'".to_string(),
+            params: Default::default(),
+            timeout: None,
+            cwd: None,
+            max_output_bytes: Some(10),
+            confirm: false,
+        };
+        let tool = CommandTool::from_config("flood", &config).expect("tool");
+        let output = tool.run(&json!({})).await.expect("run");
+        assert_eq!(output, "aaaaaaaaaa\n[truncated 99990 bytes]");
+    }
+
+    #[test]
+    fn truncate_output_respects_char_boundaries() {
+        let text = truncate_output("héllo".to_string(), 2);
+        assert!(text.starts_with('h'));
+        assert!(text.contains("[truncated"));
+    }
+}
@@ -1,13 +1,26 @@
 use std::env;
 use std::error::Error;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 
-use reqwest::blocking::Client;
-use serde_json::json;
+use futures_util::{StreamExt, TryStreamExt, stream};
+use serde_json::{Value, json};
+
+use crate::rchain::chat_runtime::{RequestFailure, RetryConfig, send_chat_request_with_retry};
+use crate::rchain::chunking::{ChunkOptions, ChunkStrategy, TextChunk};
+use crate::rchain::local_embeddings::LocalModel;
+use crate::rchain::provider::Usage;
+use crate::rchain::semantic_chunking::{SemanticOptions, semantic_chunks};
+use crate::rchain::vector_transform::VectorTransform;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EmbeddingProvider {
     Openai,
     Fireworks,
+    /// A model directory on disk (`--embedding-model /path/to/model`), run
+    /// on the CPU without network access.
+    Local,
 }
 
 impl EmbeddingProvider {
@@ -15,32 +28,16 @@ impl EmbeddingProvider {
         match self {
             Self::Openai => "openai",
             Self::Fireworks => "fireworks",
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ChunkStrategy {
-    Paragraph,
-    Sentence,
-    Token,
-}
-
-impl ChunkStrategy {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Self::Paragraph => "paragraph",
-            Self::Sentence => "sentence",
-            Self::Token => "token",
+            Self::Local => "local",
         }
     }
 
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s.trim().to_ascii_lowercase().as_str() {
-            "paragraph" => Some(Self::Paragraph),
-            "sentence" => Some(Self::Sentence),
-            "token" => Some(Self::Token),
+            "openai" => Some(Self::Openai),
+            "fireworks" => Some(Self::Fireworks),
+            "local" => Some(Self::Local),
             _ => None,
         }
     }
@@ -49,9 +46,10 @@ impl ChunkStrategy {
 pub struct EmbeddingsConfig {
     pub provider: EmbeddingProvider,
     pub model: String,
-    pub chunk_size: usize,
-    pub chunk_overlap: usize,
-    pub chunk_strategy: ChunkStrategy,
+    pub chunking: ChunkOptions,
+    pub batch: BatchOptions,
+    /// Applied to the chunk vectors; semantic chunking compares raw ones.
+    pub transform: VectorTransform,
 }
 
 impl Default for EmbeddingsConfig {
@@ -59,178 +57,93 @@ impl Default for EmbeddingsConfig {
         Self {
             provider: EmbeddingProvider::Fireworks,
             model: "accounts/fireworks/models/qwen3-embedding-8b".to_string(),
-            chunk_size: 8000,
-            chunk_overlap: 10,
-            chunk_strategy: ChunkStrategy::Paragraph,
+            chunking: ChunkOptions {
+                strategy: ChunkStrategy::Paragraph,
+                chunk_size: 8000,
+                overlap_percent: 10,
+                tokenizer: None,
+                semantic: SemanticOptions::DEFAULT,
+            },
+            batch: BatchOptions::default(),
+            transform: VectorTransform::default(),
         }
     }
 }
 
 pub struct EmbeddingResult {
-    pub chunks: Vec<String>,
+    pub chunks: Vec<TextChunk>,
     pub embeddings: Vec<Vec<f64>>,
     pub model: String,
     pub provider: String,
+    pub usage: Option<Usage>,
 }
 
-pub fn chunk_text(
-    text: &str,
-    strategy: ChunkStrategy,
-    chunk_size: usize,
-    overlap_percent: usize,
-) -> Vec<String> {
-    match strategy {
-        ChunkStrategy::Paragraph => chunk_by_paragraph(text, chunk_size, overlap_percent),
-        ChunkStrategy::Sentence => chunk_by_sentence(text, chunk_size, overlap_percent),
-        ChunkStrategy::Token => chunk_by_token(text, chunk_size, overlap_percent),
-    }
+/// Vectors returned by [`EmbeddingClient::embed`], one per input in input
+/// order, with the tokens the provider billed for them.
+#[derive(Debug, Clone)]
+pub struct Embeddings {
+    pub vectors: Vec<Vec<f64>>,
+    pub usage: Option<Usage>,
 }
 
-fn chunk_by_paragraph(text: &str, chunk_size: usize, overlap_percent: usize) -> Vec<String> {
-    let paragraphs: Vec<&str> = text
-        .split("\n\n")
-        .filter(|p| !p.trim().is_empty())
-        .collect();
-    let mut chunks = Vec::new();
-    let mut current_chunk = String::new();
-    let mut current_size = 0;
-
-    for para in paragraphs {
-        let para_size = para.len();
-        if current_size + para_size > chunk_size && !current_chunk.is_empty() {
-            chunks.push(current_chunk.trim().to_string());
-            let _overlap_chars = (chunk_size * overlap_percent) / 100;
-            current_chunk = String::new();
-            current_size = 0;
-        }
-
-        if !current_chunk.is_empty() {
-            current_chunk.push_str("\n\n");
-            current_size += 2;
-        }
-        current_chunk.push_str(para);
-        current_size += para_size;
-    }
-
-    if !current_chunk.trim().is_empty() {
-        chunks.push(current_chunk.trim().to_string());
-    }
-
-    if chunks.is_empty() && !text.trim().is_empty() {
-        chunks.push(text.trim().to_string());
-    }
-
-    chunks
+/// Inputs sent in one embeddings request when nothing else is configured.
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+/// Estimated tokens sent in one embeddings request when nothing else is
+/// configured.
+pub const DEFAULT_BATCH_TOKENS: usize = 32_000;
+/// Embeddings requests in flight at once when nothing else is configured.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// How inputs are grouped into requests and how those requests are sent.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    /// Maximum inputs per request.
+    pub batch_size: usize,
+    /// Maximum estimated tokens per request; a single larger input is still
+    /// sent, alone.
+    pub max_batch_tokens: usize,
+    /// Requests in flight at once.
+    pub concurrency: usize,
+    pub timeout_secs: Option<u64>,
+    pub retries: u32,
+    pub retry_delay_ms: u64,
 }
 
-fn chunk_by_sentence(text: &str, chunk_size: usize, overlap_percent: usize) -> Vec<String> {
-    let sentence_enders = ['.', '!', '?', '¿', '¡'];
-    let mut sentences: Vec<&str> = Vec::new();
-    let mut current_start = 0;
-    let bytes = text.as_bytes();
-
-    for (i, &byte) in bytes.iter().enumerate() {
-        let c = byte as char;
-        if sentence_enders.contains(&c) && i + 1 < bytes.len() && bytes[i + 1].is_ascii_whitespace()
-        {
-            sentences.push(&text[current_start..=i]);
-            current_start = i + 1;
-            while current_start < bytes.len() && bytes[current_start].is_ascii_whitespace() {
-                current_start += 1;
-            }
-        }
-    }
-
-    if current_start < text.len() {
-        sentences.push(&text[current_start..]);
-    }
-
-    let mut chunks = Vec::new();
-    let mut current_chunk = String::new();
-    let mut current_size = 0;
-
-    for sentence in sentences {
-        let sentence_size = sentence.len();
-        if current_size + sentence_size > chunk_size && !current_chunk.is_empty() {
-            chunks.push(current_chunk.trim().to_string());
-            let _overlap_chars = (chunk_size * overlap_percent) / 100;
-            current_chunk = String::new();
-            current_size = 0;
-        }
-
-        if !current_chunk.is_empty() {
-            current_chunk.push(' ');
-            current_size += 1;
-        }
-        current_chunk.push_str(sentence);
-        current_size += sentence_size;
-    }
-
-    if !current_chunk.trim().is_empty() {
-        chunks.push(current_chunk.trim().to_string());
-    }
-
-    if chunks.is_empty() && !text.trim().is_empty() {
-        chunks.push(text.trim().to_string());
-    }
-
-    chunks
-}
-
-fn chunk_by_token(text: &str, chunk_size: usize, overlap_percent: usize) -> Vec<String> {
-    let words: Vec<&str> = text.split_whitespace().collect();
-    let mut chunks = Vec::new();
-    let mut current_chunk = Vec::new();
-    let mut current_size = 0;
-
-    for word in words {
-        let word_size = word
-            .split(|c: char| c.is_ascii_punctuation())
-            .filter(|s| !s.is_empty())
-            .map(|s| s.chars().count())
-            .sum::<usize>()
-            .max(1);
-
-        if current_size + word_size > chunk_size && !current_chunk.is_empty() {
-            chunks.push(current_chunk.join(" "));
-            let overlap_words = (chunk_size * overlap_percent) / 100 / 5;
-            current_chunk = current_chunk[current_chunk
-                .len()
-                .saturating_sub(overlap_words.min(current_chunk.len()))..]
-                .to_vec();
-            current_size = current_chunk.iter().map(|w: &&str| w.len()).sum::<usize>();
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_batch_tokens: DEFAULT_BATCH_TOKENS,
+            concurrency: DEFAULT_CONCURRENCY,
+            timeout_secs: Some(60),
+            retries: 3,
+            retry_delay_ms: 500,
         }
-
-        current_chunk.push(word);
-        current_size += word_size;
-    }
-
-    if !current_chunk.is_empty() {
-        chunks.push(current_chunk.join(" "));
-    }
-
-    if chunks.is_empty() && !text.trim().is_empty() {
-        chunks.push(text.trim().to_string());
     }
-
-    chunks
 }
 
-pub fn embed_texts(
+/// Chunks and embeds `texts`. `source` is the file they were read from, if
+/// any, which the `code` chunk strategy needs.
+pub async fn embed_texts(
     config: &EmbeddingsConfig,
     texts: &[String],
+    source: Option<&Path>,
 ) -> Result<EmbeddingResult, Box<dyn Error + Send + Sync>> {
-    let all_chunks: Vec<String> = texts
-        .iter()
-        .flat_map(|text| {
-            chunk_text(
-                text,
-                config.chunk_strategy,
-                config.chunk_size,
-                config.chunk_overlap,
-            )
-        })
-        .collect();
+    let client = EmbeddingClient::new(config.provider, &config.model, config.batch)?;
+    let mut usage = None;
+    let all_chunks: Vec<TextChunk> = if config.chunking.strategy == ChunkStrategy::Semantic {
+        let mut chunks = Vec::new();
+        for text in texts {
+            let (text_chunks, chunking_usage) =
+                semantic_chunks(text, &config.chunking, &client).await?;
+            chunks.extend(text_chunks);
+            usage = add_usage(usage, chunking_usage);
+        }
+        chunks
+    } else {
+        let chunker = config.chunking.chunker(source)?;
+        texts.iter().flat_map(|text| chunker.chunk(text)).collect()
+    };
 
     if all_chunks.is_empty() {
         return Ok(EmbeddingResult {
@@ -238,146 +151,470 @@ pub fn embed_texts(
             embeddings: vec![],
             model: config.model.clone(),
             provider: config.provider.as_str().to_string(),
+            usage,
         });
     }
 
-    let embeddings = embed_chunks(config, &all_chunks)?;
+    let inputs = all_chunks
+        .iter()
+        .map(|chunk| chunk.text.clone())
+        .collect::<Vec<_>>();
+    let embeddings = client.embed_transformed(&inputs, &config.transform).await?;
 
     Ok(EmbeddingResult {
         chunks: all_chunks,
-        embeddings,
+        embeddings: embeddings.vectors,
         model: config.model.clone(),
         provider: config.provider.as_str().to_string(),
+        usage: add_usage(usage, embeddings.usage),
     })
 }
 
-fn embed_chunks(
-    config: &EmbeddingsConfig,
-    chunks: &[String],
-) -> Result<Vec<Vec<f64>>, Box<dyn Error + Send + Sync>> {
-    match config.provider {
-        EmbeddingProvider::Openai => embed_chunks_openai(&config.model, &config.api_key()?, chunks),
-        EmbeddingProvider::Fireworks => {
-            embed_chunks_fireworks(&config.model, &config.api_key()?, chunks)
-        }
-    }
+/// Sums the tokens billed by two embeddings calls.
+fn add_usage(a: Option<Usage>, b: Option<Usage>) -> Option<Usage> {
+    let tokens = match (
+        a.and_then(|usage| usage.prompt_tokens),
+        b.and_then(|usage| usage.prompt_tokens),
+    ) {
+        (None, None) => return None,
+        (a, b) => a.unwrap_or(0) + b.unwrap_or(0),
+    };
+    Some(Usage {
+        prompt_tokens: Some(tokens),
+        completion_tokens: None,
+        total_tokens: Some(tokens),
+    })
 }
 
-pub fn embed_chunks_with_provider(
+/// Embeds `chunks` with default batching, one vector per chunk in order.
+pub async fn embed_chunks_with_provider(
     provider: EmbeddingProvider,
     model: &str,
     chunks: &[String],
 ) -> Result<Vec<Vec<f64>>, Box<dyn Error + Send + Sync>> {
-    let config = EmbeddingsConfig {
-        provider,
-        model: model.to_string(),
-        ..EmbeddingsConfig::default()
-    };
-    embed_chunks(&config, chunks)
+    let embeddings = EmbeddingClient::new(provider, model, BatchOptions::default())?
+        .embed(chunks)
+        .await?;
+    Ok(embeddings.vectors)
 }
 
-impl EmbeddingsConfig {
-    fn api_key(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
-        let env_key = match self.provider {
-            EmbeddingProvider::Openai => "OPENAI_API_KEY",
-            EmbeddingProvider::Fireworks => "FIREWORKS_API_KEY",
-        };
-        env::var(env_key).map_err(|_| format!("{env_key} is not set in the environment").into())
+impl EmbeddingProvider {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Openai => "OpenAI",
+            Self::Fireworks => "Fireworks",
+            Self::Local => "Local model",
+        }
     }
 
-    #[allow(dead_code)]
-    fn endpoint(&self) -> &'static str {
-        match self.provider {
-            EmbeddingProvider::Openai => "https://api.openai.com/v1/embeddings",
-            EmbeddingProvider::Fireworks => "https://api.fireworks.ai/inference/v1/embeddings",
+    /// Whether `model` returns shorter vectors when asked for `dimensions`
+    /// (Matryoshka models). Other models reject the parameter.
+    fn supports_dimensions(&self, model: &str) -> bool {
+        let model = model.to_ascii_lowercase();
+        match self {
+            Self::Openai => model.starts_with("text-embedding-3"),
+            Self::Fireworks => ["nomic-embed-text-v1.5", "qwen3-embedding"]
+                .iter()
+                .any(|name| model.contains(name)),
+            Self::Local => false,
         }
     }
-}
 
-fn embed_chunks_fireworks(
-    model: &str,
-    api_key: &str,
-    chunks: &[String],
-) -> Result<Vec<Vec<f64>>, Box<dyn Error + Send + Sync>> {
-    let client = Client::new();
-    let base_url = "https://api.fireworks.ai/inference/v1/embeddings";
-
-    let mut embeddings = Vec::with_capacity(chunks.len());
+    /// Endpoint and API key variable of the providers reached over HTTP;
+    /// `None` for [`Self::Local`].
+    fn remote(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Self::Openai => Some(("https://api.openai.com/v1/embeddings", "OPENAI_API_KEY")),
+            Self::Fireworks => Some((
+                "https://api.fireworks.ai/inference/v1/embeddings",
+                "FIREWORKS_API_KEY",
+            )),
+            Self::Local => None,
+        }
+    }
+}
 
-    for chunk in chunks {
-        let payload = json!({
-            "model": model,
-            "input": chunk,
-        });
+/// Client for the OpenAI-compatible `/embeddings` endpoint both remote
+/// providers expose, or for a local model.
+///
+/// Inputs are split into batches by count and estimated tokens, batches are
+/// sent concurrently with the retry policy of chat requests, and vectors come
+/// back in input order.
+#[derive(Debug, Clone)]
+pub struct EmbeddingClient {
+    provider: EmbeddingProvider,
+    model: String,
+    options: BatchOptions,
+    backend: Backend,
+}
 
-        let response = client
-            .post(base_url)
-            .bearer_auth(api_key)
-            .json(&payload)
-            .send()?;
+#[derive(Debug, Clone)]
+enum Backend {
+    Remote {
+        endpoint: &'static str,
+        api_key: String,
+        http: reqwest::Client,
+    },
+    /// Batches run on blocking threads; `--concurrency` of them at once.
+    Local(Arc<LocalModel>),
+}
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().unwrap_or_default();
-            return Err(format!("Fireworks API error {status}: {body}").into());
-        }
+impl EmbeddingClient {
+    pub fn new(
+        provider: EmbeddingProvider,
+        model: &str,
+        options: BatchOptions,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let backend = match provider.remote() {
+            Some((endpoint, key_env)) => Backend::Remote {
+                endpoint,
+                api_key: env::var(key_env)
+                    .map_err(|_| format!("{key_env} is not set in the environment"))?,
+                http: reqwest::Client::new(),
+            },
+            None => Backend::Local(LocalModel::shared(Path::new(model))?),
+        };
+        Ok(Self {
+            provider,
+            model: model.to_string(),
+            options,
+            backend,
+        })
+    }
 
-        let body: serde_json::Value = response.json()?;
-        let embedding = body["data"][0]["embedding"]
-            .as_array()
-            .ok_or("Missing embedding data from Fireworks API")?;
+    /// Returns one vector per input, in input order. Fails unless every
+    /// input got a vector and all vectors have the same dimension.
+    pub async fn embed(
+        &self,
+        inputs: &[String],
+    ) -> Result<Embeddings, Box<dyn Error + Send + Sync>> {
+        self.embed_requested(inputs, None).await
+    }
 
-        let vector: Vec<f64> = embedding.iter().filter_map(|v| v.as_f64()).collect();
+    /// Like [`Self::embed`], with `transform` applied to every vector. Its
+    /// `dimensions` are also requested from models that shorten vectors
+    /// server-side; the others return full vectors, truncated here.
+    pub async fn embed_transformed(
+        &self,
+        inputs: &[String],
+        transform: &VectorTransform,
+    ) -> Result<Embeddings, Box<dyn Error + Send + Sync>> {
+        let embeddings = self.embed_requested(inputs, transform.dimensions).await?;
+        let vectors = embeddings
+            .vectors
+            .into_iter()
+            .map(|vector| transform.apply(vector))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Embeddings {
+            vectors,
+            usage: embeddings.usage,
+        })
+    }
 
-        embeddings.push(vector);
+    async fn embed_requested(
+        &self,
+        inputs: &[String],
+        dimensions: Option<usize>,
+    ) -> Result<Embeddings, Box<dyn Error + Send + Sync>> {
+        let batches = plan_batches(
+            inputs,
+            self.options.batch_size,
+            self.options.max_batch_tokens,
+        );
+        let results = stream::iter(batches)
+            .map(|range| self.embed_batch(&inputs[range], dimensions))
+            .buffered(self.options.concurrency.max(1))
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let mut vectors = Vec::with_capacity(inputs.len());
+        let mut prompt_tokens = None;
+        for batch in results {
+            vectors.extend(batch.vectors);
+            if let Some(tokens) = batch.usage.and_then(|usage| usage.prompt_tokens) {
+                prompt_tokens = Some(prompt_tokens.unwrap_or(0) + tokens);
+            }
+        }
+        check_dimensions(&vectors)?;
+        Ok(Embeddings {
+            vectors,
+            usage: prompt_tokens.map(|tokens| Usage {
+                prompt_tokens: Some(tokens),
+                completion_tokens: None,
+                total_tokens: Some(tokens),
+            }),
+        })
     }
 
-    Ok(embeddings)
+    async fn embed_batch(
+        &self,
+        inputs: &[String],
+        dimensions: Option<usize>,
+    ) -> Result<Embeddings, Box<dyn Error + Send + Sync>> {
+        let (endpoint, api_key, http) = match &self.backend {
+            Backend::Remote {
+                endpoint,
+                api_key,
+                http,
+            } => (*endpoint, api_key, http),
+            Backend::Local(model) => {
+                let model = Arc::clone(model);
+                let inputs = inputs.to_vec();
+                let embeddings =
+                    tokio::task::spawn_blocking(move || model.embed(&inputs)).await??;
+                let tokens = embeddings.tokens as u32;
+                return Ok(Embeddings {
+                    vectors: embeddings.vectors,
+                    usage: Some(Usage {
+                        prompt_tokens: Some(tokens),
+                        completion_tokens: None,
+                        total_tokens: Some(tokens),
+                    }),
+                });
+            }
+        };
+        let payload = request_payload(self.provider, &self.model, inputs, dimensions);
+        let retry = RetryConfig {
+            timeout_secs: self.options.timeout_secs,
+            retries: self.options.retries,
+            retry_delay_ms: self.options.retry_delay_ms,
+        };
+        let response = send_chat_request_with_retry(http, endpoint, api_key, &payload, retry)
+            .await
+            .map_err(|failure| match failure {
+                RequestFailure::Request(source) => {
+                    format!("{} request failed: {source}", self.provider.label())
+                }
+                RequestFailure::Api { status, body } => {
+                    format!("{} API error {status}: {body}", self.provider.label())
+                }
+            })?;
+
+        let body: Value = response.json().await?;
+        parse_embeddings(&body, self.provider, inputs.len())
+    }
 }
 
-fn embed_chunks_openai(
+/// Body of an `/embeddings` request, asking for `dimensions` only from
+/// models supporting it.
+fn request_payload(
+    provider: EmbeddingProvider,
     model: &str,
-    api_key: &str,
-    chunks: &[String],
-) -> Result<Vec<Vec<f64>>, Box<dyn Error + Send + Sync>> {
-    let client = Client::new();
-    let base_url = "https://api.openai.com/v1/embeddings";
-
-    let payload = json!({
+    inputs: &[String],
+    dimensions: Option<usize>,
+) -> Value {
+    let mut payload = json!({
         "model": model,
-        "input": chunks,
+        "input": inputs,
     });
+    if let Some(dimensions) = dimensions
+        && provider.supports_dimensions(model)
+    {
+        payload["dimensions"] = json!(dimensions);
+    }
+    payload
+}
 
-    let response = client
-        .post(base_url)
-        .bearer_auth(api_key)
-        .json(&payload)
-        .send()?;
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let body = response.text().unwrap_or_default();
-        return Err(format!("OpenAI API error {status}: {body}").into());
+/// Groups consecutive inputs into batches of at most `batch_size` inputs and
+/// `max_tokens` estimated tokens.
+fn plan_batches(inputs: &[String], batch_size: usize, max_tokens: usize) -> Vec<Range<usize>> {
+    let batch_size = batch_size.max(1);
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+
+    for (index, input) in inputs.iter().enumerate() {
+        let cost = estimate_tokens(input);
+        if index > start && (index - start >= batch_size || tokens + cost > max_tokens) {
+            batches.push(start..index);
+            start = index;
+            tokens = 0;
+        }
+        tokens += cost;
     }
+    if start < inputs.len() {
+        batches.push(start..inputs.len());
+    }
+    batches
+}
 
-    let body: serde_json::Value = response.json()?;
+/// Rough token count used for batching: four characters per token.
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4).max(1)
+}
+
+/// Reads an `/embeddings` response for `expected` inputs, placing each
+/// vector by its `index` field rather than by its position in `data`.
+fn parse_embeddings(
+    body: &Value,
+    provider: EmbeddingProvider,
+    expected: usize,
+) -> Result<Embeddings, Box<dyn Error + Send + Sync>> {
+    let label = provider.label();
     let data = body["data"]
         .as_array()
-        .ok_or("Missing embedding data from OpenAI API")?;
-
-    let mut embeddings: Vec<Vec<f64>> = Vec::with_capacity(data.len());
+        .ok_or_else(|| format!("Missing embedding data from {label} API"))?;
+    if data.len() != expected {
+        return Err(format!(
+            "{label} API returned {} embeddings for {expected} inputs",
+            data.len()
+        )
+        .into());
+    }
 
-    for item in data {
-        let embedding = item["embedding"]
+    let mut slots: Vec<Option<Vec<f64>>> = vec![None; expected];
+    for (position, item) in data.iter().enumerate() {
+        let index = match item.get("index") {
+            Some(index) => index
+                .as_u64()
+                .map(|index| index as usize)
+                .ok_or_else(|| format!("Invalid embedding index from {label} API: {index}"))?,
+            None => position,
+        };
+        let slot = slots
+            .get_mut(index)
+            .ok_or_else(|| format!("{label} API returned embedding index {index} out of range"))?;
+        if slot.is_some() {
+            return Err(format!("{label} API returned embedding index {index} twice").into());
+        }
+        let vector = item["embedding"]
             .as_array()
-            .ok_or("Missing embedding array")?;
+            .ok_or("Missing embedding array")?
+            .iter()
+            .map(|value| {
+                value
+                    .as_f64()
+                    .ok_or_else(|| format!("Non-numeric embedding value from {label} API"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        *slot = Some(vector);
+    }
+
+    let usage = body["usage"]["prompt_tokens"].as_u64().map(|tokens| Usage {
+        prompt_tokens: Some(tokens as u32),
+        completion_tokens: None,
+        total_tokens: Some(tokens as u32),
+    });
+    Ok(Embeddings {
+        vectors: slots.into_iter().flatten().collect(),
+        usage,
+    })
+}
 
-        let vector: Vec<f64> = embedding.iter().filter_map(|v| v.as_f64()).collect();
+fn check_dimensions(vectors: &[Vec<f64>]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(first) = vectors.first() else {
+        return Ok(());
+    };
+    if first.is_empty() {
+        return Err("Embedding provider returned an empty vector".into());
+    }
+    if let Some((index, vector)) = vectors
+        .iter()
+        .enumerate()
+        .find(|(_, vector)| vector.len() != first.len())
+    {
+        return Err(format!(
+            "Embedding dimension mismatch at input {index} (expected {}, got {})",
+            first.len(),
+            vector.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(lengths: &[usize]) -> Vec<String> {
+        lengths.iter().map(|len| "a".repeat(*len)).collect()
+    }
 
-        embeddings.push(vector);
+    #[test]
+    fn batches_respect_count_and_token_budget() {
+        assert_eq!(
+            plan_batches(&inputs(&[4, 4, 4, 4, 4]), 2, 100),
+            vec![0..2, 2..4, 4..5]
+        );
+        // 40 chars are 10 estimated tokens; an oversized input goes alone.
+        assert_eq!(
+            plan_batches(&inputs(&[40, 40, 80, 4]), 10, 20),
+            vec![0..2, 2..3, 3..4]
+        );
+        assert!(plan_batches(&[], 8, 100).is_empty());
     }
 
-    embeddings.sort_by_key(|e| e.len());
+    #[test]
+    fn dimensions_are_requested_only_from_matryoshka_models() {
+        let inputs = inputs(&[4]);
+        let payload = |provider, model| request_payload(provider, model, &inputs, Some(256));
+        assert_eq!(
+            payload(EmbeddingProvider::Openai, "text-embedding-3-small")["dimensions"],
+            256
+        );
+        assert_eq!(
+            payload(
+                EmbeddingProvider::Fireworks,
+                "accounts/fireworks/models/qwen3-embedding-8b"
+            )["dimensions"],
+            256
+        );
+        for (provider, model) in [
+            (EmbeddingProvider::Openai, "text-embedding-ada-002"),
+            (EmbeddingProvider::Fireworks, "thenlper/gte-large"),
+        ] {
+            assert!(
+                payload(provider, model).get("dimensions").is_none(),
+                "{model}"
+            );
+        }
+
+        // Full vectors from the other models are truncated locally.
+        let transform = VectorTransform {
+            dimensions: Some(2),
+            ..VectorTransform::default()
+        };
+        assert_eq!(transform.apply(vec![0.1, 0.2, 0.3]), Ok(vec![0.1, 0.2]));
+    }
 
-    Ok(embeddings)
+    #[test]
+    fn embeddings_are_mapped_back_by_index() {
+        let body = json!({
+            "data": [
+                {"index": 1, "embedding": [2.0, 3]},
+                {"index": 0, "embedding": [0.5, 1.0]}
+            ],
+            "usage": {"prompt_tokens": 7, "total_tokens": 7}
+        });
+        let parsed = parse_embeddings(&body, EmbeddingProvider::Openai, 2).expect("vectors");
+        assert_eq!(parsed.vectors, vec![vec![0.5, 1.0], vec![2.0, 3.0]]);
+        assert_eq!(parsed.usage.and_then(|usage| usage.prompt_tokens), Some(7));
+
+        let positional = json!({"data": [{"embedding": [1.0]}, {"embedding": [2.0]}]});
+        let parsed =
+            parse_embeddings(&positional, EmbeddingProvider::Fireworks, 2).expect("vectors");
+        assert_eq!(parsed.vectors, vec![vec![1.0], vec![2.0]]);
+        assert!(parsed.usage.is_none());
+    }
+
+    #[test]
+    fn inconsistent_responses_are_rejected() {
+        let provider = EmbeddingProvider::Openai;
+        let body = json!({"data": [{"index": 0, "embedding": [1.0]}]});
+        assert!(parse_embeddings(&json!({"error": "x"}), provider, 1).is_err());
+        assert!(parse_embeddings(&body, provider, 2).is_err());
+
+        let duplicate = json!({"data": [
+            {"index": 0, "embedding": [1.0]},
+            {"index": 0, "embedding": [2.0]}
+        ]});
+        assert!(parse_embeddings(&duplicate, provider, 2).is_err());
+        let out_of_range = json!({"data": [{"index": 3, "embedding": [1.0]}]});
+        assert!(parse_embeddings(&out_of_range, provider, 1).is_err());
+
+        assert!(check_dimensions(&[vec![1.0, 2.0], vec![3.0]]).is_err());
+        assert!(check_dimensions(&[vec![]]).is_err());
+        assert!(check_dimensions(&[vec![1.0], vec![2.0]]).is_ok());
+    }
 }
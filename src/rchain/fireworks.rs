@@ -1,11 +1,13 @@
 use std::env;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::rchain::chat_runtime::{RequestFailure, RetryConfig, send_chat_request_with_retry};
 use crate::rchain::provider::{
     AskOptions, AskResponse, ChatMessage, Provider, ProviderError, Usage, api_key_env,
 };
+use crate::rchain::tools::ToolCall;
 
 const FIREWORKS_CHAT_COMPLETIONS_URL: &str =
     "https://api.fireworks.ai/inference/v1/chat/completions";
@@ -18,6 +20,8 @@ struct ChatCompletionRequest {
     temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,6 +38,8 @@ struct Choice {
 #[derive(Debug, Deserialize)]
 struct AssistantMessage {
     content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,6 +58,15 @@ pub async fn ask_messages(
     messages: &[ChatMessage],
     model: &str,
     options: AskOptions,
+) -> Result<AskResponse, ProviderError> {
+    ask_messages_with_tools(messages, &[], model, options).await
+}
+
+pub async fn ask_messages_with_tools(
+    messages: &[ChatMessage],
+    tools: &[Value],
+    model: &str,
+    options: AskOptions,
 ) -> Result<AskResponse, ProviderError> {
     let provider = Provider::Fireworks;
     let key_env = api_key_env(provider);
@@ -63,6 +78,7 @@ pub async fn ask_messages(
         messages: messages.to_vec(),
         temperature: options.temperature,
         max_tokens: options.max_tokens,
+        tools: tools.to_vec(),
     };
 
     let client = reqwest::Client::new();
@@ -91,18 +107,31 @@ pub async fn ask_messages(
         .json()
         .await
         .map_err(|source| ProviderError::Request { provider, source })?;
-    
-    let content = body
+
+    let message = body
         .choices
-        .first()
-        .and_then(|choice| choice.message.content.clone())
-        .filter(|content| !content.is_empty())
+        .into_iter()
+        .next()
+        .map(|choice| choice.message)
         .ok_or(ProviderError::EmptyResponse { provider })?;
+    let tool_calls = message
+        .tool_calls
+        .iter()
+        .filter_map(ToolCall::from_json)
+        .collect::<Vec<_>>();
+    let content = message.content.unwrap_or_default();
+    if content.is_empty() && tool_calls.is_empty() {
+        return Err(ProviderError::EmptyResponse { provider });
+    }
     let usage = body.usage.map(|usage| Usage {
         prompt_tokens: usage.prompt_tokens,
         completion_tokens: usage.completion_tokens,
         total_tokens: usage.total_tokens,
     });
 
-    Ok(AskResponse { content, usage })
+    Ok(AskResponse {
+        content,
+        usage,
+        tool_calls,
+    })
 }
@@ -0,0 +1,329 @@
+use chromadb::collection::{ChromaCollection, QueryOptions, QueryResult};
+use serde::Serialize;
+
+use crate::rchain::collection_meta::{EmbeddingSpec, ensure_compatible, query_settings};
+use crate::rchain::embeddings::{BatchOptions, EmbeddingClient, EmbeddingProvider};
+use crate::rchain::vector_transform::VectorTransform;
+
+/// Results returned by `search_knowledge` when the model does not ask for a
+/// number.
+pub const DEFAULT_TOP_K: usize = 5;
+/// Upper bound on `top_k` accepted from the model.
+pub const MAX_TOP_K: usize = 20;
+
+/// One chunk returned by a collection query, ranked from 1.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceHit {
+    pub rank: usize,
+    pub id: String,
+    pub source: Option<String>,
+    /// Markdown heading breadcrumb recorded by `mpipe index`.
+    pub heading_path: Option<String>,
+    /// Code item and 1-based line range recorded by `mpipe index`.
+    pub symbol: Option<String>,
+    pub line_start: Option<usize>,
+    pub line_end: Option<usize>,
+    pub chunk_index: Option<usize>,
+    pub distance: Option<f32>,
+    pub document: String,
+}
+
+/// Embeds a query with the model and `transform` the collection was indexed
+/// with.
+pub async fn embed_query(
+    provider: EmbeddingProvider,
+    model: &str,
+    query: &str,
+    transform: &VectorTransform,
+) -> Result<Vec<f32>, String> {
+    let chunks = vec![query.to_string()];
+    let embeddings = match EmbeddingClient::new(provider, model, BatchOptions::default()) {
+        Ok(client) => client.embed_transformed(&chunks, transform).await,
+        Err(err) => Err(err),
+    };
+    let mut vectors = embeddings
+        .map_err(|err| format!("Failed to embed prompt: {err}"))?
+        .vectors;
+    let vector = vectors
+        .pop()
+        .ok_or_else(|| "Embedding provider returned no vector for prompt.".to_string())?;
+    Ok(vector.into_iter().map(|v| v as f32).collect())
+}
+
+/// Returns the `top_k` chunks nearest to `embedding`, best first.
+pub async fn query_collection(
+    collection: &ChromaCollection,
+    embedding: Vec<f32>,
+    top_k: usize,
+) -> Result<Vec<SourceHit>, String> {
+    let name = collection.name();
+    let query_result = collection
+        .query(
+            QueryOptions {
+                query_embeddings: Some(vec![embedding]),
+                query_texts: None,
+                n_results: Some(top_k),
+                where_metadata: None,
+                where_document: None,
+                include: Some(vec!["metadatas", "documents", "distances"]),
+            },
+            None,
+        )
+        .await
+        .map_err(|err| format!("Failed to query collection '{name}': {err}"))?;
+    collect_sources(&query_result)
+}
+
+fn collect_sources(query_result: &QueryResult) -> Result<Vec<SourceHit>, String> {
+    let ids = query_result
+        .ids
+        .first()
+        .ok_or_else(|| "Query result did not contain ids.".to_string())?;
+    let docs = query_result
+        .documents
+        .as_ref()
+        .and_then(|all| all.first())
+        .cloned()
+        .unwrap_or_default();
+    let metadatas = query_result
+        .metadatas
+        .as_ref()
+        .and_then(|all| all.first())
+        .cloned()
+        .unwrap_or_default();
+    let distances = query_result
+        .distances
+        .as_ref()
+        .and_then(|all| all.first())
+        .cloned()
+        .unwrap_or_default();
+
+    let mut hits = Vec::with_capacity(ids.len());
+    for (idx, id) in ids.iter().enumerate() {
+        let document = docs.get(idx).cloned().unwrap_or_default();
+        let metadata = metadatas.get(idx).and_then(|m| m.as_ref());
+        let text = |key: &str| {
+            metadata.and_then(|m| {
+                m.get(key)
+                    .and_then(|value| value.as_str())
+                    .map(str::to_string)
+            })
+        };
+        let number = |key: &str| {
+            metadata.and_then(|m| {
+                m.get(key)
+                    .and_then(|value| value.as_u64())
+                    .map(|v| v as usize)
+            })
+        };
+        let distance = distances.get(idx).cloned();
+
+        hits.push(SourceHit {
+            rank: idx + 1,
+            id: id.clone(),
+            source: text("source"),
+            heading_path: text("heading_path"),
+            symbol: text("symbol"),
+            line_start: number("line_start"),
+            line_end: number("line_end"),
+            chunk_index: number("chunk_index"),
+            distance,
+            document,
+        });
+    }
+    Ok(hits)
+}
+
+/// Collections an agent may search with the `search_knowledge` tool.
+#[derive(Debug)]
+pub struct KnowledgeBase {
+    /// Opened collections; the first one is searched by default.
+    collections: Vec<KnowledgeCollection>,
+}
+
+/// A collection with the embedding settings its queries use.
+#[derive(Debug)]
+struct KnowledgeCollection {
+    collection: ChromaCollection,
+    provider: EmbeddingProvider,
+    embedding_model: String,
+    stored: EmbeddingSpec,
+}
+
+impl KnowledgeBase {
+    /// Wraps already-opened collections, which must not be empty.
+    ///
+    /// Each collection is queried with `provider` and `embedding_model`, or
+    /// with those it records, and refused if they differ from the recorded
+    /// ones.
+    pub fn new(
+        collections: Vec<ChromaCollection>,
+        provider: Option<EmbeddingProvider>,
+        embedding_model: Option<&str>,
+    ) -> Result<Self, String> {
+        if collections.is_empty() {
+            return Err("A knowledge base needs at least one collection.".to_string());
+        }
+        let collections = collections
+            .into_iter()
+            .map(|collection| {
+                let stored = EmbeddingSpec::from_metadata(collection.metadata());
+                let (provider, embedding_model) =
+                    query_settings(collection.name(), &stored, provider, embedding_model, false)?;
+                Ok(KnowledgeCollection {
+                    collection,
+                    provider,
+                    embedding_model,
+                    stored,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { collections })
+    }
+
+    pub fn collection_names(&self) -> Vec<&str> {
+        self.collections
+            .iter()
+            .map(|target| target.collection.name())
+            .collect()
+    }
+
+    /// Embeds `query`, searches one collection and renders the ranked hits.
+    pub async fn search(
+        &self,
+        query: &str,
+        collection: Option<&str>,
+        top_k: Option<usize>,
+    ) -> Result<String, String> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Err("query cannot be empty".to_string());
+        }
+        let top_k = top_k.unwrap_or(DEFAULT_TOP_K);
+        if top_k == 0 || top_k > MAX_TOP_K {
+            return Err(format!("top_k must be between 1 and {MAX_TOP_K}"));
+        }
+        let target = match collection {
+            Some(name) => self
+                .collections
+                .iter()
+                .find(|candidate| candidate.collection.name() == name)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown collection '{name}' (available: {})",
+                        self.collection_names().join(", ")
+                    )
+                })?,
+            None => &self.collections[0],
+        };
+
+        let embedding = embed_query(
+            target.provider,
+            &target.embedding_model,
+            query,
+            &target.stored.transform.unwrap_or_default(),
+        )
+        .await?;
+        ensure_compatible(
+            target.collection.name(),
+            &target.stored,
+            &EmbeddingSpec {
+                dimension: Some(embedding.len()),
+                ..EmbeddingSpec::default()
+            },
+            false,
+        )?;
+
+        let hits = query_collection(&target.collection, embedding, top_k).await?;
+        if hits.is_empty() {
+            return Ok(format!(
+                "No matching chunks in collection '{}'.",
+                target.collection.name()
+            ));
+        }
+        Ok(render_hits(&hits))
+    }
+}
+
+impl SourceHit {
+    /// Where the chunk sits in its source: ` section="A > B"` for Markdown,
+    /// ` symbol=… lines=12-20` for code, empty otherwise.
+    pub fn location_label(&self) -> String {
+        let mut label = String::new();
+        if let Some(path) = &self.heading_path {
+            label.push_str(&format!(" section=\"{path}\""));
+        }
+        if let Some(symbol) = &self.symbol {
+            label.push_str(&format!(" symbol={symbol}"));
+        }
+        if let (Some(start), Some(end)) = (self.line_start, self.line_end) {
+            label.push_str(&format!(" lines={start}-{end}"));
+        }
+        label
+    }
+}
+
+/// Renders hits for the model: a header line per chunk followed by its text.
+pub fn render_hits(hits: &[SourceHit]) -> String {
+    hits.iter()
+        .map(|hit| {
+            let chunk = hit
+                .chunk_index
+                .map(|idx| format!(" chunk={}", idx + 1))
+                .unwrap_or_default();
+            let distance = hit
+                .distance
+                .map(|d| format!("{d:.4}"))
+                .unwrap_or_else(|| "n/a".to_string());
+            format!(
+                "[{}] source={}{}{chunk} id={} distance={distance}\n{}",
+                hit.rank,
+                hit.source.as_deref().unwrap_or("unknown"),
+                hit.location_label(),
+                hit.id,
+                hit.document.trim()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_render_with_source_location_chunk_and_distance() {
+        let hits = vec![
+            SourceHit {
+                rank: 1,
+                id: "notes-0".to_string(),
+                source: Some("notes.md".to_string()),
+                heading_path: Some("Install > Linux".to_string()),
+                symbol: None,
+                line_start: None,
+                line_end: None,
+                chunk_index: Some(0),
+                distance: Some(0.12345),
+                document: "  First chunk.\n".to_string(),
+            },
+            SourceHit {
+                rank: 2,
+                id: "x".to_string(),
+                source: None,
+                heading_path: None,
+                symbol: Some("commands::index::run".to_string()),
+                line_start: Some(12),
+                line_end: Some(20),
+                chunk_index: None,
+                distance: None,
+                document: "Second.".to_string(),
+            },
+        ];
+        assert_eq!(
+            render_hits(&hits),
+            "[1] source=notes.md section=\"Install > Linux\" chunk=1 id=notes-0 distance=0.1235\nFirst chunk.\n\n\
+             [2] source=unknown symbol=commands::index::run lines=12-20 id=x distance=n/a\nSecond."
+        );
+    }
+}
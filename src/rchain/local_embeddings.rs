@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+/// Models loaded by this process, by directory, so that repeated searches
+/// do not read the weights again.
+static LOADED: OnceLock<Mutex<HashMap<PathBuf, Arc<LocalModel>>>> = OnceLock::new();
+
+/// A sentence-transformers style BERT model in a local directory, run on the
+/// CPU: `config.json`, `tokenizer.json` and `model.safetensors`.
+pub struct LocalModel {
+    dir: PathBuf,
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+/// Vectors of [`LocalModel::embed`] with the tokens the model read.
+pub struct LocalEmbeddings {
+    pub vectors: Vec<Vec<f64>>,
+    pub tokens: usize,
+}
+
+impl LocalModel {
+    /// The model in `dir`, loaded on first use.
+    pub fn shared(dir: &Path) -> Result<Arc<Self>, String> {
+        let mut loaded = LOADED
+            .get_or_init(Default::default)
+            .lock()
+            .map_err(|_| "Local embedding model cache is poisoned.".to_string())?;
+        if let Some(model) = loaded.get(dir) {
+            return Ok(Arc::clone(model));
+        }
+        let model = Arc::new(Self::load(dir)?);
+        loaded.insert(dir.to_path_buf(), Arc::clone(&model));
+        Ok(model)
+    }
+
+    /// Loads the model in `dir`.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let failed = |err: &dyn fmt::Display| {
+            format!(
+                "Failed to load local embedding model '{}': {err}",
+                dir.display()
+            )
+        };
+        if !dir.is_dir() {
+            return Err(failed(
+                &"not a directory (pass the model directory as --embedding-model)",
+            ));
+        }
+        let weights = dir.join("model.safetensors");
+        if !weights.is_file() {
+            if dir.join("model.onnx").is_file() || dir.join("onnx/model.onnx").is_file() {
+                return Err(failed(
+                    &"ONNX weights are not supported; download the model.safetensors of the model",
+                ));
+            }
+            return Err(failed(&"missing model.safetensors"));
+        }
+
+        let config = fs::read_to_string(dir.join("config.json")).map_err(|err| failed(&err))?;
+        let config: Config = serde_json::from_str(&config).map_err(|err| failed(&err))?;
+        if let Some(model_type) = &config.model_type
+            && model_type != "bert"
+        {
+            return Err(failed(&format!(
+                "model_type '{model_type}' is not supported (expected a BERT model)"
+            )));
+        }
+
+        let mut tokenizer =
+            Tokenizer::from_file(dir.join("tokenizer.json")).map_err(|err| failed(&err))?;
+        let mut padding = tokenizer.get_padding().cloned().unwrap_or(PaddingParams {
+            pad_id: config.pad_token_id as u32,
+            ..PaddingParams::default()
+        });
+        padding.strategy = PaddingStrategy::BatchLongest;
+        tokenizer.with_padding(Some(padding));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..TruncationParams::default()
+            }))
+            .map_err(|err| failed(&err))?;
+
+        let device = Device::Cpu;
+        let bytes = fs::read(&weights).map_err(|err| failed(&err))?;
+        let weights = VarBuilder::from_buffered_safetensors(bytes, DTYPE, &device)
+            .map_err(|err| failed(&err))?;
+        let model = BertModel::load(weights, &config).map_err(|err| failed(&err))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            model,
+            tokenizer,
+            device,
+        })
+    }
+
+    /// One vector per input: the mean of the token embeddings, normalized
+    /// to unit length. Inputs longer than the model's context are truncated.
+    pub fn embed(&self, inputs: &[String]) -> Result<LocalEmbeddings, String> {
+        let failed = |err: &dyn fmt::Display| {
+            format!(
+                "Local embedding model '{}' failed: {err}",
+                self.dir.display()
+            )
+        };
+        if inputs.is_empty() {
+            return Ok(LocalEmbeddings {
+                vectors: Vec::new(),
+                tokens: 0,
+            });
+        }
+        let encodings = self
+            .tokenizer
+            .encode_batch(inputs.to_vec(), true)
+            .map_err(|err| failed(&err))?;
+        let length = encodings[0].get_ids().len();
+        let tensor = |values: Vec<u32>| {
+            Tensor::from_vec(values, (encodings.len(), length), &self.device)
+                .map_err(|err| failed(&err))
+        };
+        let flatten = |field: fn(&tokenizers::Encoding) -> &[u32]| {
+            encodings
+                .iter()
+                .flat_map(|encoding| field(encoding).iter().copied())
+                .collect::<Vec<_>>()
+        };
+        let mask = flatten(tokenizers::Encoding::get_attention_mask);
+        let tokens = mask.iter().filter(|&&value| value == 1).count();
+        let ids = tensor(flatten(tokenizers::Encoding::get_ids))?;
+        let type_ids = tensor(flatten(tokenizers::Encoding::get_type_ids))?;
+        let mask = tensor(mask)?;
+
+        let pooled = || -> candle_core::Result<Vec<Vec<f32>>> {
+            let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+            let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+            let sum = hidden.broadcast_mul(&mask)?.sum(1)?;
+            let count = mask.sum(1)?.clamp(1e-9, f64::MAX)?;
+            let mean = sum.broadcast_div(&count)?;
+            let norm = mean.sqr()?.sum_keepdim(1)?.sqrt()?.clamp(1e-12, f64::MAX)?;
+            mean.broadcast_div(&norm)?.to_vec2()
+        };
+        let vectors = pooled().map_err(|err| failed(&err))?;
+        Ok(LocalEmbeddings {
+            vectors: vectors
+                .into_iter()
+                .map(|vector| vector.into_iter().map(f64::from).collect())
+                .collect(),
+            tokens,
+        })
+    }
+}
+
+impl fmt::Debug for LocalModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalModel")
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_nn::VarMap;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// A two-layer BERT with random weights and a word-level tokenizer.
+    fn tiny_model_dir() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("mpipe-local-model-test-{nanos}"));
+        fs::create_dir_all(&dir).expect("model dir");
+
+        let config = Config {
+            vocab_size: 6,
+            hidden_size: 8,
+            num_hidden_layers: 2,
+            num_attention_heads: 2,
+            intermediate_size: 16,
+            max_position_embeddings: 16,
+            ..Config::default()
+        };
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DTYPE, &Device::Cpu);
+        BertModel::load(vb, &config).expect("init model");
+        varmap
+            .save(dir.join("model.safetensors"))
+            .expect("save weights");
+        let config = serde_json::json!({
+            "vocab_size": 6, "hidden_size": 8, "num_hidden_layers": 2,
+            "num_attention_heads": 2, "intermediate_size": 16, "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.1, "max_position_embeddings": 16,
+            "type_vocab_size": 2, "initializer_range": 0.02, "layer_norm_eps": 1e-12,
+            "pad_token_id": 0, "model_type": "bert"
+        });
+        fs::write(dir.join("config.json"), config.to_string()).expect("write config");
+        let tokenizer = r#"{
+            "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+            "normalizer": {"type": "Lowercase"}, "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": null, "decoder": null,
+            "model": {"type": "WordLevel", "vocab": {"[PAD]": 0, "[UNK]": 1, "cats": 2, "purr": 3, "rust": 4, "compiles": 5}, "unk_token": "[UNK]"}
+        }"#;
+        fs::write(dir.join("tokenizer.json"), tokenizer).expect("write tokenizer");
+        dir
+    }
+
+    #[test]
+    fn local_models_mean_pool_and_normalize() {
+        let dir = tiny_model_dir();
+        let model = LocalModel::load(&dir).expect("load model");
+        let inputs = ["Cats purr", "Rust compiles fast", "cats purr"].map(String::from);
+
+        let embeddings = model.embed(&inputs).expect("embed");
+        assert_eq!(embeddings.tokens, 7);
+        assert_eq!(embeddings.vectors.len(), 3);
+        for vector in &embeddings.vectors {
+            assert_eq!(vector.len(), 8);
+            let norm = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-5, "{norm}");
+        }
+        // Padding does not change the vector of the shorter input.
+        let alone = model.embed(&inputs[..1]).expect("embed alone");
+        for (a, b) in alone.vectors[0].iter().zip(&embeddings.vectors[2]) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        fs::remove_file(dir.join("model.safetensors")).expect("remove weights");
+        fs::write(dir.join("model.onnx"), b"").expect("write onnx");
+        let onnx = LocalModel::load(&dir);
+        assert!(onnx.is_err_and(|err| err.contains("ONNX weights are not supported")));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
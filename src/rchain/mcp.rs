@@ -0,0 +1,325 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use crate::config::McpServerConfig;
+use crate::rchain::command_tools::truncate_output;
+
+/// MCP revision announced during the initialize handshake.
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_MAX_OUTPUT_BYTES: usize = 64 * 1024;
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Tool advertised by an MCP server in `tools/list`.
+#[derive(Debug, Clone)]
+pub struct McpTool {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments (`inputSchema`).
+    pub input_schema: Value,
+    /// Whether the server annotates the tool as free of side effects.
+    pub read_only: bool,
+}
+
+#[derive(Debug)]
+struct Connection {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+/// Client for one MCP server spawned as a child process speaking JSON-RPC
+/// over stdio (one message per line).
+///
+/// Requests are serialized: a server handles one call from mpipe at a time.
+#[derive(Debug)]
+pub struct McpServer {
+    /// Server name from the `[mcp.<name>]` section.
+    pub name: String,
+    connection: Mutex<Connection>,
+    timeout: Duration,
+    max_output_bytes: usize,
+}
+
+impl McpServer {
+    /// Spawns the server and performs the initialize handshake.
+    pub async fn start(name: &str, config: &McpServerConfig) -> Result<Self, String> {
+        let (program, args) = config
+            .command
+            .split_first()
+            .ok_or_else(|| format!("MCP server '{name}' has an empty command."))?;
+
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true);
+        if let Some(cwd) = &config.cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("Failed to start MCP server '{name}' ({program}): {err}"))?;
+        let stdin = child.stdin.take();
+        let stdout = child
+            .stdout
+            .take()
+            .map(BufReader::new)
+            .ok_or_else(|| format!("MCP server '{name}' has no stdout."))?;
+
+        let server = Self {
+            name: name.to_string(),
+            connection: Mutex::new(Connection {
+                child,
+                stdin,
+                stdout,
+                next_id: 1,
+            }),
+            timeout: Duration::from_secs(config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            max_output_bytes: config.max_output_bytes.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES),
+        };
+
+        let result = server
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {"name": "mpipe", "version": env!("CARGO_PKG_VERSION")},
+                }),
+            )
+            .await
+            .map_err(|err| format!("MCP server '{name}' failed to initialize: {err}"))?;
+        if result
+            .get("protocolVersion")
+            .and_then(Value::as_str)
+            .is_none()
+        {
+            return Err(format!(
+                "MCP server '{name}' returned an invalid initialize result: {result}"
+            ));
+        }
+        server
+            .notify("notifications/initialized", json!({}))
+            .await
+            .map_err(|err| format!("MCP server '{name}' failed to initialize: {err}"))?;
+
+        Ok(server)
+    }
+
+    /// Fetches every tool the server exposes, following pagination cursors.
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>, String> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({"cursor": cursor}),
+                None => json!({}),
+            };
+            let result = self
+                .request("tools/list", params)
+                .await
+                .map_err(|err| format!("MCP server '{}': tools/list failed: {err}", self.name))?;
+
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                let Some(name) = tool["name"].as_str() else {
+                    continue;
+                };
+                tools.push(McpTool {
+                    name: name.to_string(),
+                    description: tool["description"].as_str().unwrap_or_default().to_string(),
+                    input_schema: match &tool["inputSchema"] {
+                        Value::Object(_) => tool["inputSchema"].clone(),
+                        _ => json!({"type": "object", "properties": {}}),
+                    },
+                    read_only: tool["annotations"]["readOnlyHint"]
+                        .as_bool()
+                        .unwrap_or(false),
+                });
+            }
+
+            match result["nextCursor"].as_str() {
+                Some(next) if !next.is_empty() => cursor = Some(next.to_string()),
+                _ => return Ok(tools),
+            }
+        }
+    }
+
+    /// Calls a tool and flattens its content blocks into text.
+    ///
+    /// A result flagged `isError` is returned as an error so the model sees
+    /// it as a failed call.
+    pub async fn call_tool(&self, name: &str, arguments: &Value) -> Result<String, String> {
+        let arguments = match arguments {
+            Value::Null => json!({}),
+            other => other.clone(),
+        };
+        let result = self
+            .request("tools/call", json!({"name": name, "arguments": arguments}))
+            .await?;
+
+        let text = truncate_output(content_to_text(&result["content"]), self.max_output_bytes);
+        if result["isError"].as_bool().unwrap_or(false) {
+            return Err(text);
+        }
+        Ok(text)
+    }
+
+    /// Closes stdin and waits briefly for the server to exit, killing it
+    /// otherwise.
+    pub async fn shutdown(&self) {
+        let mut connection = self.connection.lock().await;
+        drop(connection.stdin.take());
+        let exited = tokio::time::timeout(SHUTDOWN_GRACE, connection.child.wait()).await;
+        if !matches!(exited, Ok(Ok(_))) {
+            let _ = connection.child.kill().await;
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), String> {
+        let mut connection = self.connection.lock().await;
+        write_message(
+            &mut connection,
+            &json!({"jsonrpc": "2.0", "method": method, "params": params}),
+        )
+        .await
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, String> {
+        let mut connection = self.connection.lock().await;
+        let id = connection.next_id;
+        connection.next_id += 1;
+
+        write_message(
+            &mut connection,
+            &json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}),
+        )
+        .await?;
+
+        tokio::time::timeout(self.timeout, read_response(&mut connection, id))
+            .await
+            .map_err(|_| format!("{method} timed out after {}s", self.timeout.as_secs()))?
+    }
+}
+
+async fn write_message(connection: &mut Connection, message: &Value) -> Result<(), String> {
+    let stdin = connection
+        .stdin
+        .as_mut()
+        .ok_or_else(|| "connection is closed".to_string())?;
+    let mut line = message.to_string();
+    line.push('\n');
+    stdin
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|err| format!("failed to write to server: {err}"))?;
+    stdin
+        .flush()
+        .await
+        .map_err(|err| format!("failed to write to server: {err}"))
+}
+
+/// Reads messages until the response to `id`, answering pings and skipping
+/// notifications on the way.
+async fn read_response(connection: &mut Connection, id: u64) -> Result<Value, String> {
+    loop {
+        let mut line = String::new();
+        let read = connection
+            .stdout
+            .read_line(&mut line)
+            .await
+            .map_err(|err| format!("failed to read from server: {err}"))?;
+        if read == 0 {
+            return Err("server closed the connection".to_string());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message: Value = serde_json::from_str(&line)
+            .map_err(|err| format!("server sent invalid JSON ({err}): {}", line.trim()))?;
+
+        if let Some(method) = message["method"].as_str() {
+            if !message["id"].is_null() {
+                let reply = if method == "ping" {
+                    json!({"jsonrpc": "2.0", "id": message["id"], "result": {}})
+                } else {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": {"code": -32601, "message": format!("method not found: {method}")},
+                    })
+                };
+                write_message(connection, &reply).await?;
+            }
+            continue;
+        }
+
+        if message["id"].as_u64() != Some(id) {
+            continue;
+        }
+        if let Some(error) = message.get("error") {
+            let text = error["message"].as_str().unwrap_or("unknown error");
+            return Err(match error["code"].as_i64() {
+                Some(code) => format!("{text} (code {code})"),
+                None => text.to_string(),
+            });
+        }
+        return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+    }
+}
+
+fn content_to_text(content: &Value) -> String {
+    content
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|block| match block["type"].as_str() {
+            Some("text") => block["text"].as_str().unwrap_or_default().to_string(),
+            Some("resource") => match block["resource"]["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!(
+                    "[resource {}]",
+                    block["resource"]["uri"].as_str().unwrap_or("?")
+                ),
+            },
+            Some(kind) => format!(
+                "[{kind} {}]",
+                block["mimeType"].as_str().unwrap_or("content")
+            ),
+            None => block.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_blocks_are_flattened_to_text() {
+        let content = json!([
+            {"type": "text", "text": "first"},
+            {"type": "image", "data": "AAAA", "mimeType": "image/png"},
+            {"type": "resource", "resource": {"uri": "file:///a.txt", "text": "inline"}},
+            {"type": "resource", "resource": {"uri": "file:///b.bin", "blob": "AAAA"}},
+        ]);
+        assert_eq!(
+            content_to_text(&content),
+            "first\n[image image/png]\ninline\n[resource file:///b.bin]"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use chromadb::collection::{ChromaCollection, CollectionEntries, GetOptions, QueryOptions};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::rchain::collection_meta::{EmbeddingSpec, ensure_compatible, query_settings};
+use crate::rchain::embeddings::{EmbeddingProvider, embed_chunks_with_provider};
+use crate::rchain::knowledge::embed_query;
+use crate::rchain::provider::{ChatMessage, MessageContent};
+use crate::rchain::vector_transform::VectorTransform;
+
+/// Memories injected into the system message at session start.
+pub const DEFAULT_RECALL: usize = 5;
+/// Characters of a single message kept when a session is summarized for
+/// fact extraction.
+const EXTRACTION_MESSAGE_CHARS: usize = 2000;
+
+/// One remembered fact.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryEntry {
+    pub id: String,
+    pub fact: String,
+    pub session_id: Option<String>,
+    /// Unix time in milliseconds when the fact was stored.
+    pub ts_ms: Option<u64>,
+    /// Distance to the query, for recalled entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f32>,
+}
+
+/// Long-term agent memory kept in a ChromaDB collection.
+///
+/// Each fact is one entry with `kind = "memory"`, the id of the session that
+/// produced it and a timestamp in its metadata.
+#[derive(Debug)]
+pub struct MemoryStore {
+    collection: ChromaCollection,
+    provider: EmbeddingProvider,
+    embedding_model: String,
+    /// Embedding settings recorded on the collection.
+    spec: EmbeddingSpec,
+}
+
+impl MemoryStore {
+    /// Uses `provider` and `embedding_model`, or those the collection
+    /// records, refusing settings that differ from the recorded ones. A
+    /// collection recording none, such as a new one, records them.
+    pub async fn open(
+        collection: ChromaCollection,
+        provider: Option<EmbeddingProvider>,
+        embedding_model: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut store = Self::inspect(collection, provider, embedding_model)?;
+        if store.spec.model.is_none() {
+            store.spec = EmbeddingSpec {
+                dimension: store.spec.dimension,
+                ..EmbeddingSpec::new(store.provider, &store.embedding_model, None)
+            };
+            record_spec(&store.collection, &store.spec).await?;
+        }
+        Ok(store)
+    }
+
+    /// Like [`MemoryStore::open`], but never writes the settings to the
+    /// collection, for commands that only read it.
+    pub fn inspect(
+        collection: ChromaCollection,
+        provider: Option<EmbeddingProvider>,
+        embedding_model: Option<&str>,
+    ) -> Result<Self, String> {
+        let spec = EmbeddingSpec::from_metadata(collection.metadata());
+        let (provider, embedding_model) =
+            query_settings(collection.name(), &spec, provider, embedding_model, false)?;
+        Ok(Self {
+            collection,
+            provider,
+            embedding_model,
+            spec,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.collection.name()
+    }
+
+    /// Returns the `top_k` memories closest to `query`, best first.
+    pub async fn recall(&self, query: &str, top_k: usize) -> Result<Vec<MemoryEntry>, String> {
+        if self.count().await? == 0 {
+            return Ok(Vec::new());
+        }
+
+        let embedding = embed_query(
+            self.provider,
+            &self.embedding_model,
+            query,
+            &VectorTransform::default(),
+        )
+        .await?;
+        self.ensure_dimension(embedding.len())?;
+
+        let result = self
+            .collection
+            .query(
+                QueryOptions {
+                    query_embeddings: Some(vec![embedding]),
+                    query_texts: None,
+                    n_results: Some(top_k),
+                    where_metadata: None,
+                    where_document: None,
+                    include: Some(vec!["metadatas", "documents", "distances"]),
+                },
+                None,
+            )
+            .await
+            .map_err(|err| format!("Failed to query memory '{}': {err}", self.name()))?;
+
+        let ids = result.ids.first().cloned().unwrap_or_default();
+        let documents = result
+            .documents
+            .as_ref()
+            .and_then(|all| all.first())
+            .cloned()
+            .unwrap_or_default();
+        let metadatas = result
+            .metadatas
+            .as_ref()
+            .and_then(|all| all.first())
+            .cloned()
+            .unwrap_or_default();
+        let distances = result
+            .distances
+            .as_ref()
+            .and_then(|all| all.first())
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(ids
+            .into_iter()
+            .enumerate()
+            .map(|(idx, id)| {
+                let mut entry = entry_from_parts(
+                    id,
+                    documents.get(idx).cloned(),
+                    metadatas.get(idx).cloned().flatten().as_ref(),
+                );
+                entry.distance = distances.get(idx).copied();
+                entry
+            })
+            .collect())
+    }
+
+    /// Embeds and stores facts as `mem-<session>-<n>`, replacing whatever
+    /// the same session stored before. Returns the number stored.
+    pub async fn remember(
+        &self,
+        facts: &[String],
+        session_id: &str,
+        ts_ms: u64,
+    ) -> Result<usize, String> {
+        if facts.is_empty() {
+            return Ok(0);
+        }
+
+        let embeddings = embed_chunks_with_provider(self.provider, &self.embedding_model, facts)
+            .await
+            .map_err(|err| format!("Failed to embed memories: {err}"))?
+            .into_iter()
+            .map(|vector| vector.into_iter().map(|value| value as f32).collect())
+            .collect::<Vec<Vec<f32>>>();
+        if embeddings.len() != facts.len() {
+            return Err(format!(
+                "Embedding provider returned {} vectors for {} memories.",
+                embeddings.len(),
+                facts.len()
+            ));
+        }
+        let dimension = embeddings.first().map_or(0, Vec::len);
+        self.ensure_dimension(dimension)?;
+
+        let ids = (0..facts.len())
+            .map(|index| format!("mem-{session_id}-{index}"))
+            .collect::<Vec<_>>();
+        let metadatas = facts
+            .iter()
+            .map(|_| {
+                let mut metadata = Map::new();
+                metadata.insert("kind".to_string(), json!("memory"));
+                metadata.insert("session_id".to_string(), json!(session_id));
+                metadata.insert("ts_ms".to_string(), json!(ts_ms));
+                metadata
+            })
+            .collect();
+
+        forget_memories(&self.collection, &[], Some(session_id)).await?;
+        self.collection
+            .upsert(
+                CollectionEntries {
+                    ids: ids.iter().map(String::as_str).collect(),
+                    metadatas: Some(metadatas),
+                    documents: Some(facts.iter().map(String::as_str).collect()),
+                    embeddings: Some(embeddings),
+                },
+                None,
+            )
+            .await
+            .map_err(|err| format!("Failed to store memories in '{}': {err}", self.name()))?;
+        if self.spec.dimension.is_none() {
+            let spec = EmbeddingSpec {
+                dimension: Some(dimension),
+                ..self.spec.clone()
+            };
+            record_spec(&self.collection, &spec).await?;
+        }
+        Ok(facts.len())
+    }
+
+    async fn count(&self) -> Result<usize, String> {
+        self.collection
+            .count()
+            .await
+            .map_err(|err| format!("Failed to read memory '{}': {err}", self.name()))
+    }
+
+    /// Refuses vectors unlike the ones the collection holds.
+    fn ensure_dimension(&self, dimension: usize) -> Result<(), String> {
+        ensure_compatible(
+            self.name(),
+            &self.spec,
+            &EmbeddingSpec {
+                dimension: Some(dimension),
+                ..EmbeddingSpec::default()
+            },
+            false,
+        )
+    }
+}
+
+/// Records `spec` in the metadata of a memory collection.
+async fn record_spec(collection: &ChromaCollection, spec: &EmbeddingSpec) -> Result<(), String> {
+    collection
+        .modify(None, Some(&spec.to_metadata(collection.metadata())))
+        .await
+        .map_err(|err| {
+            format!(
+                "Failed to update metadata of memory '{}': {err}",
+                collection.name()
+            )
+        })
+}
+
+/// Lists stored memories newest first, optionally only those of one
+/// session, skipping `offset` entries.
+///
+/// ChromaDB pages in insertion order, so every entry is fetched and sorted
+/// before paging.
+pub async fn list_memories(
+    collection: &ChromaCollection,
+    session_id: Option<&str>,
+    limit: usize,
+    offset: usize,
+) -> Result<Vec<MemoryEntry>, String> {
+    let result = collection
+        .get(GetOptions {
+            ids: Vec::new(),
+            where_metadata: session_id.map(|session| json!({"session_id": session})),
+            limit: None,
+            offset: None,
+            where_document: None,
+            include: Some(vec!["metadatas".to_string(), "documents".to_string()]),
+        })
+        .await
+        .map_err(|err| format!("Failed to list memory '{}': {err}", collection.name()))?;
+
+    let entries = result
+        .ids
+        .iter()
+        .enumerate()
+        .map(|(idx, id)| {
+            entry_from_parts(
+                id.clone(),
+                result
+                    .documents
+                    .as_ref()
+                    .and_then(|all| all.get(idx))
+                    .cloned()
+                    .flatten(),
+                result
+                    .metadatas
+                    .as_ref()
+                    .and_then(|all| all.get(idx))
+                    .and_then(|item| item.as_ref()),
+            )
+        })
+        .collect();
+    Ok(newest_page(entries, limit, offset))
+}
+
+fn newest_page(mut entries: Vec<MemoryEntry>, limit: usize, offset: usize) -> Vec<MemoryEntry> {
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.ts_ms));
+    entries.into_iter().skip(offset).take(limit).collect()
+}
+
+/// Deletes memories by id, or every memory of a session.
+pub async fn forget_memories(
+    collection: &ChromaCollection,
+    ids: &[String],
+    session_id: Option<&str>,
+) -> Result<(), String> {
+    let result = match session_id {
+        Some(session) => {
+            collection
+                .delete(None, Some(json!({"session_id": session})), None)
+                .await
+        }
+        None => {
+            collection
+                .delete(Some(ids.iter().map(String::as_str).collect()), None, None)
+                .await
+        }
+    };
+    result.map_err(|err| {
+        format!(
+            "Failed to forget memories in '{}': {err}",
+            collection.name()
+        )
+    })
+}
+
+fn entry_from_parts(
+    id: String,
+    document: Option<String>,
+    metadata: Option<&Map<String, Value>>,
+) -> MemoryEntry {
+    MemoryEntry {
+        id,
+        fact: document.unwrap_or_default(),
+        session_id: metadata
+            .and_then(|m| m.get("session_id"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        ts_ms: metadata
+            .and_then(|m| m.get("ts_ms"))
+            .and_then(Value::as_u64),
+        distance: None,
+    }
+}
+
+/// Appends recalled memories to a system prompt.
+pub fn with_memories(system: Option<&str>, memories: &[MemoryEntry]) -> Option<String> {
+    if memories.is_empty() {
+        return system.map(str::to_string);
+    }
+    let facts = memories
+        .iter()
+        .map(|memory| format!("- {}", memory.fact.trim()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let block = format!("Facts remembered from earlier sessions (they may be outdated):\n{facts}");
+    Some(match system {
+        Some(system) => format!("{system}\n\n{block}"),
+        None => block,
+    })
+}
+
+/// Messages asking the model for the durable facts of a finished session.
+pub fn extraction_messages(conversation: &[ChatMessage]) -> Vec<ChatMessage> {
+    let rendered = conversation
+        .iter()
+        .filter(|message| message.role != "system")
+        .map(|message| {
+            let text = match &message.content {
+                MessageContent::Simple(text) => text.clone(),
+                MessageContent::Multi(_) => "[multimodal content]".to_string(),
+            };
+            let text = match text.char_indices().nth(EXTRACTION_MESSAGE_CHARS) {
+                Some((cut, _)) => format!("{}...", &text[..cut]),
+                None => text,
+            };
+            format!("[{}] {}", message.role, text.trim())
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    vec![
+        ChatMessage::system(MessageContent::text(
+            "You maintain the long-term memory of an assistant. From the session below, \
+             extract the facts worth remembering in future sessions: stable preferences, \
+             decisions, names, locations of things and conclusions reached. Skip anything \
+             transient or specific to this session only. Write each fact as one short, \
+             self-contained sentence. Reply with only a JSON array of strings, or [] if \
+             nothing is worth keeping.",
+        )),
+        ChatMessage::user(MessageContent::text(format!("Session:\n{rendered}"))),
+    ]
+}
+
+/// Reads the fact list from the extraction reply, tolerating text around
+/// the JSON array.
+pub fn parse_facts(content: &str) -> Result<Vec<String>, String> {
+    let start = content.find('[');
+    let end = content.rfind(']');
+    let json = match (start, end) {
+        (Some(start), Some(end)) if start < end => &content[start..=end],
+        _ => return Err("no JSON array found".to_string()),
+    };
+    let facts: Vec<Value> =
+        serde_json::from_str(json).map_err(|err| format!("invalid JSON array: {err}"))?;
+    Ok(facts
+        .iter()
+        .filter_map(Value::as_str)
+        .map(str::trim)
+        .filter(|fact| !fact.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn facts_are_parsed_from_surrounding_text() {
+        let reply = "Here you go:\n```json\n[\"User prefers metric units.\", \"  \", 3, \
+                     \"Repo lives in /srv/app.\"]\n```";
+        assert_eq!(
+            parse_facts(reply).expect("facts"),
+            vec!["User prefers metric units.", "Repo lives in /srv/app."]
+        );
+        assert_eq!(parse_facts("[]").expect("empty"), Vec::<String>::new());
+        assert!(parse_facts("nothing to remember").is_err());
+    }
+
+    #[test]
+    fn listing_pages_after_sorting_newest_first() {
+        let entry = |id: &str, ts_ms| MemoryEntry {
+            id: id.to_string(),
+            fact: String::new(),
+            session_id: None,
+            ts_ms,
+            distance: None,
+        };
+        let entries = vec![
+            entry("c", Some(3)),
+            entry("none", None),
+            entry("a", Some(9)),
+            entry("b", Some(5)),
+        ];
+        let ids = |page: Vec<MemoryEntry>| page.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids(newest_page(entries.clone(), 2, 0)), ["a", "b"]);
+        assert_eq!(ids(newest_page(entries, 5, 2)), ["c", "none"]);
+    }
+
+    #[test]
+    fn recalled_memories_extend_the_system_prompt() {
+        let memory = MemoryEntry {
+            id: "mem-a-0".to_string(),
+            fact: "User prefers metric units.".to_string(),
+            session_id: Some("a".to_string()),
+            ts_ms: Some(1),
+            distance: Some(0.1),
+        };
+        assert_eq!(
+            with_memories(Some("Be brief."), std::slice::from_ref(&memory)).as_deref(),
+            Some(
+                "Be brief.\n\nFacts remembered from earlier sessions (they may be outdated):\n\
+                 - User prefers metric units."
+            )
+        );
+        assert_eq!(with_memories(None, &[]), None);
+    }
+}
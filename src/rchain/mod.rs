@@ -8,15 +8,50 @@ pub mod ai;
 /// Chat model client abstractions.
 pub mod chat_models;
 pub(crate) mod chat_runtime;
+/// Text chunkers shared by `embed` and `index`.
+pub mod chunking;
+/// Syntax-aware chunking of source files for the `code` strategy.
+pub mod code_chunking;
+/// Embedding settings recorded on ChromaDB collections.
+pub mod collection_meta;
+/// Shell-command tools declared in the config file.
+pub mod command_tools;
 /// Embedding model client abstractions.
 pub mod embeddings;
 /// Fireworks chat-completions helper functions.
 pub mod fireworks;
 /// Human/user message helper types.
 pub mod human;
+/// ChromaDB retrieval shared by `grep` and the `search_knowledge` tool.
+pub mod knowledge;
+/// CPU inference of sentence-transformers models for the `local` embedding
+/// provider.
+pub mod local_embeddings;
+/// Stdio client for Model Context Protocol tool servers.
+pub mod mcp;
+/// Long-term agent memory stored in a ChromaDB collection.
+pub mod memory;
 /// OpenAI chat-completions helper functions.
 pub mod openai;
 /// Provider-agnostic chat interfaces and dispatch.
 pub mod provider;
+/// Resource-limited execution of model-written Python and shell code.
+pub mod sandbox;
+/// Embedding-based topic chunking for the `semantic` strategy.
+pub mod semantic_chunking;
+/// Prompt-based tool calling for models without function calling.
+pub mod text_tools;
+/// Tokenizers sizing `token` chunks.
+pub mod tokenizer;
+/// Approval, workspace and command restrictions for tool calls.
+pub mod tool_policy;
+/// Tool registry and execution for agent loops.
+pub mod toolbox;
 /// Tool schema and invocation payload helpers.
 pub mod tools;
+/// Agent run transcripts: recording, reading and resuming.
+pub mod transcript;
+/// Embedding matrices as JSONL, `.npy`, raw `f32` and CSV.
+pub mod vector_io;
+/// Truncation, normalization and quantization of embeddings.
+pub mod vector_transform;
@@ -3,7 +3,9 @@ use std::fmt;
 
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+use crate::rchain::tools::{ToolCall, ToolDefinition};
 use crate::rchain::{fireworks, openai};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -106,21 +108,51 @@ pub struct ImageUrl {
 pub struct ChatMessage {
     pub role: String,
     pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 impl ChatMessage {
-    pub fn system(content: impl Into<MessageContent>) -> Self {
+    fn with_role(role: &str, content: MessageContent) -> Self {
         Self {
-            role: "system".to_string(),
-            content: content.into(),
+            role: role.to_string(),
+            content,
+            tool_calls: None,
+            tool_call_id: None,
         }
     }
 
+    pub fn system(content: impl Into<MessageContent>) -> Self {
+        Self::with_role("system", content.into())
+    }
+
     pub fn user(content: impl Into<MessageContent>) -> Self {
-        Self {
-            role: "user".to_string(),
-            content: content.into(),
+        Self::with_role("user", content.into())
+    }
+
+    pub fn assistant(content: impl Into<MessageContent>) -> Self {
+        Self::with_role("assistant", content.into())
+    }
+
+    /// Assistant turn that requested tool calls.
+    pub fn assistant_with_tool_calls(
+        content: impl Into<MessageContent>,
+        calls: &[ToolCall],
+    ) -> Self {
+        let mut message = Self::assistant(content);
+        if !calls.is_empty() {
+            message.tool_calls = Some(calls.iter().map(ToolCall::to_json).collect());
         }
+        message
+    }
+
+    /// Result of one tool call, answering `tool_call_id`.
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<MessageContent>) -> Self {
+        let mut message = Self::with_role("tool", content.into());
+        message.tool_call_id = Some(tool_call_id.into());
+        message
     }
 
     pub fn user_with_text(text: impl Into<String>) -> Self {
@@ -195,7 +227,7 @@ impl Default for AskOptions {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: Option<u32>,
     pub completion_tokens: Option<u32>,
@@ -206,6 +238,7 @@ pub struct Usage {
 pub struct AskResponse {
     pub content: String,
     pub usage: Option<Usage>,
+    pub tool_calls: Vec<ToolCall>,
 }
 
 #[derive(Debug)]
@@ -268,8 +301,25 @@ pub async fn ask(
     messages: &[ChatMessage],
     options: AskOptions,
 ) -> Result<AskResponse, ProviderError> {
+    ask_with_tools(provider, model, messages, &[], options).await
+}
+
+/// Like [`ask`], but declares `tools` so the model may answer with tool calls.
+pub async fn ask_with_tools(
+    provider: Provider,
+    model: &str,
+    messages: &[ChatMessage],
+    tools: &[ToolDefinition],
+    options: AskOptions,
+) -> Result<AskResponse, ProviderError> {
+    let tools = tools
+        .iter()
+        .map(ToolDefinition::to_json)
+        .collect::<Vec<_>>();
     match provider {
-        Provider::Openai => openai::ask_messages(messages, model, options).await,
-        Provider::Fireworks => fireworks::ask_messages(messages, model, options).await,
+        Provider::Openai => openai::ask_messages_with_tools(messages, &tools, model, options).await,
+        Provider::Fireworks => {
+            fireworks::ask_messages_with_tools(messages, &tools, model, options).await
+        }
     }
 }
@@ -0,0 +1,302 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+use tempfile::TempDir;
+use tokio::process::Command;
+
+use crate::rchain::command_tools::{read_capped, truncate_dropping};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_CPU_SECS: u64 = 10;
+const DEFAULT_MEMORY_MB: u64 = 512;
+const DEFAULT_MAX_OUTPUT_BYTES: usize = 16 * 1024;
+
+/// Fragments of environment variable names that are never passed to
+/// sandboxed code.
+const SECRET_MARKERS: [&str; 6] = ["KEY", "TOKEN", "SECRET", "PASSWORD", "CREDENTIAL", "AUTH"];
+
+/// Interpreter a sandboxed snippet runs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SandboxLanguage {
+    Python,
+    Shell,
+}
+
+impl SandboxLanguage {
+    /// Name of the agent tool running this language.
+    pub fn tool_name(self) -> &'static str {
+        match self {
+            Self::Python => "run_python",
+            Self::Shell => "run_shell",
+        }
+    }
+
+    fn script_name(self) -> &'static str {
+        match self {
+            Self::Python => "main.py",
+            Self::Shell => "main.sh",
+        }
+    }
+
+    /// Program the script is run with, checked against the command lists
+    /// of the tool policy.
+    pub fn interpreter(self) -> &'static str {
+        match self {
+            Self::Python => "python3",
+            Self::Shell => "sh",
+        }
+    }
+}
+
+/// Resource limits of one sandboxed run.
+#[derive(Debug, Clone, Copy)]
+pub struct SandboxLimits {
+    /// Wall-clock limit; the process is killed when it expires.
+    pub timeout: Duration,
+    /// CPU time limit (`RLIMIT_CPU`).
+    pub cpu_secs: u64,
+    /// Address-space limit (`RLIMIT_AS`) in MiB.
+    pub memory_mb: u64,
+    /// Bytes kept from stdout and from stderr each.
+    pub max_output_bytes: usize,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            cpu_secs: DEFAULT_CPU_SECS,
+            memory_mb: DEFAULT_MEMORY_MB,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+}
+
+/// Runs model-written code in a fresh temporary directory and returns its
+/// output in the format of command tools.
+///
+/// Limits are applied with `ulimit` in a wrapping shell before the
+/// interpreter is exec'd, and environment variables that look like
+/// credentials are removed. This contains mistakes, not hostile code: the
+/// process still runs as the current user with network access.
+pub async fn run_code(
+    language: SandboxLanguage,
+    code: &str,
+    limits: &SandboxLimits,
+) -> Result<String, String> {
+    let dir = create_work_dir()?;
+    run_in(dir.path(), language, code, limits).await
+}
+
+async fn run_in(
+    dir: &Path,
+    language: SandboxLanguage,
+    code: &str,
+    limits: &SandboxLimits,
+) -> Result<String, String> {
+    let script = dir.join(language.script_name());
+    fs::write(&script, code).map_err(|err| format!("failed to write the script: {err}"))?;
+
+    let wrapper = format!(
+        "ulimit -t {} && ulimit -v {} && exec \"$@\"",
+        limits.cpu_secs,
+        limits.memory_mb * 1024
+    );
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(wrapper)
+        .arg("sh")
+        .arg(language.interpreter())
+        .arg(language.script_name())
+        .current_dir(dir)
+        .env_clear()
+        .envs(sandbox_env(env::vars(), dir))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // Its own process group, so that whatever the code starts in the
+    // background is killed with it.
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let mut child = command
+        .spawn()
+        .map_err(|err| format!("failed to start '{}': {err}", language.interpreter()))?;
+    let group = child.id();
+    let max_bytes = limits.max_output_bytes;
+    let stdout = tokio::spawn(read_capped(child.stdout.take(), max_bytes));
+    let stderr = tokio::spawn(read_capped(child.stderr.take(), max_bytes));
+    let status = tokio::time::timeout(limits.timeout, child.wait()).await;
+    // Also after a normal exit: background processes would otherwise keep
+    // running and hold the output pipes open.
+    if let Some(group) = group {
+        kill_process_group(group);
+    }
+    let status = status
+        .map_err(|_| format!("timed out after {}s", limits.timeout.as_secs()))?
+        .map_err(|err| format!("failed to wait for '{}': {err}", language.interpreter()))?;
+    let read = |output: Result<std::io::Result<_>, _>| match output {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(err)) => Err(format!("failed to read the output: {err}")),
+        Err(err) => Err(format!("failed to read the output: {err}")),
+    };
+    let stdout = read(stdout.await)?;
+    let stderr = read(stderr.await)?;
+
+    let mut text = truncate_dropping(
+        String::from_utf8_lossy(&stdout.kept).into_owned(),
+        max_bytes,
+        stdout.dropped,
+    );
+    if !stderr.kept.is_empty() {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str("[stderr]\n");
+        text.push_str(&truncate_dropping(
+            String::from_utf8_lossy(&stderr.kept).into_owned(),
+            max_bytes,
+            stderr.dropped,
+        ));
+    }
+    if !status.success() {
+        text = format!("[{}]\n{text}", describe_failure(status));
+    }
+    Ok(text)
+}
+
+/// A new directory with an unpredictable name, removed when dropped.
+/// Creation fails rather than reuse a path that already exists.
+fn create_work_dir() -> Result<TempDir, String> {
+    tempfile::Builder::new()
+        .prefix("mpipe-sandbox-")
+        .tempdir()
+        .map_err(|err| format!("failed to create the work directory: {err}"))
+}
+
+/// Kills every process left in the group led by `pid`.
+fn kill_process_group(pid: u32) {
+    #[cfg(unix)]
+    // SAFETY: kill(2) only sends a signal; a group that is already gone
+    // makes it fail with ESRCH, which is ignored.
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// The inherited environment minus anything that looks like a credential,
+/// with `HOME` and `TMPDIR` pointing at the work directory.
+fn sandbox_env(
+    inherited: impl Iterator<Item = (String, String)>,
+    dir: &Path,
+) -> Vec<(String, String)> {
+    let mut vars = inherited
+        .filter(|(name, _)| !is_secret_name(name) && name != "HOME" && name != "TMPDIR")
+        .collect::<Vec<_>>();
+    let dir = dir.to_string_lossy().into_owned();
+    vars.push(("HOME".to_string(), dir.clone()));
+    vars.push(("TMPDIR".to_string(), dir));
+    vars
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|marker| upper.contains(marker))
+}
+
+fn describe_failure(status: ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        match status.signal() {
+            Some(24) => return "killed: CPU time limit exceeded".to_string(),
+            Some(signal) => return format!("killed by signal {signal}"),
+            None => {}
+        }
+    }
+    status.code().map_or_else(
+        || "exit status unknown".to_string(),
+        |code| format!("exit status {code}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_not_inherited() {
+        let inherited = [
+            ("PATH", "/usr/bin"),
+            ("OPENAI_API_KEY", "sk-test"),
+            ("github_token", "ghp"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("HOME", "/home/me"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        let vars = sandbox_env(inherited.into_iter(), Path::new("/tmp/work"));
+        let names = vars
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["PATH", "HOME", "TMPDIR"]);
+        assert_eq!(vars[1].1, "/tmp/work");
+    }
+
+    #[tokio::test]
+    async fn shell_code_runs_in_a_temporary_directory() {
+        let output = run_code(
+            SandboxLanguage::Shell,
+            "pwd; echo oops >&2; exit 3",
+            &SandboxLimits::default(),
+        )
+        .await
+        .expect("script should run");
+
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "[exit status 3]");
+        assert!(lines[1].contains("mpipe-sandbox-"));
+        assert_eq!(&lines[2..], ["[stderr]", "oops"]);
+        assert!(!Path::new(lines[1]).exists());
+    }
+
+    #[tokio::test]
+    async fn limits_stop_runaway_code() {
+        let limits = SandboxLimits {
+            timeout: Duration::from_millis(300),
+            ..SandboxLimits::default()
+        };
+        let err = run_code(SandboxLanguage::Shell, "sleep 5", &limits)
+            .await
+            .expect_err("sleep should time out");
+        assert!(err.contains("timed out"));
+
+        // The work directory is gone by then, so the marker goes elsewhere.
+        let marker = env::temp_dir().join(format!("mpipe-sandbox-bg-{}", std::process::id()));
+        let script = format!("(sleep 1; touch '{}') &\nsleep 5", marker.display());
+        let err = run_code(SandboxLanguage::Shell, &script, &limits)
+            .await
+            .expect_err("sleep should time out");
+        assert!(err.contains("timed out"));
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(
+            !marker.exists(),
+            "a background process survived the timeout"
+        );
+
+        let limits = SandboxLimits {
+            cpu_secs: 1,
+            ..SandboxLimits::default()
+        };
+        let output = run_code(SandboxLanguage::Shell, "while :; do :; done", &limits)
+            .await
+            .expect("busy loop should be killed");
+        assert!(output.starts_with("[killed"), "{output}");
+    }
+}
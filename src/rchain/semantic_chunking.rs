@@ -0,0 +1,301 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::rchain::chunking::{CharOffsets, ChunkOptions, TextChunk, sentence_spans};
+use crate::rchain::embeddings::EmbeddingClient;
+use crate::rchain::provider::Usage;
+
+/// Sentences embedded on each side of a sentence, so that its vector
+/// reflects its context rather than the sentence alone.
+const NEIGHBOR_SENTENCES: usize = 1;
+
+/// When the distance between two neighbouring sentences starts a new chunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakpointThreshold {
+    /// Distances above this percentile (0-100) of all distances.
+    Percentile(f64),
+    /// Distances more than this many standard deviations above the mean.
+    StdDev(f64),
+}
+
+impl BreakpointThreshold {
+    /// Parses `percentile:95` or `stddev:1.5`.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "Invalid semantic threshold '{raw}'. Expected percentile:<0-100> or stddev:<number>."
+            )
+        };
+        let (kind, amount) = raw.trim().split_once(':').ok_or_else(invalid)?;
+        let amount: f64 = amount.trim().parse().map_err(|_| invalid())?;
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "percentile" if (0.0..=100.0).contains(&amount) => Ok(Self::Percentile(amount)),
+            "stddev" if amount.is_finite() => Ok(Self::StdDev(amount)),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The distance above which neighbours are cut apart.
+    fn cutoff(&self, distances: &[f64]) -> f64 {
+        match *self {
+            Self::Percentile(percentile) => {
+                let mut sorted = distances.to_vec();
+                sorted.sort_by(f64::total_cmp);
+                let rank = percentile / 100.0 * (sorted.len() - 1) as f64;
+                let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+                sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+            }
+            Self::StdDev(deviations) => {
+                let count = distances.len() as f64;
+                let mean = distances.iter().sum::<f64>() / count;
+                let variance = distances.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / count;
+                mean + deviations * variance.sqrt()
+            }
+        }
+    }
+}
+
+impl fmt::Display for BreakpointThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Percentile(percentile) => write!(f, "percentile:{percentile}"),
+            Self::StdDev(deviations) => write!(f, "stddev:{deviations}"),
+        }
+    }
+}
+
+/// Settings of the `semantic` strategy; `chunk_size` is the maximum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SemanticOptions {
+    pub threshold: BreakpointThreshold,
+    /// Characters a chunk reaches before a breakpoint may end it; a tenth of
+    /// `chunk_size` when unset.
+    pub min_chunk_size: Option<usize>,
+}
+
+impl SemanticOptions {
+    pub const DEFAULT: Self = Self {
+        threshold: BreakpointThreshold::Percentile(95.0),
+        min_chunk_size: None,
+    };
+}
+
+impl Default for SemanticOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Splits `text` where the topic changes: sentences are embedded with their
+/// neighbours by `client`, and a chunk ends where the cosine distance to the
+/// next sentence is above the threshold of `options.semantic`, as long as
+/// the chunk holds `min_chunk_size` characters. Chunks never exceed
+/// `chunk_size` unless a single sentence does. Overlap does not apply.
+pub async fn semantic_chunks(
+    text: &str,
+    options: &ChunkOptions,
+    client: &EmbeddingClient,
+) -> Result<(Vec<TextChunk>, Option<Usage>), String> {
+    let sentences = sentence_spans(text);
+    if sentences.len() < 2 {
+        return Ok((group_sentences(text, &sentences, &[], options), None));
+    }
+
+    let windows = (0..sentences.len())
+        .map(|i| {
+            let first = i.saturating_sub(NEIGHBOR_SENTENCES);
+            let last = (i + NEIGHBOR_SENTENCES).min(sentences.len() - 1);
+            text[sentences[first].start..sentences[last].end].to_string()
+        })
+        .collect::<Vec<_>>();
+    let embeddings = client
+        .embed(&windows)
+        .await
+        .map_err(|err| format!("Failed to embed sentences for semantic chunking: {err}"))?;
+
+    let distances = embeddings
+        .vectors
+        .windows(2)
+        .map(|pair| cosine_distance(&pair[0], &pair[1]))
+        .collect::<Vec<_>>();
+    let cutoff = options.semantic.threshold.cutoff(&distances);
+    let breakpoints = distances
+        .iter()
+        .map(|&distance| distance > cutoff)
+        .collect::<Vec<_>>();
+    Ok((
+        group_sentences(text, &sentences, &breakpoints, options),
+        embeddings.usage,
+    ))
+}
+
+/// Groups consecutive sentences into chunks. `breakpoints[i]` is set when
+/// a chunk should end after sentence `i`.
+fn group_sentences(
+    text: &str,
+    sentences: &[Range<usize>],
+    breakpoints: &[bool],
+    options: &ChunkOptions,
+) -> Vec<TextChunk> {
+    let offsets = CharOffsets::new(text);
+    let chars = |bytes: Range<usize>| offsets.char_at(bytes.end) - offsets.char_at(bytes.start);
+    let max = options.chunk_size;
+    let min = options
+        .semantic
+        .min_chunk_size
+        .unwrap_or(options.chunk_size / 10);
+
+    let mut groups: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+    for i in 0..sentences.len() {
+        let last = i + 1 == sentences.len();
+        let ends_here = last
+            || chars(sentences[start].start..sentences[i + 1].end) > max
+            || (breakpoints.get(i).copied().unwrap_or(false)
+                && chars(sentences[start].start..sentences[i].end) >= min);
+        if ends_here {
+            groups.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    // A short tail joins the previous chunk when both fit together.
+    if let [.., previous, tail] = groups.as_slice()
+        && chars(sentences[tail.start].start..sentences[tail.end - 1].end) < min
+        && chars(sentences[previous.start].start..sentences[tail.end - 1].end) <= max
+    {
+        let merged = previous.start..tail.end;
+        groups.truncate(groups.len() - 2);
+        groups.push(merged);
+    }
+
+    groups
+        .into_iter()
+        .map(|group| {
+            offsets.chunk(
+                text,
+                sentences[group.start].start..sentences[group.end - 1].end,
+            )
+        })
+        .collect()
+}
+
+fn cosine_distance(a: &[f64], b: &[f64]) -> f64 {
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        return 1.0;
+    }
+    1.0 - dot / denominator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rchain::chunking::ChunkStrategy;
+
+    fn options(chunk_size: usize, min_chunk_size: usize) -> ChunkOptions {
+        ChunkOptions {
+            strategy: ChunkStrategy::Semantic,
+            chunk_size,
+            overlap_percent: 0,
+            tokenizer: None,
+            semantic: SemanticOptions {
+                threshold: BreakpointThreshold::Percentile(50.0),
+                min_chunk_size: Some(min_chunk_size),
+            },
+        }
+    }
+
+    #[test]
+    fn thresholds_parse_and_pick_a_cutoff() {
+        assert_eq!(
+            BreakpointThreshold::parse("percentile:95"),
+            Ok(BreakpointThreshold::Percentile(95.0))
+        );
+        assert_eq!(
+            BreakpointThreshold::parse(" StdDev:1.5 "),
+            Ok(BreakpointThreshold::StdDev(1.5))
+        );
+        for invalid in ["95", "percentile:120", "median:3", "stddev:x"] {
+            assert!(BreakpointThreshold::parse(invalid).is_err(), "{invalid}");
+        }
+
+        let distances = [0.1, 0.2, 0.3, 0.4, 0.5];
+        assert!((BreakpointThreshold::Percentile(50.0).cutoff(&distances) - 0.3).abs() < 1e-9);
+        assert!((BreakpointThreshold::Percentile(90.0).cutoff(&distances) - 0.46).abs() < 1e-9);
+        let stddev = BreakpointThreshold::StdDev(1.0).cutoff(&distances);
+        assert!((stddev - (0.3 + 0.02f64.sqrt())).abs() < 1e-9);
+
+        assert!(cosine_distance(&[1.0, 0.0], &[1.0, 0.0]).abs() < 1e-9);
+        assert!((cosine_distance(&[1.0, 0.0], &[0.0, 2.0]) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sentences_are_grouped_at_breakpoints_within_size_limits() {
+        let text = "Cats purr. Cats nap. Rust compiles. Rust is fast. Tea is hot.";
+        let sentences = sentence_spans(text);
+        let texts = |chunks: Vec<TextChunk>| {
+            chunks
+                .into_iter()
+                .map(|chunk| chunk.text)
+                .collect::<Vec<_>>()
+        };
+
+        // Topic changes after "Cats nap." and after "Rust is fast.".
+        let breakpoints = [false, true, false, true];
+        assert_eq!(
+            texts(group_sentences(
+                text,
+                &sentences,
+                &breakpoints,
+                &options(100, 0)
+            )),
+            [
+                "Cats purr. Cats nap.",
+                "Rust compiles. Rust is fast.",
+                "Tea is hot."
+            ]
+        );
+        // The short tail joins the previous chunk.
+        assert_eq!(
+            texts(group_sentences(
+                text,
+                &sentences,
+                &breakpoints,
+                &options(100, 15)
+            )),
+            [
+                "Cats purr. Cats nap.",
+                "Rust compiles. Rust is fast. Tea is hot."
+            ]
+        );
+        // Breakpoints before the minimum size are skipped.
+        assert_eq!(
+            texts(group_sentences(
+                text,
+                &sentences,
+                &breakpoints,
+                &options(55, 25)
+            )),
+            [
+                "Cats purr. Cats nap. Rust compiles. Rust is fast.",
+                "Tea is hot."
+            ]
+        );
+        // The maximum size cuts even without a breakpoint.
+        assert_eq!(
+            texts(group_sentences(
+                text,
+                &sentences,
+                &[false; 4],
+                &options(25, 0)
+            )),
+            [
+                "Cats purr. Cats nap.",
+                "Rust compiles.",
+                "Rust is fast. Tea is hot."
+            ]
+        );
+    }
+}
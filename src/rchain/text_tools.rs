@@ -0,0 +1,530 @@
+use serde_json::{Map, Value};
+
+use crate::rchain::provider::{ChatMessage, MessageContent};
+use crate::rchain::tools::{ToolCall, ToolDefinition};
+
+/// How tools are offered to the model and how calls are read back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolProtocol {
+    /// Provider function calling (`tools` / `tool_calls` fields).
+    Native,
+    /// Tools described in the system prompt, called with `CALL name(args)`.
+    Text,
+    /// ReAct transcript with `Action:` / `Action Input:` lines.
+    React,
+}
+
+impl ToolProtocol {
+    /// Parses a protocol name as written by [`ToolProtocol::as_str`].
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "native" => Some(Self::Native),
+            "text" => Some(Self::Text),
+            "react" => Some(Self::React),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Native => "native",
+            Self::Text => "text",
+            Self::React => "react",
+        }
+    }
+}
+
+/// What the model asked for in a text-protocol answer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextTurn {
+    /// A tool call to execute.
+    Call(ToolCall),
+    /// The final answer for the user.
+    Answer(String),
+}
+
+/// Adds tool instructions to the system message, creating one if needed.
+pub fn inject_instructions(
+    messages: &mut Vec<ChatMessage>,
+    protocol: ToolProtocol,
+    tools: &[ToolDefinition],
+) {
+    let instructions = match protocol {
+        ToolProtocol::Native => return,
+        ToolProtocol::Text => text_instructions(tools),
+        ToolProtocol::React => react_instructions(tools),
+    };
+
+    match messages.first_mut() {
+        Some(first) if first.role == "system" => {
+            if let MessageContent::Simple(text) = &mut first.content {
+                text.push_str("\n\n");
+                text.push_str(&instructions);
+                return;
+            }
+            messages.insert(0, ChatMessage::system(instructions));
+        }
+        _ => messages.insert(0, ChatMessage::system(instructions)),
+    }
+}
+
+/// Message feeding a tool result (or a parse error) back to the model.
+pub fn observation_message(protocol: ToolProtocol, observation: &str) -> ChatMessage {
+    let text = match protocol {
+        ToolProtocol::React => format!("Observation: {observation}"),
+        _ => format!(
+            "Tool result:\n{observation}\n\nEither call another tool with a single CALL line, or answer the user directly."
+        ),
+    };
+    ChatMessage::user_with_text(text)
+}
+
+/// Reads the model's answer as either a tool call or a final answer.
+///
+/// `step` is used to derive a call id. Malformed calls are returned as errors
+/// worded for the model, so they can be fed back as observations.
+pub fn parse_turn(
+    protocol: ToolProtocol,
+    answer: &str,
+    tools: &[ToolDefinition],
+    step: usize,
+) -> Result<TextTurn, String> {
+    let parsed = match protocol {
+        ToolProtocol::Native => return Ok(TextTurn::Answer(answer.to_string())),
+        ToolProtocol::Text => parse_call_line(answer)?,
+        ToolProtocol::React => parse_react(answer)?,
+    };
+
+    let Some((name, raw_args)) = parsed else {
+        let answer = match protocol {
+            ToolProtocol::React => react_final_answer(answer),
+            _ => answer.trim().to_string(),
+        };
+        return Ok(TextTurn::Answer(answer));
+    };
+
+    let tool = tools
+        .iter()
+        .find(|tool| tool.function.name == name)
+        .ok_or_else(|| {
+            let available = tools
+                .iter()
+                .map(|tool| tool.function.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("Unknown tool '{name}'. Available tools: {available}.")
+        })?;
+
+    let args = parse_arguments(&raw_args, tool)?;
+    Ok(TextTurn::Call(ToolCall {
+        id: format!("call_{step}"),
+        name,
+        args,
+    }))
+}
+
+/// Assistant text to keep in the history; for ReAct, anything the model
+/// wrote after its action (typically an invented observation) is dropped.
+pub fn assistant_turn(protocol: ToolProtocol, answer: &str) -> String {
+    if protocol == ToolProtocol::React
+        && let Some(start) = answer.find("\nObservation:")
+    {
+        return answer[..start].trim_end().to_string();
+    }
+    answer.trim_end().to_string()
+}
+
+fn tool_lines(tools: &[ToolDefinition]) -> String {
+    tools
+        .iter()
+        .map(|tool| {
+            let parameters = tool.to_json()["function"]["parameters"].to_string();
+            format!(
+                "- {}: {}\n  parameters: {parameters}",
+                tool.function.name, tool.function.description
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn text_instructions(tools: &[ToolDefinition]) -> String {
+    format!(
+        "You can use the following tools:\n{}\n\nTo call a tool, reply with exactly one line and nothing else:\nCALL tool_name({{\"param\": \"value\"}})\nA tool with a single parameter may also be called positionally: CALL tool_name(\"value\").\nYou will then receive the tool result. Never invent tool results. When you have enough information, answer the user directly without any CALL line.",
+        tool_lines(tools)
+    )
+}
+
+fn react_instructions(tools: &[ToolDefinition]) -> String {
+    let names = tools
+        .iter()
+        .map(|tool| tool.function.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "You have access to the following tools:\n{}\n\nUse this format:\nThought: think about what to do next\nAction: the tool to use, one of [{names}]\nAction Input: the tool arguments as a JSON object\nObservation: the tool result (it is provided to you, never write it yourself)\n... (Thought/Action/Action Input/Observation can repeat)\nThought: I now know the final answer\nFinal Answer: the answer for the user",
+        tool_lines(tools)
+    )
+}
+
+/// Finds the first `CALL name(...)`; the arguments run to the matching
+/// `)`, possibly over several lines (pretty-printed JSON).
+fn parse_call_line(answer: &str) -> Result<Option<(String, String)>, String> {
+    let mut offset = 0;
+    let mut start = None;
+    for line in answer.split_inclusive('\n') {
+        let trimmed = line.trim().trim_matches('`').trim();
+        if trimmed.starts_with("CALL ") || trimmed.starts_with("CALL(") {
+            start = line.find("CALL").map(|idx| offset + idx);
+            break;
+        }
+        offset += line.len();
+    }
+    let Some(start) = start else {
+        return Ok(None);
+    };
+    let call = &answer[start..];
+    let line = call.lines().next().unwrap_or(call).trim_end();
+
+    let rest = call["CALL".len()..].trim_start();
+    let open = rest
+        .find('(')
+        .ok_or_else(|| malformed_call(line, "missing '('"))?;
+    let name = rest[..open].trim();
+    if !is_tool_name(name) {
+        return Err(malformed_call(line, "invalid tool name"));
+    }
+    let close =
+        closing_paren(&rest[open..]).ok_or_else(|| malformed_call(line, "missing closing ')'"))?;
+
+    Ok(Some((
+        name.to_string(),
+        rest[open + 1..open + close].trim().to_string(),
+    )))
+}
+
+/// Offset of the `)` matching the `(` that starts `raw`, skipping quoted
+/// strings and nested brackets.
+fn closing_paren(raw: &str) -> Option<usize> {
+    let mut depth = 0i32;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (idx, c) in raw.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some(_), '\\') => escaped = true,
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[' | '{' | '(') => depth += 1,
+            (None, ']' | '}' | ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return (c == ')').then_some(idx);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn malformed_call(line: &str, reason: &str) -> String {
+    format!(
+        "Malformed tool call `{line}` ({reason}). Reply with exactly one line like CALL tool_name({{\"param\": \"value\"}})."
+    )
+}
+
+fn parse_react(answer: &str) -> Result<Option<(String, String)>, String> {
+    let mut action = None;
+    let mut input_lines: Vec<&str> = Vec::new();
+    let mut in_input = false;
+
+    for line in answer.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix("Action:") {
+            if action.is_some() {
+                break;
+            }
+            action = Some(name.trim().trim_matches('`').to_string());
+        } else if let Some(input) = trimmed.strip_prefix("Action Input:") {
+            in_input = action.is_some();
+            if in_input {
+                input_lines.push(input.trim());
+            }
+        } else if trimmed.starts_with("Observation:")
+            || trimmed.starts_with("Thought:")
+            || trimmed.starts_with("Final Answer:")
+        {
+            if in_input || action.is_some() {
+                break;
+            }
+        } else if in_input {
+            input_lines.push(line);
+        }
+    }
+
+    let Some(name) = action else {
+        return Ok(None);
+    };
+    if !is_tool_name(&name) {
+        return Err(format!(
+            "Malformed action '{name}'. Write `Action: tool_name` followed by `Action Input: {{...}}`."
+        ));
+    }
+    let input = input_lines
+        .join("\n")
+        .trim()
+        .trim_matches('`')
+        .trim()
+        .to_string();
+    Ok(Some((name, input)))
+}
+
+fn react_final_answer(answer: &str) -> String {
+    match answer.find("Final Answer:") {
+        Some(start) => answer[start + "Final Answer:".len()..].trim().to_string(),
+        None => answer.trim().to_string(),
+    }
+}
+
+fn is_tool_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Converts raw call arguments to a JSON object for `tool`.
+///
+/// Accepts a JSON object, positional JSON values (mapped to parameters in
+/// declaration order), `name=value` pairs, or, for single-parameter tools, a
+/// bare string.
+fn parse_arguments(raw: &str, tool: &ToolDefinition) -> Result<Value, String> {
+    let raw = raw.trim();
+    let params = &tool.function.params;
+    let name = &tool.function.name;
+
+    if raw.is_empty() {
+        return Ok(Value::Object(Map::new()));
+    }
+    if raw.starts_with('{') {
+        return serde_json::from_str::<Value>(raw)
+            .ok()
+            .filter(Value::is_object)
+            .ok_or_else(|| format!("Arguments for '{name}' are not a valid JSON object: {raw}"));
+    }
+
+    if let Ok(Value::Array(values)) = serde_json::from_str::<Value>(&format!("[{raw}]")) {
+        if values.len() > params.len() {
+            return Err(format!(
+                "'{name}' takes {} argument(s) but {} were given.",
+                params.len(),
+                values.len()
+            ));
+        }
+        let map = params
+            .iter()
+            .zip(values)
+            .map(|(param, value)| (param.name.clone(), value))
+            .collect();
+        return Ok(Value::Object(map));
+    }
+
+    if let Some(map) = parse_keyword_arguments(raw) {
+        return Ok(Value::Object(map));
+    }
+
+    if params.len() == 1 && !raw.contains(['"', '\'']) {
+        let mut map = Map::new();
+        map.insert(params[0].name.clone(), Value::String(raw.to_string()));
+        return Ok(Value::Object(map));
+    }
+
+    Err(format!(
+        "Could not parse arguments for '{name}': {raw}. Use a JSON object, e.g. CALL {name}({{\"param\": \"value\"}})."
+    ))
+}
+
+fn parse_keyword_arguments(raw: &str) -> Option<Map<String, Value>> {
+    let mut map = Map::new();
+    for part in split_top_level(raw) {
+        let (key, value) = part.split_once('=')?;
+        let key = key.trim();
+        if !is_tool_name(key) {
+            return None;
+        }
+        let value = value.trim();
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| Value::String(value.trim_matches('\'').to_string()));
+        map.insert(key.to_string(), value);
+    }
+    Some(map)
+}
+
+fn split_top_level(raw: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (idx, c) in raw.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some(_), '\\') => escaped = true,
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[' | '{' | '(') => depth += 1,
+            (None, ']' | '}' | ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(&raw[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&raw[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rchain::tools::{ToolFunction, ToolParam, ToolParamType};
+    use serde_json::json;
+
+    fn tools() -> Vec<ToolDefinition> {
+        vec![
+            ToolDefinition::from_function(
+                ToolFunction::new("meteo", "Weather for a city").with_param(ToolParam::new(
+                    "query",
+                    ToolParamType::String,
+                    true,
+                    None,
+                )),
+            ),
+            ToolDefinition::from_function(
+                ToolFunction::new("search", "Search files")
+                    .with_param(ToolParam::new("pattern", ToolParamType::String, true, None))
+                    .with_param(ToolParam::new("limit", ToolParamType::Integer, false, None)),
+            ),
+        ]
+    }
+
+    fn parse(protocol: ToolProtocol, answer: &str) -> Result<TextTurn, String> {
+        parse_turn(protocol, answer, &tools(), 1)
+    }
+
+    fn expect_call(turn: TextTurn) -> ToolCall {
+        match turn {
+            TextTurn::Call(call) => call,
+            TextTurn::Answer(answer) => panic!("expected a call, got answer {answer:?}"),
+        }
+    }
+
+    #[test]
+    fn text_call_with_positional_string() {
+        let call = expect_call(parse(ToolProtocol::Text, "CALL meteo(\"Genève\")").unwrap());
+        assert_eq!(call.name, "meteo");
+        assert_eq!(call.args, json!({"query": "Genève"}));
+        assert_eq!(call.id, "call_1");
+    }
+
+    #[test]
+    fn text_call_with_object_keywords_and_fences() {
+        let call = expect_call(
+            parse(
+                ToolProtocol::Text,
+                "```\nCALL search({\"pattern\": \"TODO\", \"limit\": 3})\n```",
+            )
+            .unwrap(),
+        );
+        assert_eq!(call.args, json!({"pattern": "TODO", "limit": 3}));
+
+        let call = expect_call(
+            parse(ToolProtocol::Text, "CALL search(pattern=\"a, b\", limit=2)").unwrap(),
+        );
+        assert_eq!(call.args, json!({"pattern": "a, b", "limit": 2}));
+    }
+
+    #[test]
+    fn text_call_arguments_may_span_lines() {
+        let call = expect_call(
+            parse(
+                ToolProtocol::Text,
+                "```\nCALL search({\n  \"pattern\": \"fn (a)\",\n  \"limit\": 3\n})\n```\nThen I'll read it.",
+            )
+            .unwrap(),
+        );
+        assert_eq!(call.args, json!({"pattern": "fn (a)", "limit": 3}));
+
+        let err = parse(ToolProtocol::Text, "CALL search({\n  \"pattern\": \"a\"\n}").unwrap_err();
+        assert!(err.contains("missing closing ')'") && err.contains("`CALL search({`"));
+    }
+
+    #[test]
+    fn text_answer_without_call_is_final() {
+        assert_eq!(
+            parse(ToolProtocol::Text, "Il fait 12°C à Genève.").unwrap(),
+            TextTurn::Answer("Il fait 12°C à Genève.".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_text_calls_explain_the_expected_format() {
+        let err = parse(ToolProtocol::Text, "CALL meteo(\"Genève\"").unwrap_err();
+        assert!(err.contains("missing closing ')'"));
+
+        let err = parse(ToolProtocol::Text, "CALL weather(\"Genève\")").unwrap_err();
+        assert!(err.contains("Unknown tool 'weather'"));
+
+        let err = parse(ToolProtocol::Text, "CALL search(\"a\", 1, 2)").unwrap_err();
+        assert!(err.contains("takes 2 argument(s)"));
+    }
+
+    #[test]
+    fn react_action_and_final_answer() {
+        let answer = "Thought: I need the weather\nAction: meteo\nAction Input: {\"query\": \"Genève\"}\nObservation: sunny";
+        let call = expect_call(parse(ToolProtocol::React, answer).unwrap());
+        assert_eq!(call.name, "meteo");
+        assert_eq!(call.args, json!({"query": "Genève"}));
+
+        let answer = "Thought: I now know the final answer\nFinal Answer: Nuageux, 12°C.";
+        assert_eq!(
+            parse(ToolProtocol::React, answer).unwrap(),
+            TextTurn::Answer("Nuageux, 12°C.".to_string())
+        );
+    }
+
+    #[test]
+    fn react_bare_string_input_for_single_parameter_tool() {
+        let answer = "Action: meteo\nAction Input: Genève";
+        let call = expect_call(parse(ToolProtocol::React, answer).unwrap());
+        assert_eq!(call.args, json!({"query": "Genève"}));
+    }
+
+    #[test]
+    fn instructions_are_appended_to_existing_system_message() {
+        let mut messages = vec![
+            ChatMessage::system("Be concise."),
+            ChatMessage::user_with_text("Weather?"),
+        ];
+        inject_instructions(&mut messages, ToolProtocol::Text, &tools());
+        assert_eq!(messages.len(), 2);
+        let MessageContent::Simple(system) = &messages[0].content else {
+            panic!("system message should be text");
+        };
+        assert!(system.starts_with("Be concise."));
+        assert!(system.contains("- meteo: Weather for a city"));
+        assert!(system.contains("CALL tool_name("));
+    }
+}
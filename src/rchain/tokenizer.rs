@@ -0,0 +1,231 @@
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rustc_hash::FxHashMap;
+use tiktoken_rs::CoreBPE;
+
+/// Split pattern of `cl100k_base`, used for tiktoken files by default.
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+/// Split pattern of `o200k_base`, used for tiktoken files named after it.
+const O200K_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+);
+/// Characters per token assumed without a tokenizer.
+const ESTIMATED_CHARS_PER_TOKEN: usize = 4;
+
+/// Counts tokens the way an embedding model does, to size `token` chunks.
+pub enum Tokenizer {
+    /// A Hugging Face `tokenizer.json`.
+    HuggingFace(Box<tokenizers::Tokenizer>),
+    /// A tiktoken BPE rank file (`cl100k_base.tiktoken`).
+    Tiktoken(Box<CoreBPE>),
+    /// No tokenizer: one token per 4 characters of a word.
+    Estimate,
+}
+
+impl Tokenizer {
+    /// Loads `tokenizer.json` (any `.json` file) or a tiktoken rank file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        if is_json {
+            return tokenizers::Tokenizer::from_file(path)
+                .map(|tokenizer| Self::HuggingFace(Box::new(tokenizer)))
+                .map_err(|err| format!("Failed to load tokenizer '{}': {err}", path.display()));
+        }
+
+        let ranks = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read tokenizer '{}': {err}", path.display()))?;
+        let mut encoder = FxHashMap::default();
+        for (number, line) in ranks.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(token, rank)| {
+                Some((STANDARD.decode(token).ok()?, rank.trim().parse().ok()?))
+            });
+            let Some((token, rank)) = parsed else {
+                return Err(format!(
+                    "Invalid tiktoken file '{}' at line {}: expected '<base64 token> <rank>'.",
+                    path.display(),
+                    number + 1
+                ));
+            };
+            encoder.insert(token, rank);
+        }
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let pattern = if name.contains("o200k") {
+            O200K_PATTERN
+        } else {
+            CL100K_PATTERN
+        };
+        CoreBPE::new(encoder, FxHashMap::default(), pattern)
+            .map(|bpe| Self::Tiktoken(Box::new(bpe)))
+            .map_err(|err| format!("Failed to load tokenizer '{}': {err}", path.display()))
+    }
+
+    /// Byte ranges of the tokens of `text` with the number of tokens each
+    /// one stands for. Ranges are ordered, never overlap and start and end
+    /// on char boundaries: tokens sharing a character are merged.
+    pub fn token_spans(&self, text: &str) -> Vec<(Range<usize>, usize)> {
+        let raw: Vec<Range<usize>> = match self {
+            Self::HuggingFace(tokenizer) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding
+                    .get_offsets()
+                    .iter()
+                    .map(|&(start, end)| start..end)
+                    .collect(),
+                Err(_) => return Self::Estimate.token_spans(text),
+            },
+            Self::Tiktoken(bpe) => {
+                let mut offset = 0;
+                bpe._decode_native_and_split(bpe.encode_ordinary(text))
+                    .map(|bytes| {
+                        let start = offset;
+                        offset += bytes.len();
+                        start..offset
+                    })
+                    .collect()
+            }
+            Self::Estimate => {
+                let mut pieces = Vec::new();
+                let mut piece: Option<(usize, usize)> = None;
+                for (byte, c) in text.char_indices() {
+                    if c.is_whitespace() {
+                        if let Some((start, _)) = piece.take() {
+                            pieces.push(start..byte);
+                        }
+                        continue;
+                    }
+                    match piece {
+                        Some((start, chars)) if chars == ESTIMATED_CHARS_PER_TOKEN => {
+                            pieces.push(start..byte);
+                            piece = Some((byte, 1));
+                        }
+                        Some((start, chars)) => piece = Some((start, chars + 1)),
+                        None => piece = Some((byte, 1)),
+                    }
+                }
+                if let Some((start, _)) = piece {
+                    pieces.push(start..text.len());
+                }
+                pieces
+            }
+        };
+
+        let mut spans: Vec<(Range<usize>, usize)> = Vec::with_capacity(raw.len());
+        for token in raw {
+            let start = floor_char_boundary(text, token.start);
+            let end = ceil_char_boundary(text, token.end.min(text.len()));
+            match spans.last_mut() {
+                Some((last, count)) if start < last.end => {
+                    last.end = last.end.max(end);
+                    *count += 1;
+                }
+                _ => spans.push((start..end, 1)),
+            }
+        }
+        spans
+    }
+
+    /// Tokens in `text`.
+    pub fn count(&self, text: &str) -> usize {
+        self.token_spans(text).iter().map(|(_, count)| count).sum()
+    }
+}
+
+fn floor_char_boundary(text: &str, mut byte: usize) -> usize {
+    while !text.is_char_boundary(byte) {
+        byte -= 1;
+    }
+    byte
+}
+
+fn ceil_char_boundary(text: &str, mut byte: usize) -> usize {
+    while !text.is_char_boundary(byte) {
+        byte += 1;
+    }
+    byte
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rchain::chunking::{ChunkOptions, ChunkStrategy, chunk_text};
+    use crate::rchain::semantic_chunking::SemanticOptions;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn unique_temp_path(file_name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        std::env::temp_dir().join(format!("mpipe-tokenizer-test-{nanos}-{file_name}"))
+    }
+
+    #[test]
+    fn tiktoken_files_count_merged_tokens() {
+        // Every byte is a token; "ab" and "abc" are merges.
+        let mut ranks: Vec<String> = (0..=255u8)
+            .map(|byte| format!("{} {byte}", STANDARD.encode([byte])))
+            .collect();
+        ranks.push(format!("{} 256", STANDARD.encode("ab")));
+        ranks.push(format!("{} 257", STANDARD.encode("abc")));
+        let path = unique_temp_path("test.tiktoken");
+        fs::write(&path, ranks.join("\n")).expect("write ranks");
+
+        let tokenizer = Tokenizer::load(&path).expect("tiktoken file");
+        // "abc" is one token, " abc" is " " + "abc".
+        assert_eq!(tokenizer.count("abc abc"), 3);
+        assert_eq!(Tokenizer::Estimate.count("abc abc"), 2);
+
+        let options = ChunkOptions {
+            strategy: ChunkStrategy::Token,
+            chunk_size: 3,
+            overlap_percent: 0,
+            tokenizer: Some(path.clone()),
+            semantic: SemanticOptions::DEFAULT,
+        };
+        let chunks = chunk_text("abc abc abc abc", &options, None).expect("chunks");
+        let texts: Vec<_> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(texts, ["abc abc", "abc abc"]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn hugging_face_tokenizers_report_offsets() {
+        let json = r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": null,
+            "decoder": null,
+            "model": {"type": "WordLevel", "vocab": {"[UNK]": 0, "hello": 1, "wörld": 2}, "unk_token": "[UNK]"}
+        }"#;
+        let path = unique_temp_path("tokenizer.json");
+        fs::write(&path, json).expect("write tokenizer");
+
+        let tokenizer = Tokenizer::load(&path).expect("tokenizer.json");
+        let spans = tokenizer.token_spans("hello wörld, hello");
+        let ranges: Vec<_> = spans.iter().map(|(bytes, _)| bytes.clone()).collect();
+        assert_eq!(ranges, [0..5, 6..12, 12..13, 14..19]);
+        assert_eq!(tokenizer.count("hello wörld, hello"), 4);
+        let _ = fs::remove_file(path);
+
+        let missing = Tokenizer::load(Path::new("/nonexistent/tokenizer.json"));
+        assert!(missing.is_err_and(|err| err.starts_with("Failed to load tokenizer")));
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::ToolPolicyConfig;
+
+/// When tool calls are confirmed on the TTY before running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApprovalMode {
+    /// Every call is confirmed.
+    Always,
+    /// Only tools with `confirm = true` are confirmed.
+    #[default]
+    Never,
+    /// Tools that write files, run commands or call unknown servers are confirmed.
+    Dangerous,
+}
+
+impl ApprovalMode {
+    /// Parses a config value.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "never" => Some(Self::Never),
+            "dangerous" => Some(Self::Dangerous),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::Never => "never",
+            Self::Dangerous => "dangerous",
+        }
+    }
+}
+
+/// Overrides for a single tool.
+#[derive(Debug, Clone, Default)]
+pub struct ToolLimits {
+    pub timeout: Option<Duration>,
+    pub max_output_bytes: Option<usize>,
+    /// Replaces the built-in dangerous/safe classification.
+    pub dangerous: Option<bool>,
+}
+
+/// Safeguards applied by the toolbox around every tool call.
+///
+/// Timeouts and output caps come on top of the ones a tool already has, so
+/// the stricter limit wins.
+#[derive(Debug, Clone, Default)]
+pub struct ToolPolicy {
+    pub approve: ApprovalMode,
+    /// Canonical directory file tools are confined to.
+    pub workspace: Option<PathBuf>,
+    /// Programs command tools may run; empty allows any program not denied.
+    pub allow_commands: Vec<String>,
+    /// Programs command tools may never run.
+    pub deny_commands: Vec<String>,
+    pub timeout: Option<Duration>,
+    pub max_output_bytes: Option<usize>,
+    pub tools: BTreeMap<String, ToolLimits>,
+    /// Show calls without executing them.
+    pub dry_run: bool,
+}
+
+impl ToolPolicy {
+    /// Builds the policy of the `[tool_policy]` config section, confining
+    /// file tools to the current directory unless `workspace` says otherwise.
+    pub fn from_config(config: &ToolPolicyConfig) -> Result<Self, String> {
+        let mut policy = Self {
+            approve: match config.approve.as_deref() {
+                Some(raw) => ApprovalMode::from_str(raw)
+                    .ok_or_else(|| format!("Invalid tool_policy.approve value '{raw}'."))?,
+                None => ApprovalMode::default(),
+            },
+            allow_commands: config.allow_commands.clone(),
+            deny_commands: config.deny_commands.clone(),
+            timeout: config.timeout.map(Duration::from_secs),
+            max_output_bytes: config.max_output_bytes,
+            tools: config
+                .tools
+                .iter()
+                .map(|(name, limits)| {
+                    let limits = ToolLimits {
+                        timeout: limits.timeout.map(Duration::from_secs),
+                        max_output_bytes: limits.max_output_bytes,
+                        dangerous: limits.dangerous,
+                    };
+                    (name.clone(), limits)
+                })
+                .collect(),
+            ..Self::default()
+        };
+
+        let workspace = match &config.workspace {
+            Some(path) => path.clone(),
+            None => env::current_dir()
+                .map_err(|err| format!("Failed to read the current directory: {err}"))?,
+        };
+        policy.set_workspace(&workspace)?;
+        Ok(policy)
+    }
+
+    /// Confines file tools to `root`, which must exist.
+    pub fn set_workspace(&mut self, root: &Path) -> Result<(), String> {
+        let canonical = root
+            .canonicalize()
+            .map_err(|err| format!("Invalid workspace '{}': {err}", root.display()))?;
+        if !canonical.is_dir() {
+            return Err(format!(
+                "Invalid workspace '{}': not a directory.",
+                root.display()
+            ));
+        }
+        self.workspace = Some(canonical);
+        Ok(())
+    }
+
+    /// Resolves a path given by the model inside the workspace.
+    ///
+    /// Relative paths are taken from the workspace root. Symlinks are
+    /// resolved before the containment check, so a link pointing outside the
+    /// workspace is refused like the target itself would be.
+    pub fn resolve_path(&self, raw: &str) -> Result<PathBuf, String> {
+        let Some(root) = &self.workspace else {
+            return Ok(PathBuf::from(raw));
+        };
+
+        let path = root.join(raw);
+        let resolved = resolve_existing_prefix(&path)
+            .map_err(|err| format!("cannot resolve path '{raw}': {err}"))?;
+        if !resolved.starts_with(root) {
+            return Err(format!(
+                "path '{raw}' is outside the workspace '{}'",
+                root.display()
+            ));
+        }
+        Ok(resolved)
+    }
+
+    /// Checks a command tool's program against the allow and deny lists.
+    ///
+    /// Entries containing `/` match the program path exactly; other entries
+    /// match its file name.
+    pub fn check_command(&self, program: &str) -> Result<(), String> {
+        let file_name = Path::new(program)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| program.to_string());
+        let matches = |entry: &String| {
+            if entry.contains('/') {
+                entry == program
+            } else {
+                *entry == file_name
+            }
+        };
+
+        if self.deny_commands.iter().any(matches) {
+            return Err(format!("command '{program}' is denied by the tool policy"));
+        }
+        if !self.allow_commands.is_empty() && !self.allow_commands.iter().any(matches) {
+            return Err(format!(
+                "command '{program}' is not in the allowed commands ({})",
+                self.allow_commands.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether a call must be confirmed before running.
+    pub fn needs_approval(&self, tool: &str, dangerous_by_default: bool) -> bool {
+        match self.approve {
+            ApprovalMode::Always => true,
+            ApprovalMode::Never => false,
+            ApprovalMode::Dangerous => self
+                .tools
+                .get(tool)
+                .and_then(|limits| limits.dangerous)
+                .unwrap_or(dangerous_by_default),
+        }
+    }
+
+    pub fn timeout_for(&self, tool: &str) -> Option<Duration> {
+        self.tools
+            .get(tool)
+            .and_then(|limits| limits.timeout)
+            .or(self.timeout)
+    }
+
+    pub fn max_output_for(&self, tool: &str) -> Option<usize> {
+        self.tools
+            .get(tool)
+            .and_then(|limits| limits.max_output_bytes)
+            .or(self.max_output_bytes)
+    }
+}
+
+/// Canonicalizes the longest existing ancestor of `path` and appends the
+/// remaining components, which may not contain `..`.
+fn resolve_existing_prefix(path: &Path) -> Result<PathBuf, String> {
+    let mut existing = path.to_path_buf();
+    let mut missing = Vec::new();
+
+    while existing.symlink_metadata().is_err() {
+        let Some(name) = existing.file_name() else {
+            break;
+        };
+        missing.push(name.to_os_string());
+        if !existing.pop() {
+            break;
+        }
+    }
+
+    let mut resolved = existing.canonicalize().map_err(|err| err.to_string())?;
+    for name in missing.iter().rev() {
+        if Path::new(name)
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err("'..' is not allowed in paths that do not exist".to_string());
+        }
+        resolved.push(name);
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn workspace(label: &str) -> (PathBuf, ToolPolicy) {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let root = env::temp_dir().join(format!("mpipe-policy-{label}-{nanos}"));
+        fs::create_dir_all(root.join("src")).expect("workspace should be creatable");
+        let mut policy = ToolPolicy::default();
+        policy.set_workspace(&root).expect("workspace should exist");
+        (root, policy)
+    }
+
+    #[test]
+    fn paths_are_confined_to_the_workspace() {
+        let (root, policy) = workspace("confine");
+        let root = root.canonicalize().expect("root should resolve");
+
+        assert_eq!(
+            policy.resolve_path("src/new/file.rs").expect("inside"),
+            root.join("src/new/file.rs")
+        );
+        assert!(policy.resolve_path("../outside").is_err());
+        assert!(policy.resolve_path("/etc/passwd").is_err());
+        assert!(policy.resolve_path("src/../../outside").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_leaving_the_workspace_are_refused() {
+        let (root, policy) = workspace("symlink");
+        std::os::unix::fs::symlink(env::temp_dir(), root.join("escape"))
+            .expect("symlink should be creatable");
+
+        let err = policy
+            .resolve_path("escape/secret.txt")
+            .expect_err("symlinked escape should be refused");
+        assert!(err.contains("outside the workspace"));
+    }
+
+    #[test]
+    fn deny_list_wins_over_allow_list() {
+        let policy = ToolPolicy {
+            allow_commands: vec!["rg".to_string(), "rm".to_string()],
+            deny_commands: vec!["rm".to_string()],
+            ..ToolPolicy::default()
+        };
+
+        assert!(policy.check_command("/usr/bin/rg").is_ok());
+        assert!(policy.check_command("rm").is_err());
+        assert!(policy.check_command("git").is_err());
+    }
+}
@@ -0,0 +1,728 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+use crate::config::{McpServerConfig, ToolConfig};
+use crate::rchain::command_tools::{CommandTool, render_argv, truncate_output};
+use crate::rchain::knowledge::{KnowledgeBase, MAX_TOP_K};
+use crate::rchain::mcp::McpServer;
+use crate::rchain::sandbox::{self, SandboxLanguage, SandboxLimits};
+use crate::rchain::tool_policy::ToolPolicy;
+use crate::rchain::tools::{ToolCall, ToolDefinition, ToolFunction, ToolParam, ToolParamType};
+
+const BUILTIN_MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// Held while a question is on the terminal, so that calls running in
+/// parallel ask one after the other.
+static TTY_PROMPT: Mutex<()> = Mutex::new(());
+
+/// Where a registered tool comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolOrigin {
+    /// Shipped with mpipe.
+    Builtin,
+    /// Declared under `[tools.<name>]` in the config file.
+    Config,
+    /// Imported from an `[mcp.<name>]` server.
+    Mcp,
+}
+
+impl ToolOrigin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Builtin => "builtin",
+            Self::Config => "config",
+            Self::Mcp => "mcp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BuiltinTool {
+    ReadFile,
+    ListDir,
+    WriteFile,
+}
+
+#[derive(Debug, Clone)]
+enum ToolHandler {
+    Builtin(BuiltinTool),
+    Command(CommandTool),
+    Mcp {
+        server: Arc<McpServer>,
+        tool: String,
+        read_only: bool,
+    },
+    Knowledge(Arc<KnowledgeBase>),
+    Sandbox(SandboxLanguage, SandboxLimits),
+}
+
+/// Arguments checked against the tool and the policy, ready to execute.
+enum PreparedCall<'a> {
+    Builtin(BuiltinTool, Value),
+    Command(&'a CommandTool, Vec<String>),
+    Mcp(&'a McpServer, &'a str),
+    Knowledge(&'a KnowledgeBase, Value),
+    Sandbox(SandboxLanguage, &'a SandboxLimits, String),
+}
+
+/// One tool available to the model, with its declaration and executor.
+#[derive(Debug, Clone)]
+pub struct RegisteredTool {
+    /// Declaration sent to providers.
+    pub definition: ToolDefinition,
+    /// Origin of the tool.
+    pub origin: ToolOrigin,
+    handler: ToolHandler,
+}
+
+impl RegisteredTool {
+    /// Tool name as seen by the model.
+    pub fn name(&self) -> &str {
+        &self.definition.function.name
+    }
+
+    /// Checks arguments without executing the tool; command tools also
+    /// return the argv they would run.
+    pub fn validate_args(&self, args: &Value) -> Result<Option<Vec<String>>, String> {
+        match &self.handler {
+            ToolHandler::Builtin(_) | ToolHandler::Knowledge(_) | ToolHandler::Sandbox(..) => {
+                self.definition.function.validate_args(args)?;
+                Ok(None)
+            }
+            ToolHandler::Command(tool) => tool.argv(args).map(Some),
+            ToolHandler::Mcp { .. } => check_object_args(args).map(|()| None),
+        }
+    }
+
+    /// Whether calls to this tool ask for confirmation first.
+    pub fn requires_confirmation(&self) -> bool {
+        matches!(&self.handler, ToolHandler::Command(tool) if tool.confirm)
+    }
+
+    /// Whether `--approve dangerous` asks before running this tool: file
+    /// writes, local commands and MCP tools not annotated as read-only.
+    pub fn is_dangerous(&self) -> bool {
+        match &self.handler {
+            ToolHandler::Builtin(builtin) => matches!(builtin, BuiltinTool::WriteFile),
+            ToolHandler::Command(_) | ToolHandler::Sandbox(..) => true,
+            ToolHandler::Mcp { read_only, .. } => !read_only,
+            ToolHandler::Knowledge(_) => false,
+        }
+    }
+}
+
+/// Registry of tools exposed to the model and dispatch of their calls.
+#[derive(Debug, Clone, Default)]
+pub struct Toolbox {
+    tools: Vec<RegisteredTool>,
+    servers: Vec<Arc<McpServer>>,
+    policy: ToolPolicy,
+}
+
+impl Toolbox {
+    /// Creates a toolbox holding the built-in tools.
+    pub fn with_builtins() -> Self {
+        let mut toolbox = Self::default();
+        for builtin in [
+            BuiltinTool::ReadFile,
+            BuiltinTool::ListDir,
+            BuiltinTool::WriteFile,
+        ] {
+            toolbox.tools.push(RegisteredTool {
+                definition: builtin_definition(builtin),
+                origin: ToolOrigin::Builtin,
+                handler: ToolHandler::Builtin(builtin),
+            });
+        }
+        toolbox
+    }
+
+    /// Applies approval, confinement and limits to every later call.
+    pub fn set_policy(&mut self, policy: ToolPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn policy(&self) -> &ToolPolicy {
+        &self.policy
+    }
+
+    /// Registers the tools declared in the config file.
+    pub fn register_config_tools(
+        &mut self,
+        tools: &BTreeMap<String, ToolConfig>,
+    ) -> Result<(), String> {
+        for (name, config) in tools {
+            let tool = CommandTool::from_config(name, config)?;
+            self.register(RegisteredTool {
+                definition: tool.definition(),
+                origin: ToolOrigin::Config,
+                handler: ToolHandler::Command(tool),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Starts the configured MCP servers and registers their tools as
+    /// `<server>__<tool>`, made a valid function name by
+    /// [`mcp_function_name`]. Calls are sent under the server's own name.
+    pub async fn register_mcp_servers(
+        &mut self,
+        servers: &BTreeMap<String, McpServerConfig>,
+    ) -> Result<(), String> {
+        for (name, config) in servers {
+            let server = Arc::new(McpServer::start(name, config).await?);
+            self.servers.push(Arc::clone(&server));
+
+            for tool in server.list_tools().await? {
+                let function_name =
+                    mcp_function_name(name, &tool.name, |taken| self.get(taken).is_some());
+                let function = ToolFunction::new(function_name, tool.description)
+                    .with_schema(tool.input_schema);
+                self.register(RegisteredTool {
+                    definition: ToolDefinition::from_function(function),
+                    origin: ToolOrigin::Mcp,
+                    handler: ToolHandler::Mcp {
+                        server: Arc::clone(&server),
+                        tool: tool.name,
+                        read_only: tool.read_only,
+                    },
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers the built-in `search_knowledge` tool over the given
+    /// collections.
+    pub fn register_knowledge(&mut self, knowledge: KnowledgeBase) -> Result<(), String> {
+        let collections = knowledge.collection_names().join(", ");
+        let function = ToolFunction::new(
+            "search_knowledge",
+            "Search indexed documents for passages relevant to a query. Returns ranked chunks \
+             with their source and distance (lower is closer).",
+        )
+        .with_param(ToolParam::new(
+            "query",
+            ToolParamType::String,
+            true,
+            Some("What to look for, phrased as a question or keywords.".to_string()),
+        ))
+        .with_param(ToolParam::new(
+            "collection",
+            ToolParamType::String,
+            false,
+            Some(format!(
+                "Collection to search (default: the first one). Available: {collections}."
+            )),
+        ))
+        .with_param(ToolParam::new(
+            "top_k",
+            ToolParamType::Integer,
+            false,
+            Some(format!(
+                "Number of chunks to return, 1 to {MAX_TOP_K} (default 5)."
+            )),
+        ));
+        self.register(RegisteredTool {
+            definition: ToolDefinition::from_function(function),
+            origin: ToolOrigin::Builtin,
+            handler: ToolHandler::Knowledge(Arc::new(knowledge)),
+        })
+    }
+
+    /// Registers `run_python` and `run_shell`, which execute model-written
+    /// code in a temporary directory under `limits`.
+    ///
+    /// The scripts can run any program and reach any path, so both
+    /// interpreters must pass the command lists of the policy: a non-empty
+    /// allow list has to name `python3` and `sh`.
+    pub fn register_sandbox(&mut self, limits: SandboxLimits) -> Result<(), String> {
+        for language in [SandboxLanguage::Python, SandboxLanguage::Shell] {
+            self.policy
+                .check_command(language.interpreter())
+                .map_err(|err| format!("Cannot offer {}: {err}.", language.tool_name()))?;
+        }
+        for (language, description) in [
+            (
+                SandboxLanguage::Python,
+                "Run a Python 3 script and return its stdout and stderr. Use it to compute \
+                 results instead of estimating them. Each run starts in an empty temporary \
+                 directory; print what you need to see.",
+            ),
+            (
+                SandboxLanguage::Shell,
+                "Run a POSIX shell script and return its stdout and stderr. Each run starts \
+                 in an empty temporary directory.",
+            ),
+        ] {
+            let function =
+                ToolFunction::new(language.tool_name(), description).with_param(ToolParam::new(
+                    "code",
+                    ToolParamType::String,
+                    true,
+                    Some("Complete script to run.".to_string()),
+                ));
+            self.register(RegisteredTool {
+                definition: ToolDefinition::from_function(function),
+                origin: ToolOrigin::Builtin,
+                handler: ToolHandler::Sandbox(language, limits),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Stops every MCP server started by this toolbox.
+    pub async fn shutdown(&self) {
+        for server in &self.servers {
+            server.shutdown().await;
+        }
+    }
+
+    fn register(&mut self, tool: RegisteredTool) -> Result<(), String> {
+        if self.get(tool.name()).is_some() {
+            return Err(format!(
+                "Tool '{}' is already defined; tool names must be unique.",
+                tool.name()
+            ));
+        }
+        self.tools.push(tool);
+        Ok(())
+    }
+
+    /// Keeps only the named tools, failing on unknown names.
+    pub fn retain_named(&mut self, names: &[String]) -> Result<(), String> {
+        for name in names {
+            if self.get(name).is_none() {
+                return Err(format!("Unknown tool '{name}'."));
+            }
+        }
+        self.tools
+            .retain(|tool| names.iter().any(|name| name == tool.name()));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredTool> {
+        self.tools.iter().find(|tool| tool.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegisteredTool> {
+        self.tools.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Declarations of every registered tool, in registration order.
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .iter()
+            .map(|tool| tool.definition.clone())
+            .collect()
+    }
+
+    /// Executes one tool call and returns the text handed back to the model.
+    ///
+    /// Arguments are validated and checked against the policy first; in
+    /// dry-run mode the call stops there and is described instead.
+    pub async fn call(&self, call: &ToolCall) -> Result<String, String> {
+        let tool = self
+            .get(&call.name)
+            .ok_or_else(|| format!("unknown tool '{}'", call.name))?;
+        let prepared = self.prepare(tool, &call.args)?;
+
+        if self.policy.dry_run {
+            let shown = match &prepared {
+                PreparedCall::Command(_, argv) => render_argv(argv),
+                _ => format!("{}({})", call.name, call.args),
+            };
+            return Ok(format!("[dry-run] not executed: {shown}"));
+        }
+
+        let needs_approval = tool.requires_confirmation()
+            || self.policy.needs_approval(tool.name(), tool.is_dangerous());
+        if needs_approval && !confirm_in_background(call).await {
+            return Err("execution was declined by the user".to_string());
+        }
+
+        let run = async {
+            match prepared {
+                PreparedCall::Builtin(builtin, args) => run_builtin(builtin, &args),
+                PreparedCall::Command(command, argv) => command.run_argv(&argv).await,
+                PreparedCall::Mcp(server, name) => server.call_tool(name, &call.args).await,
+                PreparedCall::Sandbox(language, limits, code) => {
+                    sandbox::run_code(language, &code, limits).await
+                }
+                PreparedCall::Knowledge(knowledge, args) => {
+                    knowledge
+                        .search(
+                            args["query"].as_str().unwrap_or_default(),
+                            args["collection"].as_str(),
+                            args["top_k"].as_u64().map(|n| n as usize),
+                        )
+                        .await
+                }
+            }
+        };
+        let output = match self.policy.timeout_for(tool.name()) {
+            Some(limit) => tokio::time::timeout(limit, run)
+                .await
+                .map_err(|_| format!("timed out after {}s", limit.as_secs()))??,
+            None => run.await?,
+        };
+
+        Ok(match self.policy.max_output_for(tool.name()) {
+            Some(max_bytes) => truncate_output(output, max_bytes),
+            None => output,
+        })
+    }
+
+    fn prepare<'a>(
+        &self,
+        tool: &'a RegisteredTool,
+        args: &Value,
+    ) -> Result<PreparedCall<'a>, String> {
+        match &tool.handler {
+            ToolHandler::Builtin(builtin) => {
+                let mut args = tool.definition.function.validate_args(args)?;
+                let raw_path = args
+                    .get("path")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| "missing required string argument 'path'".to_string())?;
+                let path = self.policy.resolve_path(raw_path)?;
+                args.insert(
+                    "path".to_string(),
+                    Value::String(path.to_string_lossy().into_owned()),
+                );
+                Ok(PreparedCall::Builtin(*builtin, Value::Object(args)))
+            }
+            ToolHandler::Command(command) => {
+                let argv = command.argv(args)?;
+                if let Some(program) = argv.first() {
+                    self.policy.check_command(program)?;
+                }
+                Ok(PreparedCall::Command(command, argv))
+            }
+            ToolHandler::Mcp { server, tool, .. } => {
+                check_object_args(args)?;
+                Ok(PreparedCall::Mcp(server, tool))
+            }
+            ToolHandler::Knowledge(knowledge) => {
+                let args = tool.definition.function.validate_args(args)?;
+                Ok(PreparedCall::Knowledge(knowledge, Value::Object(args)))
+            }
+            ToolHandler::Sandbox(language, limits) => {
+                tool.definition.function.validate_args(args)?;
+                self.policy.check_command(language.interpreter())?;
+                Ok(PreparedCall::Sandbox(
+                    *language,
+                    limits,
+                    string_arg(args, "code")?,
+                ))
+            }
+        }
+    }
+}
+
+/// Longest function name providers accept.
+const MAX_FUNCTION_NAME: usize = 64;
+
+/// `<server>__<tool>` with characters outside `[a-zA-Z0-9_-]` replaced by
+/// `_` and at most [`MAX_FUNCTION_NAME`] characters. Names that had to be
+/// shortened, or that clash with a `taken` one, end with a hash of the
+/// original.
+fn mcp_function_name(server: &str, tool: &str, taken: impl Fn(&str) -> bool) -> String {
+    let original = format!("{server}__{tool}");
+    let name: String = original
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.len() <= MAX_FUNCTION_NAME && !taken(&name) {
+        return name;
+    }
+    // FNV-1a, stable across runs so tool names can be configured.
+    let hash = original.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    let suffix = format!("_{hash:08x}");
+    let keep = name.len().min(MAX_FUNCTION_NAME - suffix.len());
+    format!("{}{suffix}", &name[..keep])
+}
+
+fn check_object_args(args: &Value) -> Result<(), String> {
+    match args {
+        Value::Object(_) | Value::Null => Ok(()),
+        other => Err(format!("arguments must be a JSON object, got {other}")),
+    }
+}
+
+fn builtin_definition(builtin: BuiltinTool) -> ToolDefinition {
+    let path = |description: &str| {
+        ToolParam::new(
+            "path",
+            ToolParamType::String,
+            true,
+            Some(description.to_string()),
+        )
+    };
+    let function = match builtin {
+        BuiltinTool::ReadFile => ToolFunction::new("read_file", "Read a UTF-8 text file.")
+            .with_param(path("Path of the file to read.")),
+        BuiltinTool::ListDir => ToolFunction::new("list_dir", "List the entries of a directory.")
+            .with_param(path("Directory to list.")),
+        BuiltinTool::WriteFile => ToolFunction::new(
+            "write_file",
+            "Write a UTF-8 text file, creating parent directories and replacing existing content.",
+        )
+        .with_param(path("Path of the file to write."))
+        .with_param(ToolParam::new(
+            "content",
+            ToolParamType::String,
+            true,
+            Some("Full file content.".to_string()),
+        )),
+    };
+    ToolDefinition::from_function(function)
+}
+
+fn run_builtin(builtin: BuiltinTool, args: &Value) -> Result<String, String> {
+    let path = string_arg(args, "path")?;
+    let path = Path::new(&path);
+
+    match builtin {
+        BuiltinTool::ReadFile => {
+            let content = fs::read_to_string(path)
+                .map_err(|err| format!("failed to read '{}': {err}", path.display()))?;
+            Ok(truncate_output(content, BUILTIN_MAX_OUTPUT_BYTES))
+        }
+        BuiltinTool::ListDir => {
+            let mut entries = fs::read_dir(path)
+                .map_err(|err| format!("failed to list '{}': {err}", path.display()))?
+                .filter_map(Result::ok)
+                .map(|entry| {
+                    let mut name = entry.file_name().to_string_lossy().into_owned();
+                    if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                        name.push('/');
+                    }
+                    name
+                })
+                .collect::<Vec<_>>();
+            entries.sort();
+            Ok(truncate_output(
+                entries.join("\n"),
+                BUILTIN_MAX_OUTPUT_BYTES,
+            ))
+        }
+        BuiltinTool::WriteFile => {
+            let content = string_arg(args, "content")?;
+            if let Some(parent) = path.parent()
+                && !parent.as_os_str().is_empty()
+            {
+                fs::create_dir_all(parent).map_err(|err| {
+                    format!("failed to create directory '{}': {err}", parent.display())
+                })?;
+            }
+            fs::write(path, &content)
+                .map_err(|err| format!("failed to write '{}': {err}", path.display()))?;
+            Ok(format!(
+                "wrote {} bytes to {}",
+                content.len(),
+                path.display()
+            ))
+        }
+    }
+}
+
+fn string_arg(args: &Value, name: &str) -> Result<String, String> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| format!("missing required string argument '{name}'"))
+}
+
+/// Runs [`confirm_on_tty`] on a blocking thread, one question at a time.
+async fn confirm_in_background(call: &ToolCall) -> bool {
+    let call = call.clone();
+    tokio::task::spawn_blocking(move || {
+        let _prompt = TTY_PROMPT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        confirm_on_tty(&call)
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Asks for a yes/no answer on the controlling terminal.
+///
+/// Reads `/dev/tty` rather than stdin so piped prompts do not answer the
+/// question; without a terminal the call is declined.
+pub fn confirm_on_tty(call: &ToolCall) -> bool {
+    let Ok(mut tty) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+    else {
+        return false;
+    };
+
+    let question = format!("mpipe: run tool {}({})? [y/N] ", call.name, call.args);
+    if tty.write_all(question.as_bytes()).is_err() {
+        return false;
+    }
+
+    let mut answer = String::new();
+    if BufReader::new(&tty).read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ToolParamConfig;
+    use serde_json::json;
+
+    fn sleep_toolbox(policy: ToolPolicy) -> Toolbox {
+        let mut params = BTreeMap::new();
+        params.insert(
+            "seconds".to_string(),
+            ToolParamConfig {
+                kind: "string".to_string(),
+                description: None,
+                required: true,
+            },
+        );
+        let mut tools = BTreeMap::new();
+        tools.insert(
+            "nap".to_string(),
+            ToolConfig {
+                description: "Sleep".to_string(),
+                command: "sleep {{seconds}}".to_string(),
+                params,
+                timeout: None,
+                cwd: None,
+                max_output_bytes: None,
+                confirm: false,
+            },
+        );
+
+        let mut toolbox = Toolbox::with_builtins();
+        toolbox.set_policy(policy);
+        toolbox
+            .register_config_tools(&tools)
+            .expect("tool should register");
+        toolbox
+    }
+
+    fn call(name: &str, args: Value) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            name: name.to_string(),
+            args,
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_describes_calls_without_running_them() {
+        let toolbox = sleep_toolbox(ToolPolicy {
+            dry_run: true,
+            ..ToolPolicy::default()
+        });
+
+        let output = toolbox
+            .call(&call("nap", json!({"seconds": "30"})))
+            .await
+            .expect("dry-run should succeed");
+        assert_eq!(output, "[dry-run] not executed: sleep 30");
+    }
+
+    #[tokio::test]
+    async fn policy_timeout_and_command_lists_apply() {
+        let toolbox = sleep_toolbox(ToolPolicy {
+            timeout: Some(std::time::Duration::from_millis(100)),
+            ..ToolPolicy::default()
+        });
+        let err = toolbox
+            .call(&call("nap", json!({"seconds": "5"})))
+            .await
+            .expect_err("call should time out");
+        assert!(err.contains("timed out"));
+
+        let toolbox = sleep_toolbox(ToolPolicy {
+            deny_commands: vec!["sleep".to_string()],
+            ..ToolPolicy::default()
+        });
+        let err = toolbox
+            .call(&call("nap", json!({"seconds": "0"})))
+            .await
+            .expect_err("denied command should fail");
+        assert!(err.contains("denied"));
+    }
+
+    #[test]
+    fn mcp_function_names_are_valid_and_distinct() {
+        let free = |_: &str| false;
+        assert_eq!(mcp_function_name("git", "log", free), "git__log");
+        assert_eq!(
+            mcp_function_name("fs", "read.file v2", free),
+            "fs__read_file_v2"
+        );
+
+        let long = mcp_function_name("server", &"x".repeat(80), free);
+        assert_eq!(long.len(), MAX_FUNCTION_NAME);
+        assert_ne!(long, mcp_function_name("server", &"x".repeat(81), free));
+
+        let clash = mcp_function_name("fs", "read.file", |name| name == "fs__read_file");
+        assert!(clash.starts_with("fs__read_file_") && clash != "fs__read_file_");
+        assert!(
+            clash
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        );
+    }
+
+    #[test]
+    fn code_tools_obey_the_command_lists() {
+        let mut toolbox = Toolbox::default();
+        toolbox.set_policy(ToolPolicy {
+            allow_commands: vec!["rg".to_string()],
+            ..ToolPolicy::default()
+        });
+        let err = toolbox
+            .register_sandbox(SandboxLimits::default())
+            .expect_err("an allow list without the interpreters should refuse the code tools");
+        assert!(err.contains("run_python") && err.contains("not in the allowed commands"));
+        assert!(toolbox.definitions().is_empty());
+
+        toolbox.set_policy(ToolPolicy {
+            deny_commands: vec!["sh".to_string()],
+            ..ToolPolicy::default()
+        });
+        let err = toolbox
+            .register_sandbox(SandboxLimits::default())
+            .expect_err("a denied interpreter should refuse the code tools");
+        assert!(err.contains("run_shell") && err.contains("denied"));
+
+        toolbox.set_policy(ToolPolicy {
+            allow_commands: vec!["python3".to_string(), "sh".to_string()],
+            ..ToolPolicy::default()
+        });
+        toolbox
+            .register_sandbox(SandboxLimits::default())
+            .expect("allowed interpreters should register");
+        assert_eq!(toolbox.definitions().len(), 2);
+    }
+}
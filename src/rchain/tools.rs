@@ -7,7 +7,7 @@ use image::ImageOutputFormat;
 use serde_json::{Map, Value, json};
 
 /// JSON schema primitive types supported for tool parameters.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ToolParamType {
     Integer,
     Number,
@@ -18,7 +18,34 @@ pub enum ToolParamType {
 }
 
 impl ToolParamType {
-    fn as_str(&self) -> &'static str {
+    /// Parses a JSON schema type name.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "integer" => Some(Self::Integer),
+            "number" => Some(Self::Number),
+            "string" => Some(Self::String),
+            "boolean" => Some(Self::Boolean),
+            "object" => Some(Self::Object),
+            "array" => Some(Self::Array),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a JSON value conforms to this type.
+    pub fn matches(&self, value: &Value) -> bool {
+        match self {
+            ToolParamType::Integer => value.is_i64() || value.is_u64(),
+            ToolParamType::Number => value.is_number(),
+            ToolParamType::String => value.is_string(),
+            ToolParamType::Boolean => value.is_boolean(),
+            ToolParamType::Object => value.is_object(),
+            ToolParamType::Array => value.is_array(),
+        }
+    }
+
+    /// Returns the JSON schema type name.
+    pub fn as_str(&self) -> &'static str {
         match self {
             ToolParamType::Integer => "integer",
             ToolParamType::Number => "number",
@@ -69,6 +96,8 @@ pub struct ToolFunction {
     pub description: String,
     /// Parameter definitions.
     pub params: Vec<ToolParam>,
+    /// JSON schema sent verbatim instead of the one derived from `params`.
+    pub schema: Option<Value>,
 }
 
 impl ToolFunction {
@@ -78,6 +107,7 @@ impl ToolFunction {
             name: name.into(),
             description: description.into(),
             params: Vec::new(),
+            schema: None,
         }
     }
 
@@ -87,7 +117,77 @@ impl ToolFunction {
         self
     }
 
+    /// Declares parameters with an existing JSON schema, as imported tools do.
+    ///
+    /// Top-level properties are also recorded as `params` so prompt-based
+    /// protocols can map positional arguments; the schema itself is sent as is.
+    pub fn with_schema(mut self, schema: Value) -> Self {
+        let required = schema["required"]
+            .as_array()
+            .map(|names| names.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if let Some(properties) = schema["properties"].as_object() {
+            self.params = properties
+                .iter()
+                .map(|(name, property)| {
+                    ToolParam::new(
+                        name,
+                        property["type"]
+                            .as_str()
+                            .and_then(ToolParamType::from_str)
+                            .unwrap_or(ToolParamType::String),
+                        required.contains(&name.as_str()),
+                        property["description"].as_str().map(str::to_string),
+                    )
+                })
+                .collect();
+        }
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Checks a call's arguments against the declared parameters.
+    ///
+    /// Unknown keys, missing required parameters and type mismatches are
+    /// rejected; the validated argument object is returned.
+    pub fn validate_args(&self, args: &Value) -> Result<Map<String, Value>, String> {
+        let args = match args {
+            Value::Object(map) => map.clone(),
+            Value::Null => Map::new(),
+            Value::String(raw) if raw.trim().is_empty() => Map::new(),
+            Value::String(raw) => return Err(format!("arguments are not valid JSON: {raw}")),
+            other => return Err(format!("arguments must be a JSON object, got {other}")),
+        };
+
+        for key in args.keys() {
+            if !self.params.iter().any(|param| &param.name == key) {
+                return Err(format!("unknown argument '{key}'"));
+            }
+        }
+        for param in &self.params {
+            match args.get(&param.name) {
+                None | Some(Value::Null) if param.required => {
+                    return Err(format!("missing required argument '{}'", param.name));
+                }
+                Some(value) if !value.is_null() && !param.kind.matches(value) => {
+                    return Err(format!(
+                        "argument '{}' must be of type {}",
+                        param.name,
+                        param.kind.as_str()
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(args)
+    }
+
     fn to_schema(&self) -> Value {
+        if let Some(schema) = &self.schema {
+            return schema.clone();
+        }
+
         let mut properties = Map::new();
         let mut required = Vec::new();
 
@@ -146,7 +246,7 @@ impl ToolDefinition {
 }
 
 /// Tool call emitted by a model.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ToolCall {
     /// Provider-generated call id.
     pub id: String,
@@ -157,6 +257,21 @@ pub struct ToolCall {
 }
 
 impl ToolCall {
+    /// Parses one provider `tool_calls` entry; arguments encoded as a JSON
+    /// string are decoded when valid.
+    pub fn from_json(call: &Value) -> Option<Self> {
+        let id = call["id"].as_str().unwrap_or("").to_string();
+        let name = call["function"]["name"].as_str().unwrap_or("").to_string();
+        if name.is_empty() {
+            return None;
+        }
+        let args = match &call["function"]["arguments"] {
+            Value::String(raw) => serde_json::from_str(raw).unwrap_or(Value::String(raw.clone())),
+            other => other.clone(),
+        };
+        Some(Self { id, name, args })
+    }
+
     fn args_as_string(&self) -> String {
         match &self.args {
             Value::String(value) => value.clone(),
@@ -0,0 +1,429 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::rchain::provider::{ChatMessage, Usage};
+
+/// One line of an agent transcript (JSON Lines, tagged by `type`).
+///
+/// `message` events replay the conversation exactly: concatenating them (from
+/// the latest `resume`, if any) gives the messages of the latest request, and
+/// `request.message_count` tells how many of them each request sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TranscriptEvent {
+    Start {
+        mpipe_version: String,
+        provider: String,
+        model: String,
+        protocol: String,
+        max_steps: usize,
+        /// Tool declarations as sent to providers.
+        tools: Vec<Value>,
+        /// `plan` for `--mode plan` runs; absent for the plain loop.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<String>,
+        ts_ms: u64,
+    },
+    /// Messages the resumed run starts from, replacing all earlier ones.
+    Resume {
+        messages: Vec<ChatMessage>,
+        ts_ms: u64,
+    },
+    Message {
+        message: ChatMessage,
+    },
+    Request {
+        step: usize,
+        message_count: usize,
+        ts_ms: u64,
+    },
+    Response {
+        step: usize,
+        content: String,
+        /// Native tool calls, in provider JSON format.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tool_calls: Vec<Value>,
+        usage: Option<Usage>,
+        latency_ms: u64,
+    },
+    Tool {
+        step: usize,
+        id: String,
+        name: String,
+        args: Value,
+        output: String,
+        ok: bool,
+        duration_ms: u64,
+        /// Output came from a recorded transcript instead of running the tool.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        replayed: bool,
+    },
+    /// Plan produced in `--mode plan`; revisions after 0 are re-plans.
+    Plan {
+        revision: usize,
+        /// `{"title", "description"}` objects, in execution order.
+        steps: Vec<Value>,
+        ts_ms: u64,
+    },
+    /// Checkpoint written when a plan step starts or finishes.
+    PlanStep {
+        /// 1-based position in the executed plan.
+        index: usize,
+        title: String,
+        /// `running`, `done` or `failed`.
+        status: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        summary: Option<String>,
+        ts_ms: u64,
+    },
+    End {
+        /// `answered` or `failed`.
+        status: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        answer: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        usage: Option<Usage>,
+        ts_ms: u64,
+    },
+}
+
+/// Milliseconds since the Unix epoch, as stored in `ts_ms` fields.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Directory where transcripts are written by default:
+/// `MP_TRANSCRIPT_DIR`, else `$XDG_STATE_HOME/mpipe/transcripts`, else
+/// `~/.local/state/mpipe/transcripts`.
+pub fn default_dir() -> Result<PathBuf, String> {
+    for (var, suffix) in [
+        ("MP_TRANSCRIPT_DIR", None),
+        ("XDG_STATE_HOME", Some("mpipe/transcripts")),
+    ] {
+        if let Ok(value) = env::var(var)
+            && !value.trim().is_empty()
+        {
+            let dir = PathBuf::from(value.trim());
+            return Ok(suffix.map_or(dir.clone(), |suffix| dir.join(suffix)));
+        }
+    }
+
+    let home = env::var("HOME").map_err(|_| {
+        "Cannot resolve transcript directory: set MP_TRANSCRIPT_DIR, XDG_STATE_HOME or HOME."
+            .to_string()
+    })?;
+    Ok(PathBuf::from(home).join(".local/state/mpipe/transcripts"))
+}
+
+/// Append-only transcript file, flushed after every event so an interrupted
+/// run can be resumed.
+#[derive(Debug)]
+pub struct TranscriptWriter {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl TranscriptWriter {
+    /// Creates a new transcript under `dir` named after the current time.
+    pub fn create_in(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|err| {
+            format!(
+                "Failed to create transcript directory '{}': {err}",
+                dir.display()
+            )
+        })?;
+        let path = dir.join(format!("agent-{}-{}.jsonl", now_ms(), std::process::id()));
+        Self::open(&path, false)
+    }
+
+    /// Opens `path`, truncating it unless `append` is set.
+    pub fn open(path: &Path, append: bool) -> Result<Self, String> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent).map_err(|err| {
+                format!(
+                    "Failed to create transcript directory '{}': {err}",
+                    parent.display()
+                )
+            })?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .map_err(|err| format!("Failed to open transcript '{}': {err}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, event: &TranscriptEvent) -> Result<(), String> {
+        let mut line = serde_json::to_string(event)
+            .map_err(|err| format!("Failed to serialize transcript event: {err}"))?;
+        line.push('\n');
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| "Transcript writer is poisoned.".to_string())?;
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.flush())
+            .map_err(|err| {
+                format!(
+                    "Failed to write transcript '{}': {err}",
+                    self.path.display()
+                )
+            })
+    }
+}
+
+/// Reads every event of a transcript file.
+pub fn read(path: &Path) -> Result<Vec<TranscriptEvent>, String> {
+    let file = File::open(path)
+        .map_err(|err| format!("Failed to read transcript '{}': {err}", path.display()))?;
+
+    let mut events = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line =
+            line.map_err(|err| format!("Failed to read transcript '{}': {err}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|err| {
+            format!(
+                "Invalid transcript '{}' at line {}: {err}",
+                path.display(),
+                index + 1
+            )
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// State a resumed run continues from.
+#[derive(Debug, Clone)]
+pub struct ResumeState {
+    pub provider: String,
+    pub model: String,
+    pub protocol: String,
+    pub messages: Vec<ChatMessage>,
+    /// Last step number recorded; the resumed run continues after it.
+    pub last_step: usize,
+}
+
+/// Rebuilds the conversation of an unfinished run.
+///
+/// A step cut short (assistant turn recorded but not all of its tool
+/// results) is dropped so the resumed run asks the model again.
+pub fn resume_state(events: &[TranscriptEvent]) -> Result<ResumeState, String> {
+    let Some(TranscriptEvent::Start {
+        provider,
+        model,
+        protocol,
+        mode,
+        ..
+    }) = events.first()
+    else {
+        return Err("Transcript does not start with a 'start' event.".to_string());
+    };
+    if mode.as_deref() == Some("plan") {
+        return Err("plan-mode transcripts cannot be resumed.".to_string());
+    }
+
+    let mut messages = Vec::new();
+    let mut last_step = 0;
+    let mut finished = false;
+    for event in events {
+        match event {
+            TranscriptEvent::Message { message } => messages.push(message.clone()),
+            TranscriptEvent::Request { step, .. } => last_step = last_step.max(*step),
+            TranscriptEvent::Resume { messages: base, .. } => {
+                messages = base.clone();
+                finished = false;
+            }
+            TranscriptEvent::End { status, .. } => finished = status == "answered",
+            _ => {}
+        }
+    }
+    if finished {
+        return Err("Transcript already ends with a final answer; nothing to resume.".to_string());
+    }
+
+    if let Some(index) = messages
+        .iter()
+        .rposition(|message| message.role == "assistant")
+    {
+        let expected = messages[index].tool_calls.as_ref().map_or(0, Vec::len);
+        let answered = messages[index + 1..]
+            .iter()
+            .filter(|message| message.role == "tool")
+            .count();
+        let text_turn_pending = expected == 0 && index + 1 == messages.len();
+        if answered < expected || text_turn_pending {
+            messages.truncate(index);
+        }
+    }
+
+    Ok(ResumeState {
+        provider: provider.clone(),
+        model: model.clone(),
+        protocol: protocol.clone(),
+        messages,
+        last_step,
+    })
+}
+
+/// Tool outputs of a transcript, handed out again for matching calls.
+#[derive(Debug, Default)]
+pub struct RecordedTools {
+    calls: Mutex<Vec<(String, Value, String, bool)>>,
+}
+
+impl RecordedTools {
+    pub fn from_events(events: &[TranscriptEvent]) -> Self {
+        let calls = events
+            .iter()
+            .filter_map(|event| match event {
+                TranscriptEvent::Tool {
+                    name,
+                    args,
+                    output,
+                    ok,
+                    ..
+                } => Some((name.clone(), args.clone(), output.clone(), *ok)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            calls: Mutex::new(calls),
+        }
+    }
+
+    /// Takes the first unused recording of `name` called with `args`.
+    pub fn take(&self, name: &str, args: &Value) -> Option<Result<String, String>> {
+        let mut calls = self.calls.lock().ok()?;
+        let index = calls
+            .iter()
+            .position(|(recorded_name, recorded_args, ..)| {
+                recorded_name == name && recorded_args == args
+            })?;
+        let (_, _, output, ok) = calls.remove(index);
+        Some(if ok { Ok(output) } else { Err(output) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn start() -> TranscriptEvent {
+        TranscriptEvent::Start {
+            mpipe_version: "0.1.0".to_string(),
+            provider: "openai".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            protocol: "native".to_string(),
+            max_steps: 10,
+            tools: Vec::new(),
+            mode: None,
+            ts_ms: 0,
+        }
+    }
+
+    fn message(message: ChatMessage) -> TranscriptEvent {
+        TranscriptEvent::Message { message }
+    }
+
+    #[test]
+    fn resume_drops_a_step_with_missing_tool_results() {
+        let calls = [
+            crate::rchain::tools::ToolCall {
+                id: "a".to_string(),
+                name: "read_file".to_string(),
+                args: json!({"path": "a"}),
+            },
+            crate::rchain::tools::ToolCall {
+                id: "b".to_string(),
+                name: "read_file".to_string(),
+                args: json!({"path": "b"}),
+            },
+        ];
+        let events = vec![
+            start(),
+            message(ChatMessage::user_with_text("read a and b")),
+            TranscriptEvent::Request {
+                step: 1,
+                message_count: 1,
+                ts_ms: 0,
+            },
+            message(ChatMessage::assistant_with_tool_calls("", &calls)),
+            message(ChatMessage::tool("a", "content of a")),
+        ];
+
+        let state = resume_state(&events).expect("run should be resumable");
+        assert_eq!(state.messages.len(), 1);
+        assert_eq!(state.last_step, 1);
+        assert_eq!(state.model, "gpt-4o-mini");
+    }
+
+    #[test]
+    fn answered_transcripts_cannot_be_resumed() {
+        let events = vec![
+            start(),
+            message(ChatMessage::user_with_text("hi")),
+            TranscriptEvent::End {
+                status: "answered".to_string(),
+                answer: Some("hello".to_string()),
+                error: None,
+                usage: None,
+                ts_ms: 0,
+            },
+        ];
+        assert!(resume_state(&events).is_err());
+    }
+
+    #[test]
+    fn recorded_tools_are_matched_by_name_and_arguments() {
+        let tool = |path: &str, output: &str| TranscriptEvent::Tool {
+            step: 1,
+            id: "x".to_string(),
+            name: "read_file".to_string(),
+            args: json!({"path": path}),
+            output: output.to_string(),
+            ok: true,
+            duration_ms: 1,
+            replayed: false,
+        };
+        let recorded = RecordedTools::from_events(&[tool("a", "first a"), tool("a", "second a")]);
+
+        assert_eq!(
+            recorded.take("read_file", &json!({"path": "a"})),
+            Some(Ok("first a".to_string()))
+        );
+        assert_eq!(
+            recorded.take("read_file", &json!({"path": "a"})),
+            Some(Ok("second a".to_string()))
+        );
+        assert_eq!(recorded.take("read_file", &json!({"path": "a"})), None);
+        assert_eq!(recorded.take("read_file", &json!({"path": "b"})), None);
+    }
+}
@@ -0,0 +1,458 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use npyz::{AutoSerialize, DType, NpyFile, Order, TypeChar, WriteOptions, WriterBuilder};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::rchain::chunking::TextChunk;
+use crate::rchain::vector_transform::Quantization;
+
+/// Encodings of a matrix of embeddings, one row per chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorFormat {
+    /// One JSON object per line: chunk index, offsets, text and `embedding`.
+    Jsonl,
+    /// A 2-D NumPy array of `float32`, or of `int8`/`uint8` when quantized.
+    Npy,
+    /// Raw little-endian `f32` values, row after row, without a header.
+    F32,
+    /// One row of comma-separated numbers per line.
+    Csv,
+}
+
+impl VectorFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Npy => "npy",
+            Self::F32 => "f32",
+            Self::Csv => "csv",
+        }
+    }
+
+    /// The format of a file named `*.jsonl`, `*.ndjson`, `*.npy`, `*.f32`,
+    /// `*.bin`, `*.csv` or `*.txt`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            "npy" => Some(Self::Npy),
+            "f32" | "bin" => Some(Self::F32),
+            "csv" | "txt" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+
+    /// Whether the encoding is binary rather than text.
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::Npy | Self::F32)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonlRecord<'a> {
+    index: usize,
+    #[serde(flatten)]
+    chunk: &'a TextChunk,
+    embedding: &'a [f64],
+}
+
+/// Writes `vectors`, the embeddings of `chunks`, to `out`. Binary formats
+/// store `f32`, the precision ChromaDB keeps; `.npy` stores `int8` or
+/// `uint8` instead when the vectors went through `quantization`.
+pub fn write_vectors(
+    format: VectorFormat,
+    chunks: &[TextChunk],
+    vectors: &[Vec<f64>],
+    quantization: Option<Quantization>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let failed = |err: &dyn std::fmt::Display| {
+        format!("Failed to write {} embeddings: {err}", format.as_str())
+    };
+    match format {
+        VectorFormat::Jsonl => {
+            for (index, (chunk, embedding)) in chunks.iter().zip(vectors).enumerate() {
+                let record = JsonlRecord {
+                    index,
+                    chunk,
+                    embedding,
+                };
+                serde_json::to_writer(&mut *out, &record).map_err(|err| failed(&err))?;
+                writeln!(out).map_err(|err| failed(&err))?;
+            }
+        }
+        VectorFormat::Npy => {
+            let values = vectors.iter().flatten().copied();
+            match quantization {
+                None => write_npy(out, vectors, values.map(|value| value as f32)),
+                Some(Quantization::Int8) => {
+                    write_npy(out, vectors, values.map(|value| value as i8))
+                }
+                Some(Quantization::Binary) => {
+                    write_npy(out, vectors, values.map(|value| value as u8))
+                }
+            }
+            .map_err(|err| failed(&err))?;
+        }
+        VectorFormat::F32 => {
+            let bytes = vectors
+                .iter()
+                .flatten()
+                .flat_map(|&value| (value as f32).to_le_bytes())
+                .collect::<Vec<_>>();
+            out.write_all(&bytes).map_err(|err| failed(&err))?;
+        }
+        VectorFormat::Csv => {
+            for vector in vectors {
+                let line = vector
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(out, "{line}").map_err(|err| failed(&err))?;
+            }
+        }
+    }
+    out.flush().map_err(|err| failed(&err))
+}
+
+/// Writes a 2-D array shaped like `vectors` holding `values`.
+fn write_npy<T: AutoSerialize>(
+    out: &mut impl Write,
+    vectors: &[Vec<f64>],
+    values: impl Iterator<Item = T>,
+) -> io::Result<()> {
+    let dimension = vectors.first().map_or(0, Vec::len);
+    let mut writer = WriteOptions::new()
+        .default_dtype()
+        .shape(&[vectors.len() as u64, dimension as u64])
+        .writer(out)
+        .begin_nd()?;
+    writer.extend(values)?;
+    writer.finish()
+}
+
+/// Reads embeddings in `format` from `bytes`. `rows` is the number of
+/// vectors expected, which splits headerless `f32` data into rows.
+pub fn read_vectors(
+    format: VectorFormat,
+    bytes: &[u8],
+    rows: usize,
+) -> Result<Vec<Vec<f32>>, String> {
+    match format {
+        VectorFormat::Jsonl => read_jsonl(bytes),
+        VectorFormat::Npy => read_npy(bytes),
+        VectorFormat::F32 => read_f32(bytes, rows),
+        VectorFormat::Csv => read_csv(bytes),
+    }
+}
+
+/// Lines are objects with an `embedding` array, like `embed --output
+/// jsonl` writes, or bare arrays. Objects with an `index` are placed by it;
+/// it must then be on every line, and each index from 0 on exactly once.
+fn read_jsonl(bytes: &[u8]) -> Result<Vec<Vec<f32>>, String> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|err| format!("JSONL embeddings are not UTF-8: {err}"))?;
+    let mut embeddings = Vec::new();
+    let mut indices: Vec<(usize, Option<usize>)> = Vec::new();
+    for (line_idx, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid =
+            |reason: &str| format!("Invalid JSONL embedding at line {}: {reason}", line_idx + 1);
+        let value: Value = serde_json::from_str(line).map_err(|err| invalid(&err.to_string()))?;
+        let (values, index) = match &value {
+            Value::Array(values) => (values, None),
+            Value::Object(record) => {
+                let values = record
+                    .get("embedding")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| invalid("missing `embedding` array"))?;
+                let index = match record.get("index") {
+                    Some(index) => Some(
+                        index
+                            .as_u64()
+                            .ok_or_else(|| invalid("`index` is not a non-negative integer"))?
+                            as usize,
+                    ),
+                    None => None,
+                };
+                (values, index)
+            }
+            _ => return Err(invalid("expected an object or an array")),
+        };
+        if let Some((_, first)) = indices.first()
+            && first.is_some() != index.is_some()
+        {
+            return Err(invalid("`index` must be on every line or on none"));
+        }
+        indices.push((line_idx + 1, index));
+        let vector = values
+            .iter()
+            .map(|value| value.as_f64().map(|value| value as f32))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("non-numeric value"))?;
+        embeddings.push(vector);
+    }
+    if !indices.first().is_some_and(|(_, index)| index.is_some()) {
+        return Ok(embeddings);
+    }
+
+    // With as many slots as lines, distinct indices fill every slot.
+    let count = embeddings.len();
+    let mut placed = vec![None; count];
+    for ((line, index), vector) in indices.into_iter().zip(embeddings) {
+        let index = index.unwrap_or_default();
+        let invalid = |reason: String| format!("Invalid JSONL embedding at line {line}: {reason}");
+        let slot = placed
+            .get_mut(index)
+            .ok_or_else(|| invalid(format!("index {index} is out of range for {count} lines")))?;
+        if slot.replace(vector).is_some() {
+            return Err(invalid(format!("duplicate index {index}")));
+        }
+    }
+    Ok(placed.into_iter().flatten().collect())
+}
+
+/// 2-D arrays of `float32`, `float64`, `int8` or `uint8`, in C or Fortran
+/// order.
+fn read_npy(bytes: &[u8]) -> Result<Vec<Vec<f32>>, String> {
+    let invalid = |reason: &dyn std::fmt::Display| format!("Invalid .npy embeddings: {reason}");
+    let npy = NpyFile::new(bytes).map_err(|err| invalid(&err))?;
+    let &[rows, columns] = npy.shape() else {
+        return Err(invalid(&format!(
+            "expected a 2-D array, got shape {:?}",
+            npy.shape()
+        )));
+    };
+    let (rows, columns) = (rows as usize, columns as usize);
+    let order = npy.order();
+    let values: Vec<f32> = match npy.dtype() {
+        DType::Plain(type_str) if type_str.type_char() == TypeChar::Float => {
+            match type_str.size_field() {
+                4 => npy.into_vec::<f32>().map_err(|err| invalid(&err))?,
+                8 => npy
+                    .into_vec::<f64>()
+                    .map_err(|err| invalid(&err))?
+                    .into_iter()
+                    .map(|value| value as f32)
+                    .collect(),
+                _ => return Err(invalid(&format!("unsupported dtype {type_str}"))),
+            }
+        }
+        DType::Plain(type_str) if type_str.size_field() == 1 => match type_str.type_char() {
+            TypeChar::Int => npy
+                .into_vec::<i8>()
+                .map_err(|err| invalid(&err))?
+                .into_iter()
+                .map(f32::from)
+                .collect(),
+            TypeChar::Uint => npy
+                .into_vec::<u8>()
+                .map_err(|err| invalid(&err))?
+                .into_iter()
+                .map(f32::from)
+                .collect(),
+            _ => return Err(invalid(&format!("unsupported dtype {type_str}"))),
+        },
+        dtype => {
+            return Err(invalid(&format!(
+                "expected float32, float64, int8 or uint8, got {}",
+                dtype.descr()
+            )));
+        }
+    };
+    Ok((0..rows)
+        .map(|row| {
+            (0..columns)
+                .map(|column| match order {
+                    Order::C => values[row * columns + column],
+                    Order::Fortran => values[column * rows + row],
+                })
+                .collect()
+        })
+        .collect())
+}
+
+fn read_f32(bytes: &[u8], rows: usize) -> Result<Vec<Vec<f32>>, String> {
+    let values = bytes.len() / 4;
+    if !bytes.len().is_multiple_of(4) || rows == 0 || values == 0 || !values.is_multiple_of(rows) {
+        return Err(format!(
+            "Raw f32 embeddings hold {} bytes, which is not {rows} vectors of 4-byte floats.",
+            bytes.len()
+        ));
+    }
+    let dimension = values / rows;
+    Ok(bytes
+        .chunks_exact(dimension * 4)
+        .map(|row| {
+            row.chunks_exact(4)
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect()
+        })
+        .collect())
+}
+
+fn read_csv(bytes: &[u8]) -> Result<Vec<Vec<f32>>, String> {
+    let text =
+        std::str::from_utf8(bytes).map_err(|err| format!("CSV embeddings are not UTF-8: {err}"))?;
+    let mut embeddings = Vec::new();
+    for (line_idx, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut vector = Vec::new();
+        for (value_idx, value) in line.split(',').enumerate() {
+            let trimmed = value.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let parsed = trimmed.parse::<f32>().map_err(|_| {
+                format!(
+                    "Invalid float at line {} position {}: '{}'",
+                    line_idx + 1,
+                    value_idx + 1,
+                    trimmed
+                )
+            })?;
+            vector.push(parsed);
+        }
+        if vector.is_empty() {
+            return Err(format!(
+                "No floats parsed for embeddings line {}.",
+                line_idx + 1
+            ));
+        }
+        embeddings.push(vector);
+    }
+    Ok(embeddings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rchain::chunking::{ChunkOptions, chunk_text};
+
+    #[test]
+    fn every_format_reads_back_what_it_wrote() {
+        let chunks = chunk_text(
+            "First paragraph.\n\nSecond paragraph.",
+            &ChunkOptions {
+                chunk_size: 20,
+                overlap_percent: 0,
+                ..crate::rchain::embeddings::EmbeddingsConfig::default().chunking
+            },
+            None,
+        )
+        .expect("chunks");
+        let vectors = vec![vec![0.5, -1.25, 3.0], vec![0.0, 2.5, -0.125]];
+        let expected = vec![vec![0.5f32, -1.25, 3.0], vec![0.0, 2.5, -0.125]];
+
+        for format in [
+            VectorFormat::Jsonl,
+            VectorFormat::Npy,
+            VectorFormat::F32,
+            VectorFormat::Csv,
+        ] {
+            let mut bytes = Vec::new();
+            write_vectors(format, &chunks, &vectors, None, &mut bytes).expect("write");
+            let read = read_vectors(format, &bytes, 2).expect("read");
+            assert_eq!(read, expected, "{}", format.as_str());
+        }
+
+        let mut jsonl = Vec::new();
+        write_vectors(VectorFormat::Jsonl, &chunks, &vectors, None, &mut jsonl).expect("write");
+        let second: Value = serde_json::from_slice(jsonl.split(|&b| b == b'\n').nth(1).unwrap())
+            .expect("json line");
+        assert_eq!(second["index"], 1);
+        assert_eq!(second["text"], "Second paragraph.");
+        assert_eq!(second["char_start"], 18);
+        assert_eq!(second["embedding"][2], -0.125);
+    }
+
+    #[test]
+    fn quantized_npy_files_store_integers() {
+        let vectors = vec![vec![127.0, -127.0], vec![0.0, 64.0]];
+        let mut int8 = Vec::new();
+        write_vectors(
+            VectorFormat::Npy,
+            &[],
+            &vectors,
+            Some(Quantization::Int8),
+            &mut int8,
+        )
+        .expect("write int8");
+        assert!(String::from_utf8_lossy(&int8).contains("i1'"));
+        assert_eq!(
+            read_vectors(VectorFormat::Npy, &int8, 2).expect("read int8"),
+            [[127.0, -127.0], [0.0, 64.0]]
+        );
+
+        let bits = vec![vec![1.0, 0.0, 1.0]];
+        let mut binary = Vec::new();
+        write_vectors(
+            VectorFormat::Npy,
+            &[],
+            &bits,
+            Some(Quantization::Binary),
+            &mut binary,
+        )
+        .expect("write binary");
+        assert!(String::from_utf8_lossy(&binary).contains("u1'"));
+        assert_eq!(
+            read_vectors(VectorFormat::Npy, &binary, 1).expect("read binary"),
+            [[1.0, 0.0, 1.0]]
+        );
+    }
+
+    #[test]
+    fn npy_files_from_numpy_are_read() {
+        // [[1, 2, 3], [4, 5, 6]] saved by NumPy as float64 in Fortran order.
+        let header = "{'descr': '<f8', 'fortran_order': True, 'shape': (2, 3), }";
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        let padded = format!("{header:<117}\n");
+        bytes.extend((padded.len() as u16).to_le_bytes());
+        bytes.extend(padded.as_bytes());
+        for value in [1.0f64, 4.0, 2.0, 5.0, 3.0, 6.0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        assert_eq!(
+            read_vectors(VectorFormat::Npy, &bytes, 2).expect("read"),
+            [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]
+        );
+
+        assert!(read_vectors(VectorFormat::F32, &[0; 12], 2).is_err());
+        assert!(read_vectors(VectorFormat::F32, &[], 2).is_err());
+        assert!(
+            read_vectors(VectorFormat::Jsonl, b"{\"index\": 0}", 1)
+                .is_err_and(|err| err.contains("missing `embedding` array"))
+        );
+
+        let reordered = b"{\"index\": 1, \"embedding\": [2]}\n{\"index\": 0, \"embedding\": [1]}\n";
+        assert_eq!(
+            read_vectors(VectorFormat::Jsonl, reordered, 2).expect("read"),
+            [[1.0], [2.0]]
+        );
+        let duplicate = b"{\"index\": 0, \"embedding\": [1]}\n{\"index\": 0, \"embedding\": [2]}\n";
+        assert!(
+            read_vectors(VectorFormat::Jsonl, duplicate, 2)
+                .is_err_and(|err| err.contains("line 2: duplicate index 0"))
+        );
+        let gap = b"{\"index\": 0, \"embedding\": [1]}\n{\"index\": 2, \"embedding\": [2]}\n";
+        assert!(
+            read_vectors(VectorFormat::Jsonl, gap, 2)
+                .is_err_and(|err| err.contains("index 2 is out of range for 2 lines"))
+        );
+        let mixed = b"{\"index\": 0, \"embedding\": [1]}\n[2]\n";
+        assert!(read_vectors(VectorFormat::Jsonl, mixed, 2).is_err());
+        assert_eq!(
+            VectorFormat::from_path(Path::new("vectors.NPY")),
+            Some(VectorFormat::Npy)
+        );
+    }
+}
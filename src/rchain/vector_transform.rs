@@ -0,0 +1,138 @@
+use std::fmt;
+
+/// Reduction of embedding values to integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    /// Values clamped to [-1, 1] and scaled to -127..=127.
+    Int8,
+    /// 1 for positive values, 0 otherwise.
+    Binary,
+}
+
+impl Quantization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Int8 => "int8",
+            Self::Binary => "binary",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "int8" => Some(Self::Int8),
+            "binary" => Some(Self::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// How the vectors of an embedding model are turned into the stored ones:
+/// truncated to `dimensions` (Matryoshka), then L2-normalized, then
+/// quantized, in that order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VectorTransform {
+    pub dimensions: Option<usize>,
+    pub normalize: bool,
+    pub quantization: Option<Quantization>,
+}
+
+impl VectorTransform {
+    /// Whether vectors are kept as the model returns them.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, mut vector: Vec<f64>) -> Result<Vec<f64>, String> {
+        if let Some(dimensions) = self.dimensions {
+            if vector.len() < dimensions {
+                return Err(format!(
+                    "Embedding has {} dimensions, fewer than the {dimensions} requested.",
+                    vector.len()
+                ));
+            }
+            vector.truncate(dimensions);
+        }
+        if self.normalize {
+            let norm = vector.iter().map(|value| value * value).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                vector.iter_mut().for_each(|value| *value /= norm);
+            }
+        }
+        match self.quantization {
+            Some(Quantization::Int8) => vector
+                .iter_mut()
+                .for_each(|value| *value = (value.clamp(-1.0, 1.0) * 127.0).round()),
+            Some(Quantization::Binary) => vector
+                .iter_mut()
+                .for_each(|value| *value = if *value > 0.0 { 1.0 } else { 0.0 }),
+            None => {}
+        }
+        Ok(vector)
+    }
+}
+
+/// `dimensions=256,normalize,int8`, or `none`.
+impl fmt::Display for VectorTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut steps = Vec::new();
+        if let Some(dimensions) = self.dimensions {
+            steps.push(format!("dimensions={dimensions}"));
+        }
+        if self.normalize {
+            steps.push("normalize".to_string());
+        }
+        if let Some(quantization) = self.quantization {
+            steps.push(quantization.as_str().to_string());
+        }
+        if steps.is_empty() {
+            return f.write_str("none");
+        }
+        f.write_str(&steps.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_are_truncated_normalized_then_quantized() {
+        let vector = vec![3.0, -4.0, 12.0];
+        let truncate = VectorTransform {
+            dimensions: Some(2),
+            ..VectorTransform::default()
+        };
+        assert_eq!(truncate.apply(vector.clone()), Ok(vec![3.0, -4.0]));
+
+        let normalize = VectorTransform {
+            normalize: true,
+            ..truncate
+        };
+        assert_eq!(normalize.apply(vector.clone()), Ok(vec![0.6, -0.8]));
+        assert_eq!(
+            VectorTransform {
+                quantization: Some(Quantization::Int8),
+                ..normalize
+            }
+            .apply(vector.clone()),
+            Ok(vec![76.0, -102.0])
+        );
+        assert_eq!(
+            VectorTransform {
+                quantization: Some(Quantization::Binary),
+                ..VectorTransform::default()
+            }
+            .apply(vector.clone()),
+            Ok(vec![1.0, 0.0, 1.0])
+        );
+
+        let too_long = VectorTransform {
+            dimensions: Some(4),
+            ..VectorTransform::default()
+        };
+        assert!(too_long.apply(vector).is_err());
+        assert_eq!(normalize.to_string(), "dimensions=2,normalize");
+        assert_eq!(VectorTransform::default().to_string(), "none");
+    }
+}
@@ -0,0 +1,65 @@
+"""Minimal stdio MCP server used by the integration tests.
+
+Exposes `echo` and `fail`, paginates tools/list, and writes "closed" to the
+file given as first argument once stdin reaches EOF.
+"""
+
+import json
+import sys
+
+TOOLS = [
+    {
+        "name": "echo",
+        "description": "Echo the given text",
+        "inputSchema": {
+            "type": "object",
+            "properties": {"text": {"type": "string", "description": "Text to echo"}},
+            "required": ["text"],
+        },
+    },
+    {
+        "name": "fail",
+        "description": "Always fails",
+        "inputSchema": {"type": "object", "properties": {}},
+    },
+]
+
+
+def send(message):
+    sys.stdout.write(json.dumps(message) + "\n")
+    sys.stdout.flush()
+
+
+def handle(request):
+    method = request.get("method")
+    params = request.get("params") or {}
+    if method == "initialize":
+        return {
+            "protocolVersion": params.get("protocolVersion", "2024-11-05"),
+            "capabilities": {"tools": {}},
+            "serverInfo": {"name": "stub", "version": "0.0.1"},
+        }
+    if method == "tools/list":
+        if params.get("cursor") == "page2":
+            return {"tools": TOOLS[1:]}
+        return {"tools": TOOLS[:1], "nextCursor": "page2"}
+    if method == "tools/call":
+        if params["name"] == "echo":
+            return {"content": [{"type": "text", "text": params["arguments"]["text"]}]}
+        return {"content": [{"type": "text", "text": "stub failure"}], "isError": True}
+    raise KeyError(method)
+
+
+for line in sys.stdin:
+    request = json.loads(line)
+    if "id" not in request:
+        continue
+    # Exercise notification handling on the client side.
+    send({"jsonrpc": "2.0", "method": "notifications/message", "params": {"level": "info"}})
+    try:
+        send({"jsonrpc": "2.0", "id": request["id"], "result": handle(request)})
+    except KeyError as err:
+        send({"jsonrpc": "2.0", "id": request["id"], "error": {"code": -32601, "message": str(err)}})
+
+with open(sys.argv[1], "w") as marker:
+    marker.write("closed")
@@ -18,6 +18,7 @@ fn mpask_cmd() -> Command {
         .env_remove("MP_RETRIES")
         .env_remove("MP_RETRY_DELAY")
         .env_remove("MP_CONFIG")
+        .env_remove("MP_EMBEDDING_PROVIDER")
         .env_remove("OPENAI_API_KEY")
         .env_remove("FIREWORKS_API_KEY");
     cmd
@@ -33,6 +34,7 @@ fn mpipe_cmd() -> Command {
         .env_remove("MP_RETRIES")
         .env_remove("MP_RETRY_DELAY")
         .env_remove("MP_CONFIG")
+        .env_remove("MP_EMBEDDING_PROVIDER")
         .env_remove("OPENAI_API_KEY")
         .env_remove("FIREWORKS_API_KEY");
     cmd
@@ -798,6 +800,201 @@ fn mpipe_index_document_requires_source() {
         .stderr(contains("--source is required when using --document."));
 }
 
+#[test]
+fn mpipe_index_code_chunking_needs_a_known_extension() {
+    mpipe_cmd()
+        .args([
+            "index",
+            "--document",
+            "fn main() {}",
+            "--source",
+            "notes.md",
+            "--chunk-strategy",
+            "code",
+            "--embedding-model",
+            "dummy",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Cannot detect the language of 'notes.md' for the code chunk strategy",
+        ));
+}
+
+#[test]
+fn mpipe_index_semantic_chunking_rejects_stdin_embeddings() {
+    mpipe_cmd()
+        .args([
+            "index",
+            "--document",
+            "Cats purr. Rust compiles.",
+            "--source",
+            "notes.txt",
+            "--chunk-strategy",
+            "semantic",
+        ])
+        .write_stdin("0.1,0.2\n0.3,0.4\n")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "The semantic chunk strategy embeds the document to chunk it",
+        ));
+}
+
+#[test]
+fn mpipe_embed_rejects_an_invalid_semantic_threshold() {
+    mpipe_cmd()
+        .args([
+            "embed",
+            "--chunk-strategy",
+            "semantic",
+            "--semantic-threshold",
+            "median:3",
+            "Cats purr. Rust compiles.",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Invalid semantic threshold 'median:3'"));
+}
+
+#[test]
+fn mpipe_embed_local_provider_needs_a_model_directory() {
+    let missing = unique_temp_path("no-such-model");
+    mpipe_cmd()
+        .env_remove("OPENAI_API_KEY")
+        .env_remove("FIREWORKS_API_KEY")
+        .args(["embed", "--provider", "local", "--model"])
+        .arg(&missing)
+        .arg("Cats purr.")
+        .assert()
+        .failure()
+        .stderr(contains("Failed to load local embedding model").and(contains("not a directory")));
+}
+
+#[test]
+fn mpipe_index_reads_embeddings_file_in_its_format() {
+    let path = unique_temp_path("embeddings").with_extension("jsonl");
+    fs::write(
+        &path,
+        "{\"index\": 0, \"embedding\": [0.1, 0.2]}\n{\"index\": 1, \"embedding\": [0.3, 0.4]}\n",
+    )
+    .expect("write embeddings");
+    mpipe_cmd()
+        .args(["index", "--document", "hello", "--source", "notes.txt"])
+        .arg("--embeddings-file")
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Embeddings count (2) does not match chunk count (1).",
+        ));
+
+    let unknown = unique_temp_path("embeddings").with_extension("vec");
+    fs::write(&unknown, "0.1,0.2\n").expect("write embeddings");
+    mpipe_cmd()
+        .args(["index", "--document", "hello", "--source", "notes.txt"])
+        .arg("--embeddings-file")
+        .arg(&unknown)
+        .assert()
+        .failure()
+        .stderr(contains("pass --embeddings-format jsonl|npy|f32|csv"));
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(unknown);
+}
+
+#[test]
+fn vector_transform_flags_are_validated_before_embedding() {
+    mpipe_cmd()
+        .args(["embed", "--dimensions", "0", "hello"])
+        .assert()
+        .failure()
+        .stderr(contains("--dimensions must be > 0"));
+
+    mpipe_cmd()
+        .args(["index", "--document", "hello", "--source", "notes.txt"])
+        .args(["--dimensions", "3", "--normalize"])
+        .write_stdin("0.1,0.2\n")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Embedding has 2 dimensions, fewer than the 3 requested.",
+        ));
+}
+
+#[test]
+fn mpipe_grep_without_embedding_model_asks_the_collection() {
+    mpipe_cmd()
+        .args([
+            "grep",
+            "--model",
+            "gpt-4o-mini",
+            "--chroma-url",
+            "http://127.0.0.1:9",
+            "What changed?",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Failed to connect to ChromaDB"));
+}
+
+#[test]
+fn mpipe_grep_reads_embedding_settings_from_profile() {
+    let config_path = unique_temp_path("config-grep-embedding");
+    fs::write(
+        &config_path,
+        "[profiles.rag]
+provider = \"fireworks\"
+model = \"chat-model\"
+embedding_provider = \"openai\"
+embedding_model = \"text-embedding-3-small\"
+",
+    )
+    .expect("config should be writable");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .env("FIREWORKS_API_KEY", "test-key")
+        .args([
+            "grep",
+            "--profile",
+            "rag",
+            "--chroma-url",
+            "http://127.0.0.1:9",
+            "What changed?",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Failed to connect to ChromaDB"));
+}
+
+#[test]
+fn mpipe_grep_embedding_provider_env_is_separate_from_chat_provider() {
+    mpipe_cmd()
+        .env("MP_PROVIDER", "fireworks")
+        .env("FIREWORKS_API_KEY", "test-key")
+        .env("MP_EMBEDDING_PROVIDER", "openai")
+        .args([
+            "grep",
+            "--model",
+            "chat-model",
+            "--embedding-model",
+            "text-embedding-3-small",
+            "--chroma-url",
+            "http://127.0.0.1:9",
+            "What changed?",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Failed to connect to ChromaDB"));
+
+    mpipe_cmd()
+        .env("MP_EMBEDDING_PROVIDER", "bogus")
+        .args(["grep", "--model", "m", "--embedding-model", "e", "q"])
+        .assert()
+        .failure()
+        .stderr(contains("Invalid MP_EMBEDDING_PROVIDER 'bogus'"));
+}
+
 #[test]
 fn mpipe_completion_fish_outputs_script() {
     mpipe_cmd()
@@ -1101,7 +1298,7 @@ fn mpipe_models_json_output_contains_expected_fields() {
 
     let body = parse_stdout_json(&assert.get_output().stdout);
     let entries = body.as_array().expect("models output should be an array");
-    assert_eq!(entries.len(), 1);
+    assert_eq!(entries.len(), 2);
     assert_eq!(
         entries[0]["provider"],
         Value::String("fireworks".to_string())
@@ -1112,4 +1309,541 @@ fn mpipe_models_json_output_contains_expected_fields() {
     );
     assert_eq!(entries[0]["source"], Value::String("local".to_string()));
     assert_eq!(entries[0]["recommended"], Value::Bool(true));
+    assert_eq!(
+        entries[1]["id"],
+        Value::String("accounts/fireworks/models/minimax-m2p5".to_string())
+    );
+}
+
+#[test]
+fn ask_tools_dry_run_lists_builtin_and_configured_tools() {
+    let config_path = unique_temp_path("ask-tools-dry-run");
+    fs::write(
+        &config_path,
+        "[tools.search]\ndescription = \"Search files with ripgrep\"\ncommand = \"rg --json -- {{pattern}} {{path}}\"\ntimeout = 5\n\n[tools.search.params.pattern]\ntype = \"string\"\nrequired = true\n\n[tools.search.params.path]\ntype = \"string\"\n",
+    )
+    .expect("config should be writable");
+
+    let assert = mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args([
+            "ask",
+            "--provider",
+            "openai",
+            "--model",
+            "gpt-4o-mini",
+            "--dry-run",
+            "--tools",
+            "find TODOs",
+        ])
+        .assert()
+        .success();
+
+    let body = parse_stdout_json(&assert.get_output().stdout);
+    let tools = body["tools"].as_array().expect("tools should be an array");
+    let names = tools
+        .iter()
+        .map(|tool| tool["function"]["name"].as_str().unwrap_or_default())
+        .collect::<Vec<_>>();
+    assert!(names.contains(&"read_file"));
+    assert!(names.contains(&"search"));
+
+    let search = tools
+        .iter()
+        .find(|tool| tool["function"]["name"] == "search")
+        .expect("search tool should be declared");
+    assert_eq!(
+        search["function"]["parameters"]["required"],
+        json!(["pattern"])
+    );
+}
+
+#[test]
+fn ask_text_tool_protocol_describes_tools_in_system_prompt() {
+    let assert = mpipe_cmd()
+        .env("MP_CONFIG", unique_temp_path("ask-text-tools-missing"))
+        .args([
+            "ask",
+            "--provider",
+            "fireworks",
+            "--model",
+            "accounts/fireworks/models/kimi-k2-instruct",
+            "--dry-run",
+            "--tools",
+            "--tool-protocol",
+            "react",
+            "--system",
+            "Be brief.",
+            "list the files",
+        ])
+        .assert()
+        .success();
+
+    let body = parse_stdout_json(&assert.get_output().stdout);
+    assert!(body.get("tools").is_none());
+    assert_eq!(body["messages"][0]["role"], "system");
+    let system = body["messages"][0]["content"]
+        .as_str()
+        .expect("system content should be a string");
+    assert!(system.starts_with("Be brief."));
+    assert!(system.contains("Action Input:"));
+    assert!(system.contains("list_dir"));
+}
+
+fn write_echo_tool_config(label: &str) -> PathBuf {
+    let config_path = unique_temp_path(label);
+    fs::write(
+        &config_path,
+        "[tools.greet]\ndescription = \"Greet someone\"\ncommand = \"echo hello {{who}}\"\n\n[tools.greet.params.who]\ntype = \"string\"\nrequired = true\n",
+    )
+    .expect("config should be writable");
+    config_path
+}
+
+#[test]
+fn mpipe_tool_list_includes_builtin_and_configured_tools() {
+    let config_path = write_echo_tool_config("tool-list");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "list"])
+        .assert()
+        .success()
+        .stdout(contains("read_file\tbuiltin\t"))
+        .stdout(contains("greet\tconfig\tGreet someone"));
+}
+
+#[test]
+fn mpipe_tool_schema_prints_provider_declaration() {
+    let config_path = write_echo_tool_config("tool-schema");
+
+    let assert = mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "schema", "greet"])
+        .assert()
+        .success();
+
+    let body = parse_stdout_json(&assert.get_output().stdout);
+    assert_eq!(body["type"], json!("function"));
+    assert_eq!(body["function"]["name"], json!("greet"));
+    assert_eq!(
+        body["function"]["parameters"]["properties"]["who"]["type"],
+        json!("string")
+    );
+}
+
+#[test]
+fn mpipe_tool_run_executes_configured_tool_without_llm() {
+    let config_path = write_echo_tool_config("tool-run");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "run", "greet", "--args", r#"{"who": "world; ls"}"#])
+        .assert()
+        .success()
+        .stdout("hello world; ls\n");
+}
+
+#[test]
+fn mpipe_tool_validate_reports_invalid_arguments() {
+    let config_path = write_echo_tool_config("tool-validate");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "validate", "greet", "--args", r#"{"who": 3}"#])
+        .assert()
+        .failure()
+        .stderr(contains("argument 'who' must be of type string"));
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "validate", "greet", "--args", r#"{"who": "a b"}"#])
+        .assert()
+        .success()
+        .stdout(contains("args OK: echo hello 'a b'"));
+}
+
+#[test]
+fn mpipe_tool_run_unknown_tool_fails() {
+    mpipe_cmd()
+        .env("MP_CONFIG", unique_temp_path("tool-run-unknown"))
+        .args(["tool", "run", "nope"])
+        .assert()
+        .failure()
+        .stderr(contains("Unknown tool 'nope'"));
+}
+
+fn write_mcp_stub_config(label: &str) -> (PathBuf, PathBuf) {
+    let config_path = unique_temp_path(label);
+    let marker_path = unique_temp_path(&format!("{label}-closed"));
+    let stub = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mcp_stub.py");
+    fs::write(
+        &config_path,
+        format!(
+            "[mcp.stub]\ncommand = [\"python3\", {}, {}]\ntimeout = 10\n",
+            json!(stub),
+            json!(marker_path.display().to_string())
+        ),
+    )
+    .expect("config should be writable");
+    (config_path, marker_path)
+}
+
+#[test]
+fn mpipe_tool_list_imports_mcp_server_tools() {
+    let (config_path, marker_path) = write_mcp_stub_config("mcp-list");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "list"])
+        .assert()
+        .success()
+        .stdout(contains("stub__echo\tmcp\tEcho the given text"))
+        .stdout(contains("stub__fail\tmcp\tAlways fails"));
+
+    assert_eq!(
+        fs::read_to_string(&marker_path).expect("server should see stdin closed"),
+        "closed"
+    );
+}
+
+#[test]
+fn mpipe_tool_schema_keeps_mcp_input_schema() {
+    let (config_path, _) = write_mcp_stub_config("mcp-schema");
+
+    let assert = mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "schema", "stub__echo"])
+        .assert()
+        .success();
+
+    let schema = parse_stdout_json(&assert.get_output().stdout);
+    assert_eq!(
+        schema["function"]["parameters"]["properties"]["text"]["description"],
+        "Text to echo"
+    );
+    assert_eq!(
+        schema["function"]["parameters"]["required"],
+        json!(["text"])
+    );
+}
+
+#[test]
+fn mpipe_tool_run_routes_calls_to_mcp_server() {
+    let (config_path, marker_path) = write_mcp_stub_config("mcp-run");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args([
+            "tool",
+            "run",
+            "stub__echo",
+            "--args",
+            r#"{"text": "bonjour"}"#,
+        ])
+        .assert()
+        .success()
+        .stdout("bonjour\n");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "run", "stub__fail"])
+        .assert()
+        .failure()
+        .stderr(contains("Tool 'stub__fail' failed: stub failure"));
+
+    assert!(marker_path.exists());
+}
+
+#[test]
+fn mcp_server_that_cannot_start_is_reported() {
+    let config_path = unique_temp_path("mcp-missing");
+    fs::write(
+        &config_path,
+        "[mcp.ghost]\ncommand = [\"mpipe-test-no-such-mcp-server\"]\n",
+    )
+    .expect("config should be writable");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "list"])
+        .assert()
+        .failure()
+        .stderr(contains("Failed to start MCP server 'ghost'"));
+}
+
+#[test]
+fn file_tools_refuse_paths_outside_the_workspace() {
+    let workspace = unique_temp_path("workspace");
+    fs::create_dir_all(&workspace).expect("workspace should be creatable");
+    fs::write(workspace.join("notes.txt"), "inside\n").expect("file should be writable");
+
+    mpipe_cmd()
+        .current_dir(&workspace)
+        .args([
+            "tool",
+            "run",
+            "read_file",
+            "--args",
+            r#"{"path": "notes.txt"}"#,
+        ])
+        .assert()
+        .success()
+        .stdout("inside\n");
+
+    mpipe_cmd()
+        .current_dir(&workspace)
+        .args([
+            "tool",
+            "run",
+            "read_file",
+            "--args",
+            r#"{"path": "../notes.txt"}"#,
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("outside the workspace"));
+}
+
+#[test]
+fn tool_policy_deny_list_blocks_command_tools() {
+    let config_path = write_echo_tool_config("tool-deny");
+    let mut config = fs::read_to_string(&config_path).expect("config should be readable");
+    config.push_str("\n[tool_policy]\ndeny_commands = [\"echo\"]\n");
+    fs::write(&config_path, config).expect("config should be writable");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", &config_path)
+        .args(["tool", "run", "greet", "--args", r#"{"who": "world"}"#])
+        .assert()
+        .failure()
+        .stderr(contains("command 'echo' is denied by the tool policy"));
+}
+
+#[test]
+fn agent_records_transcript_even_when_the_request_fails() {
+    let transcript_path = unique_temp_path("agent-transcript.jsonl");
+
+    mpipe_cmd()
+        .env("MP_CONFIG", unique_temp_path("agent-transcript-config"))
+        .args([
+            "agent",
+            "--provider",
+            "openai",
+            "--model",
+            "gpt-4o-mini",
+            "--transcript",
+        ])
+        .arg(&transcript_path)
+        .args(["-p", "What is in src/?"])
+        .assert()
+        .failure()
+        .stderr(contains("transcript: "))
+        .stderr(contains("OPENAI_API_KEY is not set"));
+
+    let events = fs::read_to_string(&transcript_path)
+        .expect("transcript should be written")
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).expect("event should be JSON"))
+        .collect::<Vec<_>>();
+    let types = events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap_or_default())
+        .collect::<Vec<_>>();
+    assert_eq!(types, ["start", "message", "request", "end"]);
+    assert_eq!(events[0]["model"], "gpt-4o-mini");
+    assert_eq!(events[1]["message"]["content"], "What is in src/?");
+    assert_eq!(events[3]["status"], "failed");
+}
+
+fn write_answered_transcript(label: &str) -> PathBuf {
+    let path = unique_temp_path(label);
+    let events = [
+        json!({"type": "start", "mpipe_version": "0.1.0", "provider": "openai", "model": "gpt-4o-mini", "protocol": "native", "max_steps": 10, "tools": [], "ts_ms": 0}),
+        json!({"type": "message", "message": {"role": "user", "content": "Count the TODOs"}}),
+        json!({"type": "request", "step": 1, "message_count": 1, "ts_ms": 0}),
+        json!({"type": "response", "step": 1, "content": "", "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\":\"notes.txt\"}"}}], "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}, "latency_ms": 42}),
+        json!({"type": "message", "message": {"role": "assistant", "content": "", "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\":\"notes.txt\"}"}}]}}),
+        json!({"type": "tool", "step": 1, "id": "call_1", "name": "read_file", "args": {"path": "notes.txt"}, "output": "TODO one\nTODO two", "ok": true, "duration_ms": 1}),
+        json!({"type": "message", "message": {"role": "tool", "content": "TODO one\nTODO two", "tool_call_id": "call_1"}}),
+        json!({"type": "end", "status": "answered", "answer": "There are 2 TODOs.", "usage": null, "ts_ms": 0}),
+    ];
+    let body = events
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+    fs::write(&path, body).expect("transcript should be writable");
+    path
+}
+
+#[test]
+fn agent_replay_renders_steps_tool_results_and_answer() {
+    let path = write_answered_transcript("agent-replay.jsonl");
+
+    mpipe_cmd()
+        .args(["agent", "replay"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(contains(
+            "== agent run: openai/gpt-4o-mini (protocol native, 0 tools)\n[user] Count the TODOs\n-- step 1\n",
+        ))
+        .stdout(contains("[assistant] (42 ms, tokens in=12 out=3)\n-> read_file({\"path\":\"notes.txt\"})\n"))
+        .stdout(contains("[tool read_file] ok (1 ms)\n  TODO one\n  TODO two\n"))
+        .stdout(contains("== answered\nThere are 2 TODOs.\n"));
+}
+
+#[test]
+fn agent_resume_refuses_finished_transcripts() {
+    let path = write_answered_transcript("agent-resume-finished.jsonl");
+
+    mpipe_cmd()
+        .args(["agent", "--resume"])
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(contains("nothing to resume"));
+}
+
+#[test]
+fn agent_replay_renders_plan_checkpoints() {
+    let path = unique_temp_path("agent-replay-plan.jsonl");
+    let events = [
+        json!({"type": "start", "mpipe_version": "0.1.0", "provider": "openai", "model": "gpt-4o-mini", "protocol": "native", "max_steps": 10, "tools": [], "mode": "plan", "ts_ms": 0}),
+        json!({"type": "message", "message": {"role": "user", "content": "Tidy the notes"}}),
+        json!({"type": "plan", "revision": 0, "steps": [{"title": "Read notes", "description": ""}, {"title": "Rewrite notes", "description": ""}], "ts_ms": 0}),
+        json!({"type": "plan_step", "index": 1, "title": "Read notes", "status": "done", "summary": "3 sections", "ts_ms": 0}),
+        json!({"type": "plan_step", "index": 2, "title": "Rewrite notes", "status": "failed", "summary": "read-only file", "ts_ms": 0}),
+        json!({"type": "end", "status": "failed", "answer": "1. [done] Read notes — 3 sections\n2. [failed] Rewrite notes — read-only file", "error": "plan did not complete", "usage": null, "ts_ms": 0}),
+    ];
+    let body = events
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+    fs::write(&path, body).expect("transcript should be writable");
+
+    mpipe_cmd()
+        .args(["agent", "replay"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(contains(
+            "[user] Tidy the notes\n== plan\n  1. Read notes\n  2. Rewrite notes\n",
+        ))
+        .stdout(contains("== plan step 1 done: Read notes — 3 sections\n"))
+        .stdout(contains(
+            "== plan step 2 failed: Rewrite notes — read-only file\n",
+        ));
+
+    mpipe_cmd()
+        .args(["agent", "--resume"])
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(contains("plan-mode transcripts cannot be resumed"));
+}
+
+#[test]
+fn agent_knowledge_without_embedding_model_asks_the_collection() {
+    mpipe_cmd()
+        .env("MP_CONFIG", unique_temp_path("agent-knowledge-config"))
+        .args([
+            "agent",
+            "--provider",
+            "openai",
+            "--model",
+            "gpt-4o-mini",
+            "--knowledge",
+            "docs",
+            "--chroma-url",
+            "http://127.0.0.1:9",
+            "--no-transcript",
+            "-p",
+            "What do the docs say?",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Failed to connect to ChromaDB"));
+
+    mpipe_cmd()
+        .env("MP_CONFIG", unique_temp_path("agent-knowledge-config"))
+        .args([
+            "agent",
+            "--provider",
+            "openai",
+            "--model",
+            "gpt-4o-mini",
+            "--knowledge",
+            "docs",
+            "--embedding-provider",
+            "cohere",
+            "--no-transcript",
+            "-p",
+            "What do the docs say?",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("invalid value 'cohere'"));
+}
+
+#[test]
+fn agent_memory_without_embedding_model_asks_the_collection() {
+    mpipe_cmd()
+        .env("MP_CONFIG", unique_temp_path("agent-memory-config"))
+        .args([
+            "agent",
+            "--provider",
+            "openai",
+            "--model",
+            "gpt-4o-mini",
+            "--memory",
+            "agent-memory",
+            "--chroma-url",
+            "http://127.0.0.1:9",
+            "--no-transcript",
+            "-p",
+            "Where is the repo?",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Failed to connect to ChromaDB"));
+
+    mpipe_cmd()
+        .args([
+            "memory",
+            "search",
+            "agent-memory",
+            "deploy target",
+            "--embedding-provider",
+            "local",
+            "--chroma-url",
+            "http://127.0.0.1:9",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Failed to connect to ChromaDB"));
+}
+
+#[test]
+fn memory_forget_needs_ids_or_a_session() {
+    mpipe_cmd()
+        .args(["memory", "forget", "agent-memory"])
+        .assert()
+        .failure()
+        .stderr(contains("required arguments were not provided"));
+
+    mpipe_cmd()
+        .args([
+            "memory",
+            "forget",
+            "agent-memory",
+            "mem-a-0",
+            "--session",
+            "a",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("cannot be used with"));
 }